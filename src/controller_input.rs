@@ -0,0 +1,233 @@
+// controller input model built from hid report 0x24
+// the bitmap layout follows the vive wand: trigger click, trackpad touch and
+// click, menu, grip and system buttons in bits 0-5
+
+use serde::{Serialize, Deserialize};
+use crate::hid_reports::ButtonReport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hand {
+    #[serde(alias = "left")]
+    Left,
+    #[serde(alias = "right")]
+    Right,
+}
+
+impl Hand {
+    pub const ALL: [Hand; 2] = [Hand::Left, Hand::Right];
+
+    pub fn index(self) -> usize {
+        match self {
+            Hand::Left => 0,
+            Hand::Right => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Button {
+    TriggerClick,
+    TrackpadTouch,
+    TrackpadClick,
+    Menu,
+    Grip,
+    System,
+}
+
+impl Button {
+    pub const ALL: [Button; 6] = [
+        Button::TriggerClick,
+        Button::TrackpadTouch,
+        Button::TrackpadClick,
+        Button::Menu,
+        Button::Grip,
+        Button::System,
+    ];
+
+    // bit in the report bitmap
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// one press or release, in report order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ButtonEvent {
+    pub hand: Hand,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+// state of one controller after the latest report
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ControllerInputState {
+    pub trigger_click: bool,
+    pub trackpad_touch: bool,
+    pub trackpad_click: bool,
+    pub menu: bool,
+    pub grip: bool,
+    pub system: bool,
+    // 0-1
+    pub trigger: f32,
+    // -1..1, x right, y up
+    pub trackpad: [f32; 2],
+}
+
+impl ControllerInputState {
+    pub fn from_report(report: &ButtonReport) -> Self {
+        let mut state = Self {
+            trigger: report.trigger as f32 / 255.0,
+            trackpad: report.trackpad.map(|v| v as f32 / 32768.0),
+            ..Self::default()
+        };
+        for button in Button::ALL {
+            state.set(button, report.buttons & button.mask() != 0);
+        }
+        state
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::TriggerClick => self.trigger_click,
+            Button::TrackpadTouch => self.trackpad_touch,
+            Button::TrackpadClick => self.trackpad_click,
+            Button::Menu => self.menu,
+            Button::Grip => self.grip,
+            Button::System => self.system,
+        }
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::TriggerClick => self.trigger_click = pressed,
+            Button::TrackpadTouch => self.trackpad_touch = pressed,
+            Button::TrackpadClick => self.trackpad_click = pressed,
+            Button::Menu => self.menu = pressed,
+            Button::Grip => self.grip = pressed,
+            Button::System => self.system = pressed,
+        }
+    }
+
+    // same layout as the report bitmap, used for the csv export
+    pub fn bitmap(&self) -> u8 {
+        Button::ALL
+            .iter()
+            .filter(|b| self.is_pressed(**b))
+            .fold(0, |bits, b| bits | b.mask())
+    }
+}
+
+// input state of one controller with press/release edge detection
+pub struct ControllerInput {
+    hand: Hand,
+    state: Option<ControllerInputState>,
+}
+
+impl ControllerInput {
+    pub fn new(hand: Hand) -> Self {
+        Self { hand, state: None }
+    }
+
+    // apply a report, returns the buttons that changed since the previous one
+    // the first report only sets the state, buttons held at startup are not presses
+    pub fn update(&mut self, report: &ButtonReport) -> Vec<ButtonEvent> {
+        self.apply(ControllerInputState::from_report(report))
+    }
+
+    // same as update for a state that did not come from a report (openxr actions)
+    pub fn apply(&mut self, next: ControllerInputState) -> Vec<ButtonEvent> {
+        let events = match self.state {
+            Some(previous) => Button::ALL
+                .iter()
+                .filter(|b| previous.is_pressed(**b) != next.is_pressed(**b))
+                .map(|&button| ButtonEvent {
+                    hand: self.hand,
+                    button,
+                    pressed: next.is_pressed(button),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.state = Some(next);
+        events
+    }
+
+    pub fn state(&self) -> Option<ControllerInputState> {
+        self.state
+    }
+
+    pub fn hand(&self) -> Hand {
+        self.hand
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(buttons: u8, trigger: u8) -> ButtonReport {
+        ButtonReport {
+            data_size: 8,
+            buttons,
+            trigger,
+            trackpad: [16384, -32768],
+        }
+    }
+
+    fn event(button: Button, pressed: bool) -> ButtonEvent {
+        ButtonEvent {
+            hand: Hand::Right,
+            button,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn buttons_held_at_startup_are_not_presses() {
+        let mut input = ControllerInput::new(Hand::Right);
+        let held = Button::Grip.mask() | Button::Menu.mask();
+        assert!(input.update(&report(held, 0)).is_empty());
+
+        let state = input.state().unwrap();
+        assert!(state.grip && state.menu && !state.trigger_click);
+        assert_eq!(state.bitmap(), held);
+        assert_eq!(state.trackpad, [0.5, -1.0]);
+
+        // releasing them is still an edge
+        assert_eq!(
+            input.update(&report(0, 0)),
+            [event(Button::Menu, false), event(Button::Grip, false)]
+        );
+    }
+
+    #[test]
+    fn press_and_release_edges() {
+        let mut input = ControllerInput::new(Hand::Right);
+        input.update(&report(0, 0));
+
+        let click = Button::TriggerClick.mask() | Button::TrackpadTouch.mask();
+        assert_eq!(
+            input.update(&report(click, 255)),
+            [event(Button::TriggerClick, true), event(Button::TrackpadTouch, true)]
+        );
+        // unchanged buttons and analog-only changes give no events
+        assert!(input.update(&report(click, 128)).is_empty());
+        assert_eq!(input.state().unwrap().trigger, 128.0 / 255.0);
+
+        // a press and a release in the same report
+        let swapped = Button::TrackpadTouch.mask() | Button::System.mask();
+        assert_eq!(
+            input.update(&report(swapped, 0)),
+            [event(Button::TriggerClick, false), event(Button::System, true)]
+        );
+    }
+
+    #[test]
+    fn unknown_bits_are_ignored() {
+        let mut input = ControllerInput::new(Hand::Left);
+        input.update(&report(0, 0));
+        assert!(input.update(&report(0xc0, 0)).is_empty());
+        assert_eq!(input.state().unwrap().bitmap(), 0);
+        assert_eq!(input.hand(), Hand::Left);
+    }
+}
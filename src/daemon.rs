@@ -0,0 +1,378 @@
+// --daemon: read the headset over hidraw without an openxr runtime
+// (what packaging/librevr.service starts). the device can come and go,
+// tracking state (room setup, ootx, calibration) lives across reconnects
+
+use std::time::{Duration, Instant};
+use nalgebra::{Isometry3, Point3, UnitQuaternion};
+use crate::device_config::DeviceConfig;
+use crate::hid_reports::{HidReport, ImuStream};
+use crate::hotplug::{HotplugEvent, HotplugFilter, HotplugMonitor};
+use crate::imu_filter::{FilterGains, ImuFilter};
+use crate::kernel_api::{helpers, KernelApi};
+use crate::lighthouse_room::{RoomCalibrator, RoomSetup, ROOM_SETUP_FILE};
+use crate::lighthouse_tracking::{BaseStation, LighthouseTracker};
+use crate::sensor_fusion::{FusionNoise, PoseMeasurement, SensorFusion};
+
+// udev may still be fixing permissions when the add event arrives
+const REOPEN_INTERVAL: Duration = Duration::from_secs(2);
+const HOTPLUG_WAIT: Duration = Duration::from_millis(500);
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_SENSORS: usize = 32;
+// one lighthouse v1 sweep, no point solving the pose more often
+const POSE_INTERVAL_US: u64 = 8333;
+
+// room-setup: fcal comes over ootx, one frame takes a few seconds per station
+const OOTX_TIMEOUT: Duration = Duration::from_secs(30);
+const FLOOR_CAPTURE: Duration = Duration::from_secs(5);
+// one floor frame per tdma cycle (a-h, a-v, b-h, b-v)
+const FLOOR_FRAME_US: u64 = 4 * POSE_INTERVAL_US;
+// headset standing upright on the floor, its origin sits about this high
+const FLOOR_ORIGIN_HEIGHT: f32 = 0.06;
+
+// one headset connection plus the tracking fed from it
+pub struct DeviceLink {
+    api: Option<KernelApi>,
+    imu: ImuStream,
+    orientation: ImuFilter,
+    tracker: LighthouseTracker,
+    fusion: SensorFusion,
+    last_solve_us: Option<u64>,
+    reconnects: u64,
+    imu_samples: u64,
+    last_reopen: Option<Instant>,
+}
+
+impl DeviceLink {
+    pub fn new(tracker: LighthouseTracker) -> Self {
+        Self {
+            api: None,
+            imu: ImuStream::new(),
+            orientation: ImuFilter::new(FilterGains::mahony()),
+            tracker,
+            fusion: SensorFusion::new(FusionNoise::consumer_imu()),
+            last_solve_us: None,
+            reconnects: 0,
+            imu_samples: 0,
+            last_reopen: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.api.as_ref().is_some_and(|api| api.is_open())
+    }
+
+    // first connection, or an existing api (replay, usbfs...)
+    pub fn connect(&mut self, mut api: KernelApi) {
+        self.configure(&mut api);
+        self.api = Some(api);
+    }
+
+    // device timestamps restart on reconnect, everything else is kept
+    pub fn handle_hotplug(&mut self, event: &HotplugEvent) {
+        let Some(api) = self.api.as_mut() else {
+            return;
+        };
+        let was_open = api.is_open();
+        if let Err(err) = api.handle_hotplug(event) {
+            println!("reopen failed, retrying: {}", err);
+        }
+        self.after_state_change(was_open);
+    }
+
+    // poll fallback while disconnected, in case the add event was missed
+    pub fn try_reopen(&mut self) {
+        if self.last_reopen.is_some_and(|t| t.elapsed() < REOPEN_INTERVAL) {
+            return;
+        }
+        self.reopen_now();
+    }
+
+    // hotplug events were lost, look at sysfs right away instead of waiting
+    pub fn reopen_now(&mut self) {
+        if self.is_connected() {
+            return;
+        }
+        self.last_reopen = Some(Instant::now());
+        let Some(api) = self.api.as_mut() else {
+            return;
+        };
+        if api.reopen().is_ok() {
+            self.after_state_change(false);
+        }
+    }
+
+    // read and dispatch one report, a read error means the device went away
+    pub fn poll_device(&mut self) {
+        let Some(api) = self.api.as_mut().filter(|api| api.is_open()) else {
+            return;
+        };
+        match api.read_sensors() {
+            Ok(report) => self.process_report(&report),
+            Err(err) => {
+                println!("device read failed: {}", err);
+                api.close();
+                self.after_state_change(true);
+            }
+        }
+    }
+
+    pub fn process_report(&mut self, report: &[u8]) {
+        match HidReport::parse(report) {
+            Some(HidReport::Imu(imu)) => {
+                let samples = self.imu.push(&imu);
+                self.orientation.update_all(&samples);
+                self.fusion.push_imu_samples(&samples);
+                self.imu_samples += samples.len() as u64;
+            }
+            Some(HidReport::Light(_)) => {
+                self.tracker.process_light_report(report);
+                self.update_pose();
+            }
+            Some(HidReport::Buttons(_)) | None => {}
+        }
+    }
+
+    // solve once per sweep and hand the pose to the ekf at the sweep time
+    fn update_pose(&mut self) {
+        let Some(sweep_us) = self.tracker.last_sweep_us() else {
+            return;
+        };
+        if self.last_solve_us.is_some_and(|t| sweep_us < t + POSE_INTERVAL_US) {
+            return;
+        }
+        self.last_solve_us = Some(sweep_us);
+        if let Some(solution) = self.tracker.solve_pose() {
+            self.fusion.push_pose(&PoseMeasurement::from_solution(&solution, sweep_us));
+        }
+    }
+
+    pub fn tracker(&self) -> &LighthouseTracker {
+        &self.tracker
+    }
+
+    pub fn imu(&self) -> &ImuStream {
+        &self.imu
+    }
+
+    // imu only 3dof orientation, None until aligned to gravity
+    pub fn orientation(&self) -> Option<UnitQuaternion<f32>> {
+        self.orientation.orientation()
+    }
+
+    pub fn fusion(&self) -> &SensorFusion {
+        &self.fusion
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    fn after_state_change(&mut self, was_open: bool) {
+        let is_open = self.is_connected();
+        if was_open && !is_open {
+            self.tracker.reset_device_clock();
+            self.imu.reset();
+            self.orientation.reset_clock();
+            self.fusion.reset_clock();
+            self.last_solve_us = None;
+        } else if !was_open && is_open {
+            self.reconnects += 1;
+            if let Some(mut api) = self.api.take() {
+                self.configure(&mut api);
+                self.api = Some(api);
+            }
+        }
+    }
+
+    // re-read the config every time, a different headset may have been plugged in
+    fn configure(&mut self, api: &mut KernelApi) {
+        match DeviceConfig::read(api) {
+            Ok(config) => {
+                self.tracker.apply_device_config(&config);
+                self.imu.set_calibration(config.imu_calibration());
+                let device_from_imu = config.imu_extrinsics().unwrap_or_else(Isometry3::identity);
+                self.fusion.set_imu_extrinsics(device_from_imu.cast::<f64>());
+            }
+            Err(err) => println!("device config not available: {}", err),
+        }
+    }
+}
+
+// --record / --replay, see kernel_api::capture for the file format
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    // write every call to the headset to this file, stops at the first disconnect
+    pub record: Option<String>,
+    // run from a capture instead of the headset, in real time, and exit at its end
+    pub replay: Option<String>,
+}
+
+pub fn run(options: &DaemonOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tracker = LighthouseTracker::new(DEFAULT_SENSORS);
+    match tracker.load_room_setup(ROOM_SETUP_FILE) {
+        Ok(count) => println!("{} base stations from {}", count, ROOM_SETUP_FILE),
+        Err(err) => println!("no room setup ({}), run `librevr room-setup` first", err),
+    }
+    let mut link = DeviceLink::new(tracker);
+
+    // no hotplug or reopen, the capture ends with a read error
+    if let Some(path) = &options.replay {
+        link.connect(KernelApi::replay(path, true)?);
+        let mut last_status = Instant::now();
+        while link.is_connected() {
+            link.poll_device();
+            if last_status.elapsed() >= STATUS_INTERVAL {
+                last_status = Instant::now();
+                print_status(&link);
+            }
+        }
+        print_status(&link);
+        println!("replay of {} finished", path);
+        return Ok(());
+    }
+
+    let mut monitor = HotplugMonitor::new(HotplugFilter::vive())?;
+
+    // wait for the first headset
+    let mut api = loop {
+        match KernelApi::open_first() {
+            Ok(api) => break api,
+            Err(_) => {
+                monitor.poll(REOPEN_INTERVAL)?;
+            }
+        }
+    };
+    // before connect, so the device config reads are in the capture too
+    if let Some(path) = &options.record {
+        api.start_recording(path)?;
+    }
+    link.connect(api);
+
+    let mut last_status = Instant::now();
+    loop {
+        let wait = if link.is_connected() { Duration::ZERO } else { HOTPLUG_WAIT };
+        for event in monitor.poll(wait)? {
+            link.handle_hotplug(&event);
+        }
+        if monitor.take_overflow() {
+            link.reopen_now();
+        }
+
+        if link.is_connected() {
+            link.poll_device();
+        } else {
+            link.try_reopen();
+        }
+
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            last_status = Instant::now();
+            print_status(&link);
+        }
+    }
+}
+
+fn print_status(link: &DeviceLink) {
+    println!(
+        "connected={} reconnects={} imu samples={} dropped={}",
+        link.is_connected(),
+        link.reconnects(),
+        link.imu_samples,
+        link.imu().dropped_samples()
+    );
+    if let Some(q) = link.orientation() {
+        let [roll, pitch, yaw] = helpers::to_degrees(helpers::quaternion_to_euler([q.i, q.j, q.k, q.w]));
+        println!("imu orientation: roll={:.1} pitch={:.1} yaw={:.1}", roll, pitch, yaw);
+    }
+    if let Some(pose) = link.fusion().pose() {
+        let std = link.fusion().position_std();
+        println!(
+            "fused position: [{:.3}, {:.3}, {:.3}] ±{:.1} mm{}",
+            pose.position.x,
+            pose.position.y,
+            pose.position.z,
+            std.max() * 1000.0,
+            if link.fusion().is_degraded() { " (degraded)" } else { "" }
+        );
+    }
+    link.tracker().print_status();
+}
+
+// `librevr room-setup`: record angles with the headset resting on the floor,
+// solve the station poses and write ROOM_SETUP_FILE for the next --daemon start
+pub fn room_setup() -> Result<(), Box<dyn std::error::Error>> {
+    // placeholders, angles are only collected for stations the tracker knows
+    let mut tracker = LighthouseTracker::new(DEFAULT_SENSORS);
+    for id in 0..2 {
+        tracker.add_base_station(BaseStation::new(id, Point3::origin()));
+    }
+    let mut link = DeviceLink::new(tracker);
+    link.connect(KernelApi::open_first()?);
+    let constellation = link
+        .tracker()
+        .constellation()
+        .cloned()
+        .ok_or("device config has no sensor positions")?;
+
+    println!("put the headset upright on the floor and step out of view");
+
+    // without fcal the angles are a degree or more off
+    let start = Instant::now();
+    while start.elapsed() < OOTX_TIMEOUT && (0..2).any(|s| link.tracker().get_station_info(s).is_none()) {
+        poll_connected(&mut link)?;
+    }
+    if link.tracker().get_station_info(0).is_none() {
+        return Err("no ootx data from station a, is it in view?".into());
+    }
+
+    println!("recording for {} seconds, keep the headset still", FLOOR_CAPTURE.as_secs());
+    let floor_pose = Isometry3::translation(0.0, FLOOR_ORIGIN_HEIGHT, 0.0);
+    let mut calibrator = RoomCalibrator::new(constellation, floor_pose);
+    let mut last_frame_us: Option<u64> = None;
+    let start = Instant::now();
+    while start.elapsed() < FLOOR_CAPTURE {
+        poll_connected(&mut link)?;
+        let Some(sweep_us) = link.tracker().last_sweep_us() else {
+            continue;
+        };
+        if last_frame_us.is_some_and(|t| sweep_us < t + FLOOR_FRAME_US) {
+            continue;
+        }
+        last_frame_us = Some(sweep_us);
+        calibrator.add_floor_frame(&link.tracker().collect_observations());
+    }
+
+    let solution = calibrator.solve(false)?;
+    println!(
+        "{} base stations from {} floor frames, rms error {:.2} mrad",
+        solution.stations.len(),
+        calibrator.floor_frames(),
+        solution.reprojection_error * 1000.0
+    );
+
+    // pose from the solve, id, mode and fcal from ootx
+    let stations: Vec<BaseStation> = solution
+        .stations
+        .iter()
+        .enumerate()
+        .map(|(index, station)| {
+            let mut station = station.clone();
+            if let Some(info) = link.tracker().get_station_info(index) {
+                station.apply_ootx(info);
+            }
+            station
+        })
+        .collect();
+    RoomSetup::from_stations(&stations).save(ROOM_SETUP_FILE)?;
+    println!("saved {}", ROOM_SETUP_FILE);
+    Ok(())
+}
+
+// room-setup can not wait for a reconnect, the headset must not move
+fn poll_connected(link: &mut DeviceLink) -> Result<(), Box<dyn std::error::Error>> {
+    link.poll_device();
+    if link.is_connected() {
+        Ok(())
+    } else {
+        Err("headset disconnected".into())
+    }
+}
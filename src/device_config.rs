@@ -0,0 +1,461 @@
+// factory configuration stored in the headset / controller firmware
+// feature report 0x10 rewinds the read pointer, then every 0x11 report returns
+// one chunk (byte 1 = length, 0 at the end). the chunks form a zlib stream that
+// inflates to json with the photodiode positions, imu calibration and optics
+//
+// field names follow the json as the firmware sends it (see libsurvive / openhmd)
+
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use nalgebra::{Isometry3, Matrix3, Point3, Rotation3, Translation3, UnitQuaternion, Vector3};
+use serde::{Serialize, Deserialize};
+use crate::kernel_api::KernelApi;
+use crate::lighthouse_pose::{SensorConstellation, SensorModel};
+
+const CONFIG_START_REPORT_ID: u8 = 0x10;
+const CONFIG_READ_REPORT_ID: u8 = 0x11;
+const CONFIG_REPORT_LEN: usize = 64;
+const CONFIG_CHUNK_HEADER: usize = 2;
+// real configs are 5-20 kb compressed, anything bigger means a broken read
+const CONFIG_MAX_COMPRESSED: usize = 256 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    #[serde(default)]
+    pub device_class: String,
+    #[serde(default)]
+    pub device_serial_number: String,
+    #[serde(default)]
+    pub device_pid: Option<u32>,
+    #[serde(default)]
+    pub lighthouse_config: Option<LighthouseConfig>,
+    // newer firmware groups the imu values, older firmware has them at the top level
+    #[serde(default)]
+    pub imu: Option<ImuConfig>,
+    #[serde(default)]
+    pub acc_bias: Option<[f32; 3]>,
+    #[serde(default)]
+    pub acc_scale: Option<[f32; 3]>,
+    #[serde(default)]
+    pub gyro_bias: Option<[f32; 3]>,
+    #[serde(default)]
+    pub gyro_scale: Option<[f32; 3]>,
+    #[serde(default)]
+    pub head: Option<ConfigFrame>,
+    #[serde(default)]
+    pub lens_separation: Option<f32>,
+    #[serde(default)]
+    pub device: Option<DisplayGeometry>,
+    #[serde(default)]
+    pub tracking_to_eye_transform: Vec<EyeTransform>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct LighthouseConfig {
+    // hardware channel of model point i
+    #[serde(rename = "channelMap", default)]
+    pub channel_map: Vec<u8>,
+    #[serde(rename = "modelPoints", default)]
+    pub model_points: Vec<[f32; 3]>,
+    #[serde(rename = "modelNormals", default)]
+    pub model_normals: Vec<[f32; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ImuConfig {
+    #[serde(default)]
+    pub acc_bias: Option<[f32; 3]>,
+    #[serde(default)]
+    pub acc_scale: Option<[f32; 3]>,
+    #[serde(default)]
+    pub gyro_bias: Option<[f32; 3]>,
+    #[serde(default)]
+    pub gyro_scale: Option<[f32; 3]>,
+    #[serde(default)]
+    pub plus_x: Option<[f32; 3]>,
+    #[serde(default)]
+    pub plus_z: Option<[f32; 3]>,
+    #[serde(default)]
+    pub position: Option<[f32; 3]>,
+}
+
+// axes and origin of a sub frame (head, imu) in the device frame
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ConfigFrame {
+    pub plus_x: [f32; 3],
+    pub plus_z: [f32; 3],
+    pub position: [f32; 3],
+}
+
+impl ConfigFrame {
+    // device_from_frame, None for degenerate axes
+    pub fn isometry(&self) -> Option<Isometry3<f32>> {
+        frame_isometry(self.plus_x, self.plus_z, self.position)
+    }
+}
+
+// +y completes the right handed frame, +z is re-orthogonalised against +x
+fn frame_isometry(plus_x: [f32; 3], plus_z: [f32; 3], position: [f32; 3]) -> Option<Isometry3<f32>> {
+    let x = Vector3::from(plus_x).try_normalize(1.0e-6)?;
+    let z = Vector3::from(plus_z);
+    let z = (z - x * x.dot(&z)).try_normalize(1.0e-6)?;
+    let y = z.cross(&x);
+    let rotation = Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[x, y, z]));
+    Some(Isometry3::from_parts(
+        Translation3::from(Vector3::from(position)),
+        UnitQuaternion::from_rotation_matrix(&rotation),
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DisplayGeometry {
+    #[serde(default)]
+    pub eye_target_height_in_pixels: u32,
+    #[serde(default)]
+    pub eye_target_width_in_pixels: u32,
+    #[serde(default)]
+    pub first_eye: Option<String>,
+    #[serde(default)]
+    pub num_windows: Option<u32>,
+    #[serde(default)]
+    pub persistence: Option<f32>,
+    #[serde(default)]
+    pub physical_aspect_x_over_y: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct EyeTransform {
+    #[serde(default)]
+    pub distortion: Option<Distortion>,
+    #[serde(default)]
+    pub distortion_red: Option<Distortion>,
+    #[serde(default)]
+    pub distortion_blue: Option<Distortion>,
+    #[serde(default)]
+    pub eye_to_head: Option<[[f32; 4]; 3]>,
+    #[serde(default)]
+    pub intrinsics: Option<[[f32; 3]; 3]>,
+    #[serde(default)]
+    pub grow_for_undistort: Option<f32>,
+    #[serde(default)]
+    pub undistort_r2_cutoff: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Distortion {
+    #[serde(default)]
+    pub center_x: f32,
+    #[serde(default)]
+    pub center_y: f32,
+    #[serde(default)]
+    pub coeffs: Vec<f32>,
+    #[serde(rename = "type", default)]
+    pub kind: String,
+}
+
+// imu correction in the units of hid_reports::ImuSample (g, deg/s)
+//   corrected = raw * scale - bias
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ImuCalibration {
+    pub acc_bias: [f32; 3],
+    pub acc_scale: [f32; 3],
+    pub gyro_bias: [f32; 3],
+    pub gyro_scale: [f32; 3],
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self {
+            acc_bias: [0.0; 3],
+            acc_scale: [1.0; 3],
+            gyro_bias: [0.0; 3],
+            gyro_scale: [1.0; 3],
+        }
+    }
+}
+
+impl ImuCalibration {
+    pub fn apply_accel(&self, accel: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| accel[i] * self.acc_scale[i] - self.acc_bias[i])
+    }
+
+    pub fn apply_gyro(&self, gyro: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| gyro[i] * self.gyro_scale[i] - self.gyro_bias[i])
+    }
+}
+
+impl DeviceConfig {
+    // pull the compressed blob over feature reports and decode it
+    pub fn read(api: &mut KernelApi) -> Result<Self, Box<dyn std::error::Error>> {
+        let compressed = read_compressed(api)?;
+        println!("device config: {} bytes compressed", compressed.len());
+        Self::from_compressed(&compressed)
+    }
+
+    pub fn from_compressed(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut json = String::new();
+        ZlibDecoder::new(data).read_to_string(&mut json)?;
+        Self::parse(&json)
+    }
+
+    pub fn parse(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    // photodiodes indexed by hardware channel (the sensor id in light reports)
+    pub fn constellation(&self) -> Option<SensorConstellation> {
+        let config = self.lighthouse_config.as_ref()?;
+        let count = config.model_points.len();
+        if count == 0 || config.model_normals.len() != count {
+            return None;
+        }
+
+        let channel = |i: usize| -> usize {
+            if config.channel_map.len() == count {
+                config.channel_map[i] as usize
+            } else {
+                i
+            }
+        };
+        let size = (0..count).map(|i| channel(i) + 1).max()?;
+
+        let mut sensors = vec![
+            SensorModel {
+                position: Point3::origin(),
+                normal: Vector3::zeros(),
+            };
+            size
+        ];
+        for i in 0..count {
+            let [px, py, pz] = config.model_points[i];
+            let [nx, ny, nz] = config.model_normals[i];
+            sensors[channel(i)] = SensorModel {
+                position: Point3::new(px, py, pz),
+                normal: Vector3::new(nx, ny, nz),
+            };
+        }
+        Some(SensorConstellation::new(sensors))
+    }
+
+    // device_from_imu from the imu axes, None when the config has none
+    pub fn imu_extrinsics(&self) -> Option<Isometry3<f32>> {
+        let imu = self.imu.as_ref()?;
+        frame_isometry(imu.plus_x?, imu.plus_z?, imu.position.unwrap_or([0.0; 3]))
+    }
+
+    // imu axes in the head frame for the 3dof orientation, identity for missing frames
+    pub fn head_from_imu(&self) -> UnitQuaternion<f32> {
+        let device_from_head = self.head.as_ref().and_then(ConfigFrame::isometry);
+        let device_from_imu = self.imu_extrinsics();
+        let rotation = |frame: Option<Isometry3<f32>>| frame.map_or(UnitQuaternion::identity(), |f| f.rotation);
+        rotation(device_from_head).inverse() * rotation(device_from_imu)
+    }
+
+    // grouped values win over the old top level ones, missing values stay neutral
+    pub fn imu_calibration(&self) -> ImuCalibration {
+        let imu = self.imu.clone().unwrap_or_default();
+        let defaults = ImuCalibration::default();
+
+        ImuCalibration {
+            acc_bias: imu.acc_bias.or(self.acc_bias).unwrap_or(defaults.acc_bias),
+            acc_scale: imu.acc_scale.or(self.acc_scale).unwrap_or(defaults.acc_scale),
+            gyro_bias: imu.gyro_bias.or(self.gyro_bias).unwrap_or(defaults.gyro_bias),
+            gyro_scale: imu.gyro_scale.or(self.gyro_scale).unwrap_or(defaults.gyro_scale),
+        }
+    }
+}
+
+fn read_compressed(api: &mut KernelApi) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    api.get_feature_report(CONFIG_START_REPORT_ID, CONFIG_REPORT_LEN)?;
+
+    let mut data = Vec::new();
+    loop {
+        let report = api.get_feature_report(CONFIG_READ_REPORT_ID, CONFIG_REPORT_LEN)?;
+        if report.len() < CONFIG_CHUNK_HEADER || report[0] != CONFIG_READ_REPORT_ID {
+            return Err("unexpected config report".into());
+        }
+
+        let len = report[1] as usize;
+        if len == 0 {
+            break;
+        }
+        let end = CONFIG_CHUNK_HEADER + len;
+        if end > report.len() {
+            return Err(format!("config chunk too long: {} bytes", len).into());
+        }
+        data.extend_from_slice(&report[CONFIG_CHUNK_HEADER..end]);
+
+        if data.len() > CONFIG_MAX_COMPRESSED {
+            return Err("config blob too large".into());
+        }
+    }
+
+    if data.is_empty() {
+        return Err("config blob is empty".into());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use crate::kernel_api::capture::{self, CaptureEntry, CaptureOp};
+    use crate::kernel_api::DeviceInfo;
+
+    // trimmed down vive headset config, values as the firmware sends them
+    const CONFIG_JSON: &str = r#"{
+        "device_class": "hmd",
+        "device_serial_number": "LHR-0A1B2C3D",
+        "device_pid": 8192,
+        "lighthouse_config": {
+            "channelMap": [4, 0, 2],
+            "modelPoints": [[0.01, 0.02, 0.03], [0.04, 0.05, 0.06], [0.07, 0.08, 0.09]],
+            "modelNormals": [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
+        },
+        "imu": {
+            "acc_bias": [0.01, -0.02, 0.03],
+            "gyro_scale": [1.1, 1.0, 0.9],
+            "plus_x": [0, 0, -1],
+            "plus_z": [1, 0, 0],
+            "position": [0.0, 0.01, -0.02]
+        },
+        "acc_bias": [0.5, 0.5, 0.5],
+        "acc_scale": [1.02, 0.98, 1.0],
+        "head": { "plus_x": [1, 0, 0], "plus_z": [0, 0, 1], "position": [0, 0, 0] },
+        "lens_separation": 0.0635
+    }"#;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let config = DeviceConfig::from_compressed(&compress(CONFIG_JSON.as_bytes())).unwrap();
+        assert_eq!(config, DeviceConfig::parse(CONFIG_JSON).unwrap());
+        assert_eq!(config.device_serial_number, "LHR-0A1B2C3D");
+        assert_eq!(config.device_pid, Some(8192));
+        assert_eq!(config.lens_separation, Some(0.0635));
+
+        // a config that went through serde again compresses to the same values
+        let again = serde_json::to_string(&config).unwrap();
+        assert_eq!(DeviceConfig::from_compressed(&compress(again.as_bytes())).unwrap(), config);
+
+        assert!(DeviceConfig::from_compressed(CONFIG_JSON.as_bytes()).is_err());
+        let compressed = compress(CONFIG_JSON.as_bytes());
+        assert!(DeviceConfig::from_compressed(&compressed[..compressed.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn channel_map_places_sensors_by_channel() {
+        let config = DeviceConfig::parse(CONFIG_JSON).unwrap();
+        let constellation = config.constellation().unwrap();
+
+        // channels 1 and 3 are not wired, they stay as holes without a normal
+        assert_eq!(constellation.len(), 5);
+        assert_eq!(constellation.get(4).unwrap().position, Point3::new(0.01, 0.02, 0.03));
+        assert_eq!(constellation.get(0).unwrap().normal, Vector3::y());
+        assert_eq!(constellation.get(2).unwrap().normal, Vector3::z());
+        for hole in [1, 3] {
+            assert_eq!(constellation.get(hole).unwrap().normal, Vector3::zeros());
+        }
+
+        // a map that does not cover every point is ignored
+        let mut config = config;
+        let lighthouse = config.lighthouse_config.as_mut().unwrap();
+        lighthouse.channel_map = vec![4, 0];
+        let constellation = config.constellation().unwrap();
+        assert_eq!(constellation.len(), 3);
+        assert_eq!(constellation.get(0).unwrap().normal, Vector3::x());
+
+        let lighthouse = config.lighthouse_config.as_mut().unwrap();
+        lighthouse.model_normals.pop();
+        assert!(config.constellation().is_none());
+    }
+
+    #[test]
+    fn imu_calibration_prefers_the_grouped_values() {
+        let calibration = DeviceConfig::parse(CONFIG_JSON).unwrap().imu_calibration();
+        // grouped over top level
+        assert_eq!(calibration.acc_bias, [0.01, -0.02, 0.03]);
+        // top level when the group has none
+        assert_eq!(calibration.acc_scale, [1.02, 0.98, 1.0]);
+        assert_eq!(calibration.gyro_scale, [1.1, 1.0, 0.9]);
+        // neutral when neither has it
+        assert_eq!(calibration.gyro_bias, [0.0; 3]);
+
+        // old firmware without the group
+        let old = DeviceConfig::parse(r#"{ "acc_bias": [0.5, 0.5, 0.5], "gyro_bias": [1, 2, 3] }"#).unwrap();
+        let calibration = old.imu_calibration();
+        assert_eq!(calibration.acc_bias, [0.5; 3]);
+        assert_eq!(calibration.gyro_bias, [1.0, 2.0, 3.0]);
+        assert_eq!(calibration.acc_scale, [1.0; 3]);
+
+        assert_eq!(DeviceConfig::default().imu_calibration(), ImuCalibration::default());
+    }
+
+    // 0x10 rewind, then 0x11 chunks of up to 62 bytes and an empty one
+    fn config_capture(compressed: &[u8]) -> Vec<u8> {
+        let device = DeviceInfo {
+            vendor_id: 0x28de,
+            product_id: 0x2000,
+            device_path: "/dev/hidraw0".to_string(),
+            name: None,
+            serial: None,
+            interface: Some(0),
+        };
+        let entry = |report_id: u8, rx: Vec<u8>| CaptureEntry {
+            time_us: 0,
+            op: CaptureOp::GetFeature { report_id, len: CONFIG_REPORT_LEN },
+            tx: Vec::new(),
+            rx,
+            result: None,
+            error: None,
+        };
+        let chunk = |data: &[u8]| {
+            let mut report = vec![CONFIG_READ_REPORT_ID, data.len() as u8];
+            report.extend_from_slice(data);
+            report.resize(CONFIG_REPORT_LEN, 0);
+            report
+        };
+
+        let mut entries = vec![entry(CONFIG_START_REPORT_ID, vec![CONFIG_START_REPORT_ID; CONFIG_REPORT_LEN])];
+        for data in compressed.chunks(CONFIG_REPORT_LEN - CONFIG_CHUNK_HEADER) {
+            entries.push(entry(CONFIG_READ_REPORT_ID, chunk(data)));
+        }
+        entries.push(entry(CONFIG_READ_REPORT_ID, chunk(&[])));
+
+        let mut bytes = Vec::new();
+        capture::write_header(&mut bytes, &device).unwrap();
+        for entry in entries {
+            writeln!(bytes, "{}", serde_json::to_string(&entry).unwrap()).unwrap();
+        }
+        bytes
+    }
+
+    fn replay(capture: &[u8]) -> KernelApi {
+        let replay = capture::ReplayTransport::from_reader(capture, false).unwrap();
+        let device = replay.device_info().clone();
+        KernelApi::with_transport(Box::new(replay), device)
+    }
+
+    #[test]
+    fn reads_the_config_over_feature_reports() {
+        let compressed = compress(CONFIG_JSON.as_bytes());
+        assert!(compressed.len() > CONFIG_REPORT_LEN);
+
+        let mut api = replay(&config_capture(&compressed));
+        assert_eq!(read_compressed(&mut api).unwrap(), compressed);
+        let mut api = replay(&config_capture(&compressed));
+        assert_eq!(DeviceConfig::read(&mut api).unwrap(), DeviceConfig::parse(CONFIG_JSON).unwrap());
+
+        // nothing but the end marker
+        let mut api = replay(&config_capture(&[]));
+        assert!(read_compressed(&mut api).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+// vive pro 2 display control over hid feature reports
+// the pro 2 keeps its panels dark on linux until a display mode is picked, which
+// steamvr normally does through lighthouse_console. every command is one 64 byte
+// feature report on the headset's first hid interface (0bb4:0342):
+//   byte 0     report id 0x04
+//   byte 1..3  command id, little endian
+//   byte 3     payload length
+//   byte 4..   payload, zero padded
+// text commands go to 0x2970. mode selection is "wireless,0" followed by "dtd,<n>";
+// the headset then drops off the bus and re-enumerates with the new edid
+// (see the vivepro2-linux-driver project for the reverse engineering)
+
+use std::fmt;
+use std::str::FromStr;
+use crate::kernel_api::{KernelApi, SysfsScanner};
+
+pub const VIVE_PRO_2_VENDOR_ID: u16 = 0x0bb4;
+pub const VIVE_PRO_2_PRODUCT_ID: u16 = 0x0342;
+
+const COMMAND_REPORT_ID: u8 = 0x04;
+const COMMAND_REPORT_LEN: usize = 64;
+const COMMAND_HEADER_LEN: usize = 4;
+const COMMAND_MAX_PAYLOAD: usize = COMMAND_REPORT_LEN - COMMAND_HEADER_LEN;
+
+const TEXT_COMMAND_ID: u16 = 0x2970;
+// same power request as the original vive (openhmd vive_magic_power_on/off)
+const POWER_COMMAND_ID: u16 = 0x2978;
+const POWER_PAYLOAD_LEN: usize = 0x38;
+const POWER_STATE_OFFSET: usize = 4;
+
+// brightness is a percentage on the headset side
+pub const MAX_BRIGHTNESS: u8 = 100;
+
+// modes as the firmware numbers them, resolution is both eyes side by side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayMode {
+    Res2448x1224Hz90,
+    Res2448x1224Hz120,
+    Res3264x1632Hz90,
+    Res3680x1836Hz90,
+    Res4896x2448Hz90,
+    Res4896x2448Hz120,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 6] = [
+        DisplayMode::Res2448x1224Hz90,
+        DisplayMode::Res2448x1224Hz120,
+        DisplayMode::Res3264x1632Hz90,
+        DisplayMode::Res3680x1836Hz90,
+        DisplayMode::Res4896x2448Hz90,
+        DisplayMode::Res4896x2448Hz120,
+    ];
+
+    // number sent in "dtd,<n>"
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    pub fn resolution(self) -> (u32, u32) {
+        match self {
+            DisplayMode::Res2448x1224Hz90 | DisplayMode::Res2448x1224Hz120 => (2448, 1224),
+            DisplayMode::Res3264x1632Hz90 => (3264, 1632),
+            DisplayMode::Res3680x1836Hz90 => (3680, 1836),
+            DisplayMode::Res4896x2448Hz90 | DisplayMode::Res4896x2448Hz120 => (4896, 2448),
+        }
+    }
+
+    pub fn refresh_hz(self) -> u32 {
+        match self {
+            DisplayMode::Res2448x1224Hz120 | DisplayMode::Res4896x2448Hz120 => 120,
+            _ => 90,
+        }
+    }
+
+    // per eye, what the compositor should render to
+    pub fn eye_resolution(self) -> (u32, u32) {
+        let (width, height) = self.resolution();
+        (width / 2, height)
+    }
+}
+
+impl fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (width, height) = self.resolution();
+        write!(f, "{}x{}@{}hz", width, height, self.refresh_hz())
+    }
+}
+
+// accepts "120hz" / "90" (full resolution at that rate), "2448x1224@120hz",
+// "2448x1224" (90 hz) or the raw mode number "0".."5"
+impl FromStr for DisplayMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_ascii_lowercase();
+        let value = value.strip_suffix("hz").unwrap_or(&value);
+        let error = || format!("unknown display mode '{}', expected one of 90hz, 120hz, WxH@RATE or 0-5", value);
+
+        let (resolution, refresh) = match value.split_once('@') {
+            Some((resolution, refresh)) => (Some(resolution), refresh.parse::<u32>().map_err(|_| error())?),
+            None if value.contains('x') => (Some(value), 90),
+            None => match value.parse::<u32>().map_err(|_| error())? {
+                index @ 0..=5 => return Self::from_index(index as u8).ok_or_else(error),
+                refresh => (None, refresh),
+            },
+        };
+
+        let size = match resolution {
+            Some(resolution) => {
+                let (width, height) = resolution.split_once('x').ok_or_else(error)?;
+                Some((width.parse::<u32>().map_err(|_| error())?, height.parse::<u32>().map_err(|_| error())?))
+            }
+            None => None,
+        };
+
+        // without a resolution pick the largest one that runs at that rate
+        Self::ALL
+            .iter()
+            .rev()
+            .find(|mode| mode.refresh_hz() == refresh && size.is_none_or(|size| mode.resolution() == size))
+            .copied()
+            .ok_or_else(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayCommand {
+    SetMode(DisplayMode),
+    PanelPower(bool),
+    // 0-MAX_BRIGHTNESS
+    Brightness(u8),
+}
+
+impl DisplayCommand {
+    // feature reports to send, in order
+    pub fn to_reports(self) -> Vec<Vec<u8>> {
+        match self {
+            DisplayCommand::SetMode(mode) => vec![
+                text_report("wireless,0"),
+                text_report(&format!("dtd,{}", mode.index())),
+            ],
+            DisplayCommand::PanelPower(on) => {
+                let mut payload = [0u8; POWER_PAYLOAD_LEN];
+                payload[POWER_STATE_OFFSET] = on as u8;
+                vec![command_report(POWER_COMMAND_ID, &payload)]
+            }
+            // same text command as the mode switch, not confirmed on hardware yet
+            DisplayCommand::Brightness(level) => {
+                vec![text_report(&format!("setbrightness,{}", level.min(MAX_BRIGHTNESS)))]
+            }
+        }
+    }
+
+    pub fn send(&self, api: &mut KernelApi) -> Result<(), Box<dyn std::error::Error>> {
+        for report in self.to_reports() {
+            api.send_feature_report(&report)?;
+        }
+        Ok(())
+    }
+}
+
+fn text_report(text: &str) -> Vec<u8> {
+    command_report(TEXT_COMMAND_ID, text.as_bytes())
+}
+
+fn command_report(command: u16, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len().min(COMMAND_MAX_PAYLOAD);
+    let mut report = vec![0u8; COMMAND_REPORT_LEN];
+    report[0] = COMMAND_REPORT_ID;
+    report[1..3].copy_from_slice(&command.to_le_bytes());
+    report[3] = len as u8;
+    report[COMMAND_HEADER_LEN..COMMAND_HEADER_LEN + len].copy_from_slice(&payload[..len]);
+    report
+}
+
+// first hid interface of the pro 2, the other interfaces ignore these reports
+pub fn open_headset() -> Result<KernelApi, Box<dyn std::error::Error>> {
+    let device = SysfsScanner::new()
+        .find_hidraw_devices()
+        .into_iter()
+        .filter(|d| d.vendor_id == VIVE_PRO_2_VENDOR_ID && d.product_id == VIVE_PRO_2_PRODUCT_ID)
+        .min_by_key(|d| d.interface.unwrap_or(u8::MAX))
+        .ok_or("no vive pro 2 found (is the udev rule installed?)")?;
+    KernelApi::open(&device.device_path)
+}
+
+// `librevr set-mode <mode>`, `librevr panel on|off`, `librevr brightness <0-100>`
+// returns None when args are not a display command
+pub fn run_cli(args: &[String]) -> Option<Result<(), Box<dyn std::error::Error>>> {
+    let command = match (args.first()?.as_str(), args.get(1)) {
+        ("set-mode", Some(mode)) => mode.parse().map(DisplayCommand::SetMode),
+        ("panel", Some(state)) => match state.as_str() {
+            "on" => Ok(DisplayCommand::PanelPower(true)),
+            "off" => Ok(DisplayCommand::PanelPower(false)),
+            _ => Err(format!("expected 'on' or 'off', got '{}'", state)),
+        },
+        ("brightness", Some(level)) => match level.parse::<u8>() {
+            Ok(level) if level <= MAX_BRIGHTNESS => Ok(DisplayCommand::Brightness(level)),
+            _ => Err(format!("brightness must be 0-{}, got '{}'", MAX_BRIGHTNESS, level)),
+        },
+        ("set-mode" | "panel" | "brightness", None) => Err(format!("missing argument for {}", args[0])),
+        _ => return None,
+    };
+
+    Some(command.map_err(|e| e.into()).and_then(|command| {
+        let mut api = open_headset()?;
+        command.send(&mut api)?;
+        match command {
+            DisplayCommand::SetMode(mode) => {
+                println!("display mode set to {}, the headset will reconnect", mode)
+            }
+            DisplayCommand::PanelPower(on) => println!("panel {}", if on { "on" } else { "off" }),
+            DisplayCommand::Brightness(level) => println!("brightness {}", level),
+        }
+        Ok(())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn padded(bytes: &[u8]) -> Vec<u8> {
+        let mut report = bytes.to_vec();
+        report.resize(COMMAND_REPORT_LEN, 0);
+        report
+    }
+
+    #[test]
+    fn set_mode_reports() {
+        let reports = DisplayCommand::SetMode(DisplayMode::Res4896x2448Hz120).to_reports();
+        assert_eq!(
+            reports,
+            vec![
+                padded(&[&[0x04, 0x70, 0x29, 0x0a][..], b"wireless,0"].concat()),
+                padded(&[&[0x04, 0x70, 0x29, 0x05][..], b"dtd,5"].concat()),
+            ]
+        );
+    }
+
+    #[test]
+    fn panel_power_report() {
+        let reports = DisplayCommand::PanelPower(true).to_reports();
+        assert_eq!(reports, vec![padded(&[0x04, 0x78, 0x29, 0x38, 0x00, 0x00, 0x00, 0x00, 0x01])]);
+
+        let reports = DisplayCommand::PanelPower(false).to_reports();
+        assert_eq!(reports, vec![padded(&[0x04, 0x78, 0x29, 0x38])]);
+    }
+
+    #[test]
+    fn brightness_report() {
+        let reports = DisplayCommand::Brightness(80).to_reports();
+        assert_eq!(reports, vec![padded(&[&[0x04, 0x70, 0x29, 0x10][..], b"setbrightness,80"].concat())]);
+
+        let reports = DisplayCommand::Brightness(255).to_reports();
+        assert_eq!(reports, vec![padded(&[&[0x04, 0x70, 0x29, 0x11][..], b"setbrightness,100"].concat())]);
+    }
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!("120hz".parse(), Ok(DisplayMode::Res4896x2448Hz120));
+        assert_eq!("90".parse(), Ok(DisplayMode::Res4896x2448Hz90));
+        assert_eq!("2448x1224@120hz".parse(), Ok(DisplayMode::Res2448x1224Hz120));
+        assert_eq!("3264x1632".parse(), Ok(DisplayMode::Res3264x1632Hz90));
+        assert_eq!("5".parse(), Ok(DisplayMode::Res4896x2448Hz120));
+        assert!("60hz".parse::<DisplayMode>().is_err());
+        assert!("3264x1632@120".parse::<DisplayMode>().is_err());
+        assert!("fast".parse::<DisplayMode>().is_err());
+    }
+
+    #[test]
+    fn mode_display_round_trips() {
+        for mode in DisplayMode::ALL {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+            assert_eq!(DisplayMode::from_index(mode.index()), Some(mode));
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// scripted controller vibration for perception experiments (--haptics <pattern.json>)
+// a pattern is a list of pulses at fixed offsets from its start, optionally repeated:
+//
+//   {
+//     "name": "two_point",
+//     "repeat": 3,
+//     "period_ms": 1000,
+//     "pulses": [
+//       { "at_ms": 0,   "hand": "left",  "duration_ms": 50, "frequency_hz": 160, "amplitude": 0.8 },
+//       { "at_ms": 250, "hand": "right", "duration_ms": 50, "amplitude": 0.8 }
+//     ]
+//   }
+//
+// pulses go out from the frame loop, so they are issued up to one frame after
+// their scheduled time; both times end up in SessionMetrics::haptic_pulses
+
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use crate::controller_input::Hand;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternPulse {
+    // offset from the start of the repetition
+    pub at_ms: u64,
+    pub hand: Hand,
+    pub duration_ms: f32,
+    // 0 = runtime default
+    #[serde(default)]
+    pub frequency_hz: f32,
+    // 0-1
+    pub amplitude: f32,
+}
+
+impl PatternPulse {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.duration_ms.max(0.0) / 1000.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HapticPattern {
+    pub name: String,
+    // total number of runs
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+    // time between run starts, defaults to the end of the last pulse
+    #[serde(default)]
+    pub period_ms: Option<u64>,
+    pub pulses: Vec<PatternPulse>,
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+impl HapticPattern {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = std::fs::read_to_string(path)?;
+        let mut pattern: HapticPattern = serde_json::from_str(&json)?;
+        if pattern.pulses.is_empty() {
+            return Err(format!("haptic pattern '{}' has no pulses", pattern.name).into());
+        }
+        if let Some(pulse) = pattern.pulses.iter().find(|p| !(0.0..=1.0).contains(&p.amplitude)) {
+            return Err(format!("amplitude {} at {} ms is outside 0-1", pulse.amplitude, pulse.at_ms).into());
+        }
+        pattern.pulses.sort_by_key(|p| p.at_ms);
+        Ok(pattern)
+    }
+
+    pub fn period_ms(&self) -> u64 {
+        self.period_ms.unwrap_or_else(|| {
+            self.pulses
+                .iter()
+                .map(|p| p.at_ms + p.duration_ms.max(0.0).ceil() as u64)
+                .max()
+                .unwrap_or(0)
+        })
+    }
+}
+
+// a pulse whose time has come, scheduled_ms is on the caller's clock
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuePulse {
+    pub scheduled_ms: u64,
+    pub pulse: PatternPulse,
+}
+
+pub struct HapticPlayer {
+    pattern: HapticPattern,
+    start_ms: u64,
+    run: u32,
+    next: usize,
+}
+
+impl HapticPlayer {
+    // start_ms on the same clock later passed to due()
+    pub fn new(pattern: HapticPattern, start_ms: u64) -> Self {
+        Self {
+            pattern,
+            start_ms,
+            run: 0,
+            next: 0,
+        }
+    }
+
+    pub fn pattern(&self) -> &HapticPattern {
+        &self.pattern
+    }
+
+    // pulses scheduled at or before now_ms that were not returned yet, in order
+    pub fn due(&mut self, now_ms: u64) -> Vec<DuePulse> {
+        let period = self.pattern.period_ms();
+        let mut due = Vec::new();
+        while !self.is_finished() {
+            let pulse = &self.pattern.pulses[self.next];
+            let scheduled_ms = self.start_ms + self.run as u64 * period + pulse.at_ms;
+            if scheduled_ms > now_ms {
+                break;
+            }
+            due.push(DuePulse {
+                scheduled_ms,
+                pulse: pulse.clone(),
+            });
+            self.next += 1;
+            if self.next == self.pattern.pulses.len() {
+                self.next = 0;
+                self.run += 1;
+            }
+        }
+        due
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.run >= self.pattern.repeat || self.pattern.pulses.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // load() goes through a file like --haptics does, one file per call as tests run in parallel
+    fn load(json: &str) -> Result<HapticPattern, Box<dyn std::error::Error>> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let n = FILES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("librevr-haptics-{}-{}.json", std::process::id(), n));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, json).unwrap();
+        let pattern = HapticPattern::load(path);
+        std::fs::remove_file(path).unwrap();
+        pattern
+    }
+
+    fn pattern(repeat: &str, period: &str) -> HapticPattern {
+        let json = format!(
+            r#"{{ "name": "two_point", {} {} "pulses": [
+                {{ "at_ms": 250, "hand": "right", "duration_ms": 60.5, "amplitude": 0.8 }},
+                {{ "at_ms": 0, "hand": "left", "duration_ms": 50, "frequency_hz": 160, "amplitude": 1.0 }}
+            ] }}"#,
+            repeat, period
+        );
+        load(&json).unwrap()
+    }
+
+    fn times(due: &[DuePulse]) -> Vec<u64> {
+        due.iter().map(|d| d.scheduled_ms).collect()
+    }
+
+    #[test]
+    fn load_sorts_pulses_and_defaults() {
+        let pattern = pattern("", "");
+        assert_eq!(pattern.repeat, 1);
+        assert_eq!(pattern.period_ms, None);
+        assert_eq!(pattern.pulses[0].hand, Hand::Left);
+        assert_eq!(pattern.pulses[0].frequency_hz, 160.0);
+        assert_eq!(pattern.pulses[1].at_ms, 250);
+        assert_eq!(pattern.pulses[1].frequency_hz, 0.0);
+        assert_eq!(pattern.pulses[1].duration(), Duration::from_micros(60_500));
+    }
+
+    #[test]
+    fn load_rejects_bad_patterns() {
+        let pulse = |amplitude: f32| {
+            format!(
+                r#"{{ "name": "bad", "pulses": [{{ "at_ms": 0, "hand": "Left", "duration_ms": 10, "amplitude": {} }}] }}"#,
+                amplitude
+            )
+        };
+        assert!(load(&pulse(0.0)).is_ok());
+        for amplitude in [1.5, -0.1] {
+            let err = load(&pulse(amplitude)).unwrap_err().to_string();
+            assert!(err.contains("outside 0-1"), "{}", err);
+        }
+        assert!(load(r#"{ "name": "empty", "pulses": [] }"#).is_err());
+        assert!(load(&pulse(0.5).replace("Left", "middle")).is_err());
+    }
+
+    #[test]
+    fn repeats_with_the_given_period() {
+        let mut player = HapticPlayer::new(pattern(r#""repeat": 3,"#, r#""period_ms": 1000,"#), 100);
+        assert!(player.due(99).is_empty());
+        assert_eq!(times(&player.due(100)), [100]);
+        assert!(player.due(349).is_empty());
+        assert_eq!(times(&player.due(350)), [350]);
+        assert_eq!(times(&player.due(1100)), [1100]);
+        assert_eq!(times(&player.due(1400)), [1350]);
+        assert!(!player.is_finished());
+        assert_eq!(times(&player.due(2350)), [2100, 2350]);
+        assert!(player.is_finished());
+        assert!(player.due(10_000).is_empty());
+    }
+
+    #[test]
+    fn default_period_ends_with_the_last_pulse() {
+        // 250 ms + 60.5 ms rounded up
+        let pattern = pattern(r#""repeat": 2,"#, "");
+        assert_eq!(pattern.period_ms(), 311);
+        let mut player = HapticPlayer::new(pattern, 0);
+        assert_eq!(times(&player.due(311)), [0, 250, 311]);
+        assert_eq!(times(&player.due(600)), [561]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn late_frame_gets_every_missed_pulse_in_order() {
+        let mut player = HapticPlayer::new(pattern(r#""repeat": 2,"#, r#""period_ms": 500,"#), 1000);
+        let due = player.due(5000);
+        assert_eq!(times(&due), [1000, 1250, 1500, 1750]);
+        let hands: Vec<Hand> = due.iter().map(|d| d.pulse.hand).collect();
+        assert_eq!(hands, [Hand::Left, Hand::Right, Hand::Left, Hand::Right]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn zero_repeats_play_nothing() {
+        let mut player = HapticPlayer::new(pattern(r#""repeat": 0,"#, ""), 0);
+        assert!(player.is_finished());
+        assert!(player.due(u64::MAX).is_empty());
+    }
+}
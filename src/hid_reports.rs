@@ -0,0 +1,743 @@
+// vive hid input reports (see docs/lighthouse.md, "hid report structure")
+// all multi byte fields are little endian, timestamps are 48 mhz ticks
+
+use std::time::Duration;
+use crate::device_config::ImuCalibration;
+
+pub const TICKS_PER_US: u64 = 48;
+
+pub const IMU_REPORT_ID: u8 = 0x20;
+pub const IMU_SAMPLES_PER_REPORT: usize = 3;
+// nominal imu rate (1 khz)
+const IMU_SAMPLE_TICKS: u64 = 1000 * TICKS_PER_US;
+const IMU_HEADER_LEN: usize = 4;
+const IMU_SAMPLE_LEN: usize = 16;
+pub const IMU_REPORT_LEN: usize = IMU_HEADER_LEN + IMU_SAMPLES_PER_REPORT * IMU_SAMPLE_LEN;
+
+// full scale of the raw i16 values
+const ACCEL_RANGE_G: f32 = 4.0;
+const GYRO_RANGE_DPS: f32 = 2000.0;
+
+pub const LIGHT_REPORT_ID: u8 = 0x21;
+const LIGHT_HEADER_LEN: usize = 5;
+const LIGHT_EVENT_LEN: usize = 5;
+// the duration byte counts microseconds (sync pulses are 60-165 us long)
+const LIGHT_DURATION_TICKS: u64 = TICKS_PER_US;
+
+pub const BUTTON_REPORT_ID: u8 = 0x24;
+pub const BUTTON_REPORT_LEN: usize = 12;
+
+// any parsed input report
+#[derive(Debug, Clone, PartialEq)]
+pub enum HidReport {
+    Imu(ImuReport),
+    Light(LightReport),
+    Buttons(ButtonReport),
+}
+
+impl HidReport {
+    // dispatch on the report id, None for unknown ids or short reports
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        match *data.first()? {
+            IMU_REPORT_ID => ImuReport::parse(data).map(HidReport::Imu),
+            LIGHT_REPORT_ID => LightReport::parse(data).map(HidReport::Light),
+            BUTTON_REPORT_ID => ButtonReport::parse(data).map(HidReport::Buttons),
+            _ => None,
+        }
+    }
+}
+
+// one accel + gyro reading as it sits in the report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawImuSample {
+    pub accel: [i16; 3],
+    pub gyro: [i16; 3],
+    pub timestamp_lo: u32,
+}
+
+// report 0x20, three samples per report
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImuReport {
+    pub timestamp_hi: u16,
+    // sequence of the first sample, the others follow it
+    pub sequence: u8,
+    pub samples: [RawImuSample; IMU_SAMPLES_PER_REPORT],
+}
+
+impl ImuReport {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < IMU_REPORT_LEN || data[0] != IMU_REPORT_ID {
+            return None;
+        }
+
+        let i16_at = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]);
+        let sample = |k: usize| {
+            let base = IMU_HEADER_LEN + k * IMU_SAMPLE_LEN;
+            RawImuSample {
+                accel: [i16_at(base), i16_at(base + 2), i16_at(base + 4)],
+                gyro: [i16_at(base + 6), i16_at(base + 8), i16_at(base + 10)],
+                timestamp_lo: u32::from_le_bytes([
+                    data[base + 12],
+                    data[base + 13],
+                    data[base + 14],
+                    data[base + 15],
+                ]),
+            }
+        };
+
+        Some(Self {
+            timestamp_hi: u16::from_le_bytes([data[1], data[2]]),
+            sequence: data[3],
+            samples: [sample(0), sample(1), sample(2)],
+        })
+    }
+
+    // inverse of parse, used by the simulator and recordings
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(IMU_REPORT_LEN);
+        out.push(IMU_REPORT_ID);
+        out.extend_from_slice(&self.timestamp_hi.to_le_bytes());
+        out.push(self.sequence);
+        for sample in &self.samples {
+            for value in sample.accel.iter().chain(&sample.gyro) {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&sample.timestamp_lo.to_le_bytes());
+        }
+        out
+    }
+}
+
+// scaled imu sample on a monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub sequence: u8,
+    // 48 mhz device clock extended to 64 bits, never goes backwards
+    pub timestamp_ticks: u64,
+    pub accel: [f32; 3], // g
+    pub gyro: [f32; 3],  // deg/s
+    // samples lost between the previous one and this one
+    pub dropped_before: u32,
+}
+
+impl ImuSample {
+    pub fn from_raw(raw: &RawImuSample, sequence: u8, timestamp_ticks: u64) -> Self {
+        Self {
+            sequence,
+            timestamp_ticks,
+            accel: raw.accel.map(|a| a as f32 / 32768.0 * ACCEL_RANGE_G),
+            gyro: raw.gyro.map(|g| g as f32 / 32768.0 * GYRO_RANGE_DPS),
+            dropped_before: 0,
+        }
+    }
+
+    pub fn timestamp_us(&self) -> u64 {
+        self.timestamp_ticks / TICKS_PER_US
+    }
+}
+
+// turns consecutive 0x20 reports into a sample stream
+// reports overlap (each carries the 3 newest samples), repeats are skipped by
+// timestamp, missing sequence numbers are counted as dropped samples
+pub struct ImuStream {
+    last_sequence: Option<u8>,
+    // hi (16 bits, per report) + lo (32 bits, per sample)
+    clock: ClockExtender,
+    // factory bias/scale from the device config, identity until set
+    calibration: ImuCalibration,
+    dropped: u64,
+    duplicates: u64,
+}
+
+impl ImuStream {
+    pub fn new() -> Self {
+        Self {
+            last_sequence: None,
+            clock: ClockExtender::new(48, 32),
+            calibration: ImuCalibration::default(),
+            dropped: 0,
+            duplicates: 0,
+        }
+    }
+
+    // keeps the calibration
+    pub fn reset(&mut self) {
+        let calibration = self.calibration;
+        *self = Self::new();
+        self.calibration = calibration;
+    }
+
+    pub fn set_calibration(&mut self, calibration: ImuCalibration) {
+        self.calibration = calibration;
+    }
+
+    // parse one raw report, returns only the samples not seen before
+    pub fn push_report(&mut self, data: &[u8]) -> Vec<ImuSample> {
+        match ImuReport::parse(data) {
+            Some(report) => self.push(&report),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, report: &ImuReport) -> Vec<ImuSample> {
+        let mut out = Vec::with_capacity(IMU_SAMPLES_PER_REPORT);
+
+        for (k, raw) in report.samples.iter().enumerate() {
+            let sequence = report.sequence.wrapping_add(k as u8);
+            let mut dropped_before = 0;
+
+            if let Some(last) = self.last_sequence {
+                // a repeat is not newer than the last sample, the 8 bit sequence
+                // alone cannot tell it from a gap of 128 samples or more
+                let elapsed = self.clock.delta(report.timestamp_hi, raw.timestamp_lo);
+                let steps = sample_steps(sequence.wrapping_sub(last), elapsed);
+                if steps == 0 {
+                    self.duplicates += 1;
+                    continue;
+                }
+                dropped_before = (steps - 1) as u32;
+                self.dropped += dropped_before as u64;
+            }
+
+            let ticks = self.clock.extend(report.timestamp_hi, raw.timestamp_lo);
+            self.last_sequence = Some(sequence);
+
+            let mut sample = ImuSample::from_raw(raw, sequence, ticks);
+            sample.accel = self.calibration.apply_accel(sample.accel);
+            sample.gyro = self.calibration.apply_gyro(sample.gyro);
+            sample.dropped_before = dropped_before;
+            out.push(sample);
+        }
+        out
+    }
+
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn duplicate_samples(&self) -> u64 {
+        self.duplicates
+    }
+
+    // low word rollovers (every ~89 s)
+    pub fn timestamp_wraps(&self) -> u64 {
+        self.clock.wraps()
+    }
+
+    // samples whose clock went backwards, they keep the previous timestamp
+    pub fn timestamp_errors(&self) -> u64 {
+        self.clock.backwards()
+    }
+}
+
+// samples from the previous one to this one: the sequence distance plus the
+// whole 256 sample laps the clock says went by (off by up to half a lap is fine)
+fn sample_steps(forward: u8, elapsed_ticks: i64) -> u64 {
+    if elapsed_ticks <= 0 {
+        return 0;
+    }
+    let forward = forward as u64;
+    let expected = (elapsed_ticks as u64 + IMU_SAMPLE_TICKS / 2) / IMU_SAMPLE_TICKS;
+    let laps = (expected.saturating_sub(forward) + 128) / 256;
+    forward + 256 * laps
+}
+
+// report 0x21, variable number of photodiode events
+//
+//   0      report id
+//   1-2    timestamp hi (bits 24-39 of the clock)
+//   3      event count
+//   4      flags
+//   5..    events, 5 bytes each:
+//            sensor (bits 7-3), timestamp mid, timestamp lo (u16), duration (us)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightReport {
+    pub timestamp_hi: u16,
+    pub flags: u8,
+    pub events: Vec<RawLightEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawLightEvent {
+    pub sensor_id: u8,
+    // 24 bit, 48 mhz
+    pub timestamp: u32,
+    pub duration: u8,
+}
+
+impl LightReport {
+    // events that do not fit in the buffer are dropped, a count larger than
+    // the report is not an error (hidraw reports are a fixed 64 bytes)
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < LIGHT_HEADER_LEN || data[0] != LIGHT_REPORT_ID {
+            return None;
+        }
+
+        let count = data[3] as usize;
+        let events = data[LIGHT_HEADER_LEN..]
+            .chunks_exact(LIGHT_EVENT_LEN)
+            .take(count)
+            .map(|e| RawLightEvent {
+                sensor_id: e[0] >> 3,
+                timestamp: (e[1] as u32) << 16 | u16::from_le_bytes([e[2], e[3]]) as u32,
+                duration: e[4],
+            })
+            .collect();
+
+        Some(Self {
+            timestamp_hi: u16::from_le_bytes([data[1], data[2]]),
+            flags: data[4],
+            events,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(LIGHT_HEADER_LEN + self.events.len() * LIGHT_EVENT_LEN);
+        out.push(LIGHT_REPORT_ID);
+        out.extend_from_slice(&self.timestamp_hi.to_le_bytes());
+        out.push(self.events.len() as u8);
+        out.push(self.flags);
+        for event in &self.events {
+            out.push(event.sensor_id << 3);
+            out.push((event.timestamp >> 16) as u8);
+            out.extend_from_slice(&(event.timestamp as u16).to_le_bytes());
+            out.push(event.duration);
+        }
+        out
+    }
+}
+
+// photodiode pulse on the monotonic device clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LightEvent {
+    pub sensor_id: u8,
+    pub timestamp_ticks: u64,
+    pub duration_ticks: u64,
+}
+
+impl LightEvent {
+    pub fn start(&self) -> Duration {
+        ticks_to_duration(self.timestamp_ticks)
+    }
+
+    pub fn duration(&self) -> Duration {
+        ticks_to_duration(self.duration_ticks)
+    }
+}
+
+// turns 0x21 reports into time ordered light events
+pub struct LightStream {
+    // hi (16 bits, per report) + 24 bit event timestamp
+    clock: ClockExtender,
+    events: u64,
+}
+
+impl LightStream {
+    pub fn new() -> Self {
+        Self {
+            clock: ClockExtender::new(40, 24),
+            events: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn push_report(&mut self, data: &[u8]) -> Vec<LightEvent> {
+        match LightReport::parse(data) {
+            Some(report) => self.push(&report),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, report: &LightReport) -> Vec<LightEvent> {
+        // the fpga batches events per sensor, not strictly in time order
+        // sort by signed 24 bit distance to the first event so a wrap inside the report is kept in order
+        let first = report.events.first().map(|e| e.timestamp).unwrap_or(0);
+        let mut raw = report.events.clone();
+        raw.sort_by_key(|e| ((e.timestamp.wrapping_sub(first) << 8) as i32) >> 8);
+
+        self.events += raw.len() as u64;
+        raw.iter()
+            .map(|e| LightEvent {
+                sensor_id: e.sensor_id,
+                timestamp_ticks: self.clock.extend(report.timestamp_hi, e.timestamp),
+                duration_ticks: e.duration as u64 * LIGHT_DURATION_TICKS,
+            })
+            .collect()
+    }
+
+    pub fn event_count(&self) -> u64 {
+        self.events
+    }
+
+    pub fn timestamp_wraps(&self) -> u64 {
+        self.clock.wraps()
+    }
+
+    pub fn timestamp_errors(&self) -> u64 {
+        self.clock.backwards()
+    }
+}
+
+// report 0x24, controller buttons
+//
+//   0      report id
+//   1      data size
+//   2      button bitmap (see controller_input::Button)
+//   3      trigger, 0-255
+//   4-5    trackpad x, i16
+//   6-7    trackpad y, i16
+//   8-11   reserved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonReport {
+    pub data_size: u8,
+    pub buttons: u8,
+    pub trigger: u8,
+    pub trackpad: [i16; 2],
+}
+
+impl ButtonReport {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < BUTTON_REPORT_LEN || data[0] != BUTTON_REPORT_ID {
+            return None;
+        }
+
+        Some(Self {
+            data_size: data[1],
+            buttons: data[2],
+            trigger: data[3],
+            trackpad: [
+                i16::from_le_bytes([data[4], data[5]]),
+                i16::from_le_bytes([data[6], data[7]]),
+            ],
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut out = vec![BUTTON_REPORT_ID, self.data_size, self.buttons, self.trigger];
+        out.extend_from_slice(&self.trackpad[0].to_le_bytes());
+        out.extend_from_slice(&self.trackpad[1].to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * 1000 / TICKS_PER_US)
+}
+
+// extends a clock that is latched as hi (once per report) + lo (per sample)
+// into a monotonic 64 bit tick count. a lo that wrapped before or after hi was
+// latched is off by exactly one lo period and gets moved back next to the last value
+pub struct ClockExtender {
+    bits: u32,
+    lo_bits: u32,
+    started: bool,
+    last_raw: u64,
+    last_ticks: u64,
+    wraps: u64,
+    backwards: u64,
+}
+
+impl ClockExtender {
+    pub fn new(bits: u32, lo_bits: u32) -> Self {
+        Self {
+            bits,
+            lo_bits,
+            started: false,
+            last_raw: 0,
+            last_ticks: 0,
+            wraps: 0,
+            backwards: 0,
+        }
+    }
+
+    pub fn extend(&mut self, hi: u16, lo: u32) -> u64 {
+        let raw = self.raw(hi, lo);
+        if !self.started {
+            self.started = true;
+            self.last_raw = raw;
+            self.last_ticks = raw;
+            return raw;
+        }
+
+        let delta = self.signed_delta(raw);
+        if delta < 0 {
+            self.backwards += 1;
+            return self.last_ticks;
+        }
+
+        if raw >> self.lo_bits != self.last_raw >> self.lo_bits {
+            self.wraps += 1;
+        }
+        self.last_raw = raw;
+        self.last_ticks += delta as u64;
+        self.last_ticks
+    }
+
+    // ticks from the last value to this one without taking it, 0 before the first
+    pub fn delta(&self, hi: u16, lo: u32) -> i64 {
+        if !self.started {
+            return 0;
+        }
+        self.signed_delta(self.raw(hi, lo))
+    }
+
+    fn raw(&self, hi: u16, lo: u32) -> u64 {
+        let mask = (1u64 << self.bits) - 1;
+        let lo_period = 1u64 << self.lo_bits;
+        let lo = lo as u64 & (lo_period - 1);
+        let raw = (((hi as u64) << self.lo_bits) | lo) & mask;
+        if !self.started {
+            return raw;
+        }
+
+        // a lo near the top of its range may belong to the previous hi,
+        // one near zero to the next
+        let guard = lo_period / 8;
+        let delta = self.signed_delta(raw);
+        if lo >= lo_period - guard && delta > (lo_period / 2) as i64 {
+            raw.wrapping_sub(lo_period) & mask
+        } else if lo < guard && delta < -((lo_period / 2) as i64) {
+            (raw + lo_period) & mask
+        } else {
+            raw
+        }
+    }
+
+    // distance from the last value on the (wrapping) full clock
+    fn signed_delta(&self, raw: u64) -> i64 {
+        let mask = (1u64 << self.bits) - 1;
+        let delta = (raw.wrapping_sub(self.last_raw) & mask) as i64;
+        if delta >= 1 << (self.bits - 1) {
+            delta - (1 << self.bits)
+        } else {
+            delta
+        }
+    }
+
+    pub fn wraps(&self) -> u64 {
+        self.wraps
+    }
+
+    pub fn backwards(&self) -> u64 {
+        self.backwards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x20, hi 0x1234, sequence 7, samples 7-9
+    const IMU_GOLDEN: [u8; IMU_REPORT_LEN] = [
+        0x20, 0x34, 0x12, 0x07,
+        // accel 0x4000, -1, 0 / gyro 0x4000, -0x4000, 1 / lo 0x89abcdef
+        0x00, 0x40, 0xff, 0xff, 0x00, 0x00, 0x00, 0x40, 0x00, 0xc0, 0x01, 0x00, 0xef, 0xcd, 0xab, 0x89,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x6f, 0xce, 0xab, 0x89,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xce, 0xab, 0x89,
+    ];
+
+    // 0x21, hi 0x0102, two events (listed out of order), flags 0x80
+    const LIGHT_GOLDEN: [u8; 15] = [
+        0x21, 0x02, 0x01, 0x02, 0x80,
+        // sensor 31, 0x123500, 12 us
+        0xf8, 0x12, 0x00, 0x35, 0x0c,
+        // sensor 5, 0x123456, 80 us
+        0x28, 0x12, 0x56, 0x34, 0x50,
+    ];
+
+    // 0x24, buttons 0x05, trigger 255, trackpad (-32768, 32767)
+    const BUTTON_GOLDEN: [u8; BUTTON_REPORT_LEN] = [
+        0x24, 0x0a, 0x05, 0xff, 0x00, 0x80, 0xff, 0x7f, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn imu_report(sequence: u8, timestamp_hi: u16, timestamp_lo: u32) -> ImuReport {
+        let sample = |k: u32| RawImuSample {
+            accel: [0, 0, 8192],
+            gyro: [0; 3],
+            timestamp_lo: timestamp_lo.wrapping_add(k * 48_000),
+        };
+        ImuReport {
+            timestamp_hi,
+            sequence,
+            samples: [sample(0), sample(1), sample(2)],
+        }
+    }
+
+    #[test]
+    fn imu_golden_bytes() {
+        let Some(HidReport::Imu(report)) = HidReport::parse(&IMU_GOLDEN) else {
+            panic!("not an imu report");
+        };
+        assert_eq!(report.timestamp_hi, 0x1234);
+        assert_eq!(report.sequence, 7);
+        assert_eq!(
+            report.samples[0],
+            RawImuSample {
+                accel: [0x4000, -1, 0],
+                gyro: [0x4000, -0x4000, 1],
+                timestamp_lo: 0x89ab_cdef,
+            }
+        );
+        assert_eq!(report.to_bytes(), IMU_GOLDEN);
+
+        let samples = ImuStream::new().push(&report);
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].sequence, 7);
+        assert_eq!(samples[0].timestamp_ticks, 0x1234_89ab_cdef);
+        assert_eq!(samples[0].accel, [2.0, -ACCEL_RANGE_G / 32768.0, 0.0]);
+        assert_eq!(samples[0].gyro, [1000.0, -1000.0, GYRO_RANGE_DPS / 32768.0]);
+        assert_eq!(samples[1].timestamp_ticks - samples[0].timestamp_ticks, 0x80);
+        assert_eq!(samples[2].sequence, 9);
+    }
+
+    #[test]
+    fn light_golden_bytes() {
+        let Some(HidReport::Light(report)) = HidReport::parse(&LIGHT_GOLDEN) else {
+            panic!("not a light report");
+        };
+        assert_eq!(report.timestamp_hi, 0x0102);
+        assert_eq!(report.flags, 0x80);
+        assert_eq!(
+            report.events,
+            vec![
+                RawLightEvent { sensor_id: 31, timestamp: 0x12_3500, duration: 12 },
+                RawLightEvent { sensor_id: 5, timestamp: 0x12_3456, duration: 80 },
+            ]
+        );
+        assert_eq!(report.to_bytes(), LIGHT_GOLDEN);
+
+        // padding after the counted events is ignored
+        let mut padded = LIGHT_GOLDEN.to_vec();
+        padded.resize(64, 0);
+        assert_eq!(LightReport::parse(&padded), Some(report.clone()));
+
+        let events = LightStream::new().push(&report);
+        assert_eq!(
+            events,
+            vec![
+                LightEvent { sensor_id: 5, timestamp_ticks: 0x01_0212_3456, duration_ticks: 80 * 48 },
+                LightEvent { sensor_id: 31, timestamp_ticks: 0x01_0212_3500, duration_ticks: 12 * 48 },
+            ]
+        );
+        assert_eq!(events[0].duration(), Duration::from_micros(80));
+    }
+
+    #[test]
+    fn button_golden_bytes() {
+        let Some(HidReport::Buttons(report)) = HidReport::parse(&BUTTON_GOLDEN) else {
+            panic!("not a button report");
+        };
+        assert_eq!(
+            report,
+            ButtonReport {
+                data_size: 0x0a,
+                buttons: 0x05,
+                trigger: 255,
+                trackpad: [i16::MIN, i16::MAX],
+            }
+        );
+        assert_eq!(report.to_bytes(), BUTTON_GOLDEN);
+        assert_eq!(HidReport::parse(&BUTTON_GOLDEN[..BUTTON_REPORT_LEN - 1]), None);
+    }
+
+    #[test]
+    fn repeated_imu_samples_are_skipped() {
+        let mut stream = ImuStream::new();
+        let first = stream.push(&imu_report(10, 0, 1_000_000));
+        assert_eq!(first.iter().map(|s| s.sequence).collect::<Vec<_>>(), [10, 11, 12]);
+
+        // the next report repeats 11 and 12
+        let second = stream.push(&imu_report(11, 0, 1_048_000));
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].sequence, 13);
+        assert_eq!(second[0].timestamp_ticks, first[2].timestamp_ticks + 48_000);
+        assert_eq!(second[0].dropped_before, 0);
+        assert_eq!(stream.duplicate_samples(), 2);
+
+        // 14 never arrived
+        let third = stream.push(&imu_report(15, 0, 1_240_000));
+        assert_eq!(third[0].sequence, 15);
+        assert_eq!(third[0].dropped_before, 1);
+        assert_eq!(stream.dropped_samples(), 1);
+
+        // the 8 bit sequence wraps
+        let mut stream = ImuStream::new();
+        stream.push(&imu_report(254, 0, 0));
+        let wrapped = stream.push(&imu_report(255, 0, 48_000));
+        assert_eq!(wrapped.iter().map(|s| s.sequence).collect::<Vec<_>>(), [1]);
+        assert_eq!(stream.duplicate_samples(), 2);
+    }
+
+    #[test]
+    fn long_imu_gaps_are_not_repeats() {
+        let mut stream = ImuStream::new();
+        stream.push(&imu_report(10, 0, 0));
+
+        // 200 samples lost after 12, the sequence distance reads as -55
+        let after = stream.push(&imu_report(213, 0, 201 * 48_000));
+        assert_eq!(after.len(), 3);
+        assert_eq!(after[0].sequence, 213);
+        assert_eq!(after[0].dropped_before, 200);
+        assert_eq!(stream.duplicate_samples(), 0);
+
+        // 300 lost after 215 at 203 ms: the sequence went past 215 once more
+        let after = stream.push(&imu_report(4, 0, (203 + 301) * 48_000));
+        assert_eq!(after[0].sequence, 4);
+        assert_eq!(after[0].dropped_before, 300);
+        assert_eq!(stream.dropped_samples(), 500);
+
+        // repeats after the gap are still skipped
+        let again = stream.push(&imu_report(5, 0, (203 + 302) * 48_000));
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].dropped_before, 0);
+        assert_eq!(stream.duplicate_samples(), 2);
+        assert_eq!(stream.timestamp_errors(), 0);
+    }
+
+    #[test]
+    fn clock_wraps_at_48_bits() {
+        let mut clock = ClockExtender::new(48, 32);
+        let start = clock.extend(0xffff, 0xffff_ff00);
+        assert_eq!(start, 0xffff_ffff_ff00);
+
+        // lo and then hi roll over, the extended clock keeps counting
+        let ticks = clock.extend(0x0000, 0x0000_0100);
+        assert_eq!(ticks - start, 0x200);
+        assert_eq!(clock.wraps(), 1);
+
+        let later = clock.extend(0x0000, 0x0000_0300);
+        assert_eq!(later - start, 0x400);
+
+        // lo wrapped after hi was latched: hi still reads the old value
+        let mut clock = ClockExtender::new(48, 32);
+        clock.extend(0x0005, 0xffff_fff0);
+        assert_eq!(clock.extend(0x0005, 0x0000_0010), 0x0006_0000_0010);
+
+        // lo from before the rollover read together with the new hi
+        let mut clock = ClockExtender::new(48, 32);
+        clock.extend(0x0005, 0xffff_ff00);
+        assert_eq!(clock.extend(0x0006, 0xffff_ff80), 0x0005_ffff_ff80);
+
+        // a sample going backwards keeps the previous timestamp
+        assert_eq!(clock.extend(0x0005, 0xffff_fe00), 0x0005_ffff_ff80);
+        assert_eq!(clock.backwards(), 1);
+    }
+
+    #[test]
+    fn light_clock_wraps_at_40_bits() {
+        let mut stream = LightStream::new();
+        let report = |timestamp_hi, timestamp| LightReport {
+            timestamp_hi,
+            flags: 0,
+            events: vec![RawLightEvent { sensor_id: 0, timestamp, duration: 10 }],
+        };
+        let before = stream.push(&report(0xffff, 0xff_ff00))[0].timestamp_ticks;
+        let after = stream.push(&report(0x0000, 0x00_0100))[0].timestamp_ticks;
+        assert_eq!(after - before, 0x200);
+        assert_eq!(stream.timestamp_wraps(), 1);
+    }
+}
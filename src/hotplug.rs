@@ -0,0 +1,379 @@
+// headset connect / disconnect through the kernel uevent netlink socket
+// the kernel broadcasts one message per device on multicast group 1:
+//   add@/devices/pci0000:00/.../0003:28DE:2000.0005/hidraw/hidraw3\0ACTION=add\0
+//   DEVPATH=...\0SUBSYSTEM=hidraw\0MAJOR=240\0MINOR=3\0DEVNAME=hidraw3\0SEQNUM=4711\0
+// usb devices carry PRODUCT=bb4/342/100 (vid/pid/bcdDevice in hex), hidraw nodes only
+// have the vid/pid in the parent hid device name (bus:vid:pid.instance) of DEVPATH.
+// we listen to the kernel directly so this works without udevd (containers, initramfs)
+
+use std::time::Duration;
+use crate::kernel_api::{DeviceInfo, VIVE_VENDOR_IDS};
+
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+
+// kernel uevents, udevd re-broadcasts on group 2 with its own header
+const UEVENT_GROUP_KERNEL: u32 = 1;
+const UEVENT_BUFFER_LEN: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugAction {
+    Added,
+    Removed,
+}
+
+// one parsed uevent, keys as the kernel sends them
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Uevent {
+    pub action: String,
+    pub devpath: String,
+    pub subsystem: String,
+    pub devname: Option<String>,
+    pub devtype: Option<String>,
+    pub product: Option<String>,
+}
+
+// "add@/devices/..." header followed by nul separated KEY=VALUE pairs
+pub fn parse_uevent(message: &[u8]) -> Option<Uevent> {
+    let mut fields = message.split(|&b| b == 0).filter(|f| !f.is_empty());
+    let header = std::str::from_utf8(fields.next()?).ok()?;
+    // udevd messages start with "libudev\0", not action@devpath
+    let (header_action, header_path) = header.split_once('@')?;
+
+    let mut event = Uevent {
+        action: header_action.to_string(),
+        devpath: header_path.to_string(),
+        ..Uevent::default()
+    };
+    for field in fields {
+        let Some((key, value)) = std::str::from_utf8(field).ok().and_then(|f| f.split_once('=')) else {
+            continue;
+        };
+        match key {
+            "ACTION" => event.action = value.to_string(),
+            "DEVPATH" => event.devpath = value.to_string(),
+            "SUBSYSTEM" => event.subsystem = value.to_string(),
+            "DEVNAME" => event.devname = Some(value.to_string()),
+            "DEVTYPE" => event.devtype = Some(value.to_string()),
+            "PRODUCT" => event.product = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Some(event)
+}
+
+impl Uevent {
+    // vid/pid of hidraw nodes and usb devices, None for everything else
+    // (usb interfaces also carry PRODUCT but would report every device twice)
+    pub fn ids(&self) -> Option<(u16, u16)> {
+        match self.subsystem.as_str() {
+            "hidraw" => self.devpath.split('/').rev().find_map(parse_hid_name),
+            "usb" if self.devtype.as_deref() == Some("usb_device") => {
+                let mut parts = self.product.as_deref()?.split('/');
+                let vendor = u16::from_str_radix(parts.next()?, 16).ok()?;
+                let product = u16::from_str_radix(parts.next()?, 16).ok()?;
+                Some((vendor, product))
+            }
+            _ => None,
+        }
+    }
+
+    // /dev node of the device (DEVNAME is relative to /dev)
+    pub fn device_path(&self) -> Option<String> {
+        self.devname.as_ref().map(|name| format!("/dev/{}", name))
+    }
+}
+
+// "0003:000028DE:00002000.0005" or "0003:28DE:2000.0005"
+fn parse_hid_name(name: &str) -> Option<(u16, u16)> {
+    let (ids, _instance) = name.split_once('.')?;
+    let mut parts = ids.split(':');
+    let _bus = parts.next()?;
+    let vendor = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let product = u32::from_str_radix(parts.next()?, 16).ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((vendor as u16, product as u16))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotplugEvent {
+    pub action: HotplugAction,
+    // "hidraw" or "usb"
+    pub subsystem: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub devpath: String,
+    pub device_path: Option<String>,
+}
+
+impl HotplugEvent {
+    // same node as an open device; on removal sysfs is already gone, so the
+    // /dev path is the only thing left to compare
+    pub fn is_device(&self, info: &DeviceInfo) -> bool {
+        self.vendor_id == info.vendor_id
+            && self.product_id == info.product_id
+            && self.device_path.as_deref() == Some(info.device_path.as_str())
+    }
+}
+
+// vid/pid pairs to report, a None pid matches every product of the vendor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotplugFilter {
+    devices: Vec<(u16, Option<u16>)>,
+}
+
+impl HotplugFilter {
+    pub fn vive() -> Self {
+        Self {
+            devices: VIVE_VENDOR_IDS.iter().map(|&vid| (vid, None)).collect(),
+        }
+    }
+
+    pub fn with_devices(devices: &[(u16, Option<u16>)]) -> Self {
+        Self {
+            devices: devices.to_vec(),
+        }
+    }
+
+    pub fn matches(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.devices
+            .iter()
+            .any(|&(vid, pid)| vid == vendor_id && pid.is_none_or(|pid| pid == product_id))
+    }
+
+    // add / remove of a matching hidraw node or usb device
+    pub fn event(&self, uevent: &Uevent) -> Option<HotplugEvent> {
+        let action = match uevent.action.as_str() {
+            "add" => HotplugAction::Added,
+            "remove" => HotplugAction::Removed,
+            _ => return None,
+        };
+        let (vendor_id, product_id) = uevent.ids()?;
+        if !self.matches(vendor_id, product_id) {
+            return None;
+        }
+
+        Some(HotplugEvent {
+            action,
+            subsystem: uevent.subsystem.clone(),
+            vendor_id,
+            product_id,
+            devpath: uevent.devpath.clone(),
+            device_path: uevent.device_path(),
+        })
+    }
+
+    pub fn parse(&self, message: &[u8]) -> Option<HotplugEvent> {
+        self.event(&parse_uevent(message)?)
+    }
+}
+
+// NETLINK_KOBJECT_UEVENT listener
+#[cfg(target_os = "linux")]
+pub struct HotplugMonitor {
+    socket: OwnedFd,
+    filter: HotplugFilter,
+    // the kernel dropped events since the last take_overflow()
+    overflowed: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl HotplugMonitor {
+    pub fn new(filter: HotplugFilter) -> Result<Self, Box<dyn std::error::Error>> {
+        use nix::sys::socket::{bind, socket, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType};
+
+        let socket = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            SockProtocol::NetlinkKObjectUEvent,
+        )?;
+        bind(socket.as_raw_fd(), &NetlinkAddr::new(0, UEVENT_GROUP_KERNEL))?;
+        Ok(Self {
+            socket,
+            filter,
+            overflowed: false,
+        })
+    }
+
+    pub fn filter(&self) -> &HotplugFilter {
+        &self.filter
+    }
+
+    // true once after events were lost, the caller has to rescan sysfs
+    // (KernelApi::reopen) instead of waiting for an add that never comes
+    pub fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
+
+    // matching events that arrive within timeout (empty when nothing happened)
+    pub fn poll(&mut self, timeout: Duration) -> Result<Vec<HotplugEvent>, Box<dyn std::error::Error>> {
+        use nix::errno::Errno;
+        use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+        use nix::sys::socket::{recvfrom, NetlinkAddr};
+
+        let mut fds = [PollFd::new(self.socket.as_fd(), PollFlags::POLLIN)];
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        match poll(&mut fds, timeout) {
+            Ok(0) | Err(Errno::EINTR) => return Ok(Vec::new()),
+            Ok(_) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let mut events = Vec::new();
+        let mut buffer = vec![0u8; UEVENT_BUFFER_LEN];
+        loop {
+            match recvfrom::<NetlinkAddr>(self.socket.as_raw_fd(), &mut buffer) {
+                Ok((len, sender)) => {
+                    // only trust messages from the kernel itself (port id 0)
+                    if sender.is_some_and(|addr| addr.pid() != 0) {
+                        continue;
+                    }
+                    events.extend(self.filter.parse(&buffer[..len]));
+                }
+                Err(Errno::EAGAIN) => break,
+                // the socket buffer overflowed during an event storm, see take_overflow()
+                Err(Errno::ENOBUFS) => {
+                    println!("hotplug: uevent buffer overflow, some events were lost");
+                    self.overflowed = true;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct HotplugMonitor {
+    filter: HotplugFilter,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl HotplugMonitor {
+    pub fn new(_filter: HotplugFilter) -> Result<Self, Box<dyn std::error::Error>> {
+        Err("hotplug monitoring is only available on linux".into())
+    }
+
+    pub fn filter(&self) -> &HotplugFilter {
+        &self.filter
+    }
+
+    pub fn take_overflow(&mut self) -> bool {
+        false
+    }
+
+    pub fn poll(&mut self, _timeout: Duration) -> Result<Vec<HotplugEvent>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(header: &str, fields: &[&str]) -> Vec<u8> {
+        let mut message = header.as_bytes().to_vec();
+        for field in fields {
+            message.push(0);
+            message.extend_from_slice(field.as_bytes());
+        }
+        message.push(0);
+        message
+    }
+
+    #[test]
+    fn hidraw_add() {
+        let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0/0003:28DE:2000.0005/hidraw/hidraw3";
+        let message = message(
+            &format!("add@{}", devpath),
+            &[
+                "ACTION=add",
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=hidraw",
+                "MAJOR=240",
+                "MINOR=3",
+                "DEVNAME=hidraw3",
+                "SEQNUM=4711",
+            ],
+        );
+
+        let event = HotplugFilter::vive().parse(&message).unwrap();
+        assert_eq!(event.action, HotplugAction::Added);
+        assert_eq!(event.subsystem, "hidraw");
+        assert_eq!((event.vendor_id, event.product_id), (0x28de, 0x2000));
+        assert_eq!(event.devpath, devpath);
+        assert_eq!(event.device_path.as_deref(), Some("/dev/hidraw3"));
+    }
+
+    #[test]
+    fn usb_device_remove() {
+        let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-2";
+        let message = message(
+            &format!("remove@{}", devpath),
+            &[
+                "ACTION=remove",
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=usb",
+                "DEVNAME=bus/usb/001/007",
+                "DEVTYPE=usb_device",
+                "PRODUCT=bb4/342/100",
+                "SEQNUM=4712",
+            ],
+        );
+
+        let event = HotplugFilter::vive().parse(&message).unwrap();
+        assert_eq!(event.action, HotplugAction::Removed);
+        assert_eq!(event.subsystem, "usb");
+        assert_eq!((event.vendor_id, event.product_id), (0x0bb4, 0x0342));
+        assert_eq!(event.device_path.as_deref(), Some("/dev/bus/usb/001/007"));
+    }
+
+    #[test]
+    fn usb_interface_is_ignored() {
+        let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2:1.0";
+        let message = message(
+            &format!("add@{}", devpath),
+            &[
+                "ACTION=add",
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=usb",
+                "DEVTYPE=usb_interface",
+                "PRODUCT=bb4/342/100",
+                "INTERFACE=3/0/0",
+            ],
+        );
+
+        assert!(parse_uevent(&message).is_some());
+        assert_eq!(HotplugFilter::vive().parse(&message), None);
+    }
+
+    #[test]
+    fn libudev_header_is_rejected() {
+        let message = message("libudev", &["ACTION=add", "SUBSYSTEM=hidraw", "DEVNAME=hidraw3"]);
+
+        assert_eq!(parse_uevent(&message), None);
+        assert_eq!(HotplugFilter::vive().parse(&message), None);
+    }
+
+    #[test]
+    fn other_vendors_are_filtered() {
+        let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-4/1-4:1.0/0003:046D:C52B.0007/hidraw/hidraw5";
+        let message = message(
+            &format!("add@{}", devpath),
+            &[
+                "ACTION=add",
+                &format!("DEVPATH={}", devpath),
+                "SUBSYSTEM=hidraw",
+                "DEVNAME=hidraw5",
+            ],
+        );
+
+        assert_eq!(parse_uevent(&message).unwrap().ids(), Some((0x046d, 0xc52b)));
+        assert_eq!(HotplugFilter::vive().parse(&message), None);
+        assert!(HotplugFilter::with_devices(&[(0x046d, Some(0xc52b))]).parse(&message).is_some());
+    }
+}
@@ -0,0 +1,310 @@
+// orientation from the raw imu, no runtime needed (standalone 3dof)
+// consumes hid_reports::ImuSample at the imu rate (1 khz) and integrates the gyro,
+// the accelerometer pulls the tilt back towards gravity and the same error
+// drives the gyro bias estimate. yaw is not observable without a magnetometer,
+// it drifts with the remaining bias until the lighthouse fusion corrects it
+//
+// world frame is the openxr one: +y up, yaw 0 at startup. orientation maps imu
+// axes to world axes
+
+use nalgebra::{UnitQuaternion, Vector3};
+use crate::hid_reports::{ImuSample, TICKS_PER_US};
+
+// samples averaged for the initial gravity alignment (0.1 s)
+const ALIGN_SAMPLES: u32 = 100;
+// gyro spread below which the headset counts as still during alignment (rad/s)
+const ALIGN_STILL_GYRO: f32 = 0.05;
+// accel corrections are skipped while the norm is this far from 1 g (moving)
+const ACCEL_REJECT_G: f32 = 0.15;
+// longer gaps (usb stall, reconnect) are not integrated
+const MAX_DT_S: f32 = 0.05;
+// first second after alignment uses this many times the normal gains
+const STARTUP_GAIN_SCALE: f32 = 10.0;
+const STARTUP_TIME_S: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterGains {
+    // proportional / integral feedback on the gravity error
+    Mahony { kp: f32, ki: f32 },
+    // gradient step (rad/s) and gyro bias drift rate
+    Madgwick { beta: f32, zeta: f32 },
+}
+
+impl FilterGains {
+    pub fn mahony() -> Self {
+        FilterGains::Mahony { kp: 2.0, ki: 0.2 }
+    }
+
+    pub fn madgwick() -> Self {
+        FilterGains::Madgwick { beta: 0.05, zeta: 0.005 }
+    }
+}
+
+pub struct ImuFilter {
+    gains: FilterGains,
+    orientation: UnitQuaternion<f32>,
+    // rad/s, subtracted from the gyro
+    gyro_bias: Vector3<f32>,
+    // bias corrected gyro of the last sample, imu frame (rad/s)
+    angular_velocity: Vector3<f32>,
+    last_ticks: Option<u64>,
+    last_timestamp_us: u64,
+    aligned: bool,
+    align_accel: Vector3<f32>,
+    align_gyro: Vector3<f32>,
+    align_gyro_max: f32,
+    align_count: u32,
+    time_since_align_s: f32,
+    accel_rejected: u64,
+    gaps: u64,
+}
+
+impl ImuFilter {
+    pub fn new(gains: FilterGains) -> Self {
+        Self {
+            gains,
+            orientation: UnitQuaternion::identity(),
+            gyro_bias: Vector3::zeros(),
+            angular_velocity: Vector3::zeros(),
+            last_ticks: None,
+            last_timestamp_us: 0,
+            aligned: false,
+            align_accel: Vector3::zeros(),
+            align_gyro: Vector3::zeros(),
+            align_gyro_max: 0.0,
+            align_count: 0,
+            time_since_align_s: 0.0,
+            accel_rejected: 0,
+            gaps: 0,
+        }
+    }
+
+    // start over with a new gravity alignment
+    pub fn reset(&mut self) {
+        *self = Self::new(self.gains);
+    }
+
+    // after a device reconnect the clock restarts, orientation and bias stay
+    pub fn reset_clock(&mut self) {
+        self.last_ticks = None;
+    }
+
+    // returns the orientation once aligned, None while still collecting the gravity average
+    pub fn update(&mut self, sample: &ImuSample) -> Option<UnitQuaternion<f32>> {
+        let accel = Vector3::from(sample.accel);
+        let gyro = Vector3::from(sample.gyro).map(f32::to_radians);
+
+        let dt = match self.last_ticks {
+            Some(last) => sample.timestamp_ticks.saturating_sub(last) as f32 / (TICKS_PER_US as f32 * 1e6),
+            None => 0.0,
+        };
+        self.last_ticks = Some(sample.timestamp_ticks);
+        self.last_timestamp_us = sample.timestamp_us();
+
+        if !self.aligned {
+            self.accumulate_alignment(accel, gyro);
+            return self.aligned.then_some(self.orientation);
+        }
+
+        self.angular_velocity = gyro - self.gyro_bias;
+        if dt <= 0.0 || dt > MAX_DT_S {
+            if dt > MAX_DT_S {
+                self.gaps += 1;
+            }
+            return Some(self.orientation);
+        }
+
+        let correction = self.gravity_correction(accel, dt);
+        self.time_since_align_s += dt;
+
+        let rate = self.angular_velocity + correction;
+        self.orientation *= UnitQuaternion::from_scaled_axis(rate * dt);
+        self.orientation.renormalize();
+        Some(self.orientation)
+    }
+
+    pub fn update_all(&mut self, samples: &[ImuSample]) -> Option<UnitQuaternion<f32>> {
+        samples.iter().fold(None, |_, sample| self.update(sample))
+    }
+
+    // correction rate (rad/s, imu frame) that turns the predicted gravity
+    // towards the measured one, also updates the bias
+    fn gravity_correction(&mut self, accel: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let norm = accel.norm();
+        if (norm - 1.0).abs() > ACCEL_REJECT_G {
+            self.accel_rejected += 1;
+            return Vector3::zeros();
+        }
+
+        // at rest the accelerometer reads +1 g along world up
+        let predicted = self.orientation.inverse_transform_vector(&Vector3::y());
+        let error = (accel / norm).cross(&predicted);
+        let scale = if self.time_since_align_s < STARTUP_TIME_S { STARTUP_GAIN_SCALE } else { 1.0 };
+
+        match self.gains {
+            FilterGains::Mahony { kp, ki } => {
+                self.gyro_bias -= error * ki * dt;
+                error * kp * scale
+            }
+            FilterGains::Madgwick { beta, zeta } => {
+                let magnitude = error.norm();
+                if magnitude < 1e-6 {
+                    return Vector3::zeros();
+                }
+                let direction = error / magnitude;
+                self.gyro_bias -= direction * zeta * dt;
+                // the normalised gradient step is a rotation of 2 * beta rad/s
+                direction * (2.0 * beta * scale)
+            }
+        }
+    }
+
+    // tilt from the averaged accel, yaw zero; the gyro mean becomes the initial
+    // bias when the headset was still the whole time
+    fn accumulate_alignment(&mut self, accel: Vector3<f32>, gyro: Vector3<f32>) {
+        self.align_accel += accel;
+        self.align_gyro += gyro;
+        self.align_gyro_max = self.align_gyro_max.max(gyro.norm());
+        self.align_count += 1;
+        if self.align_count < ALIGN_SAMPLES {
+            return;
+        }
+
+        let up = self.align_accel / self.align_count as f32;
+        if up.norm() < 1e-3 {
+            // no usable gravity, try again
+            self.align_accel = Vector3::zeros();
+            self.align_gyro = Vector3::zeros();
+            self.align_gyro_max = 0.0;
+            self.align_count = 0;
+            return;
+        }
+
+        self.orientation = UnitQuaternion::rotation_between(&up, &Vector3::y())
+            .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI));
+        if self.align_gyro_max < ALIGN_STILL_GYRO {
+            self.gyro_bias = self.align_gyro / self.align_count as f32;
+        }
+        self.aligned = true;
+        self.time_since_align_s = 0.0;
+    }
+
+    pub fn orientation(&self) -> Option<UnitQuaternion<f32>> {
+        self.aligned.then_some(self.orientation)
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.aligned
+    }
+
+    // imu frame, rad/s
+    pub fn angular_velocity(&self) -> Vector3<f32> {
+        self.angular_velocity
+    }
+
+    // world frame, rad/s
+    pub fn world_angular_velocity(&self) -> Vector3<f32> {
+        self.orientation.transform_vector(&self.angular_velocity)
+    }
+
+    pub fn gyro_bias(&self) -> Vector3<f32> {
+        self.gyro_bias
+    }
+
+    // device clock of the last sample
+    pub fn timestamp_us(&self) -> u64 {
+        self.last_timestamp_us
+    }
+
+    // samples where the accel was ignored because the headset was accelerating
+    pub fn accel_rejected(&self) -> u64 {
+        self.accel_rejected
+    }
+
+    // sample gaps longer than MAX_DT_S
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_US: u64 = 1000;
+
+    fn sample(n: u64, accel: Vector3<f32>, gyro_dps: Vector3<f32>) -> ImuSample {
+        ImuSample {
+            sequence: n as u8,
+            timestamp_ticks: (n + 1) * SAMPLE_US * TICKS_PER_US,
+            accel: accel.into(),
+            gyro: gyro_dps.into(),
+            dropped_before: 0,
+        }
+    }
+
+    // world up as a still imu at `orientation` (world_from_imu) measures it
+    fn gravity(orientation: &UnitQuaternion<f32>) -> Vector3<f32> {
+        orientation.inverse_transform_vector(&Vector3::y())
+    }
+
+    #[test]
+    fn aligns_to_a_tilted_gravity() {
+        let truth = UnitQuaternion::from_scaled_axis(Vector3::new(25f32.to_radians(), 0.0, -10f32.to_radians()));
+        let mut filter = ImuFilter::new(FilterGains::mahony());
+        for n in 0..ALIGN_SAMPLES as u64 - 1 {
+            assert_eq!(filter.update(&sample(n, gravity(&truth), Vector3::zeros())), None);
+        }
+        let aligned = filter.update(&sample(99, gravity(&truth), Vector3::zeros())).unwrap();
+
+        assert!((gravity(&aligned) - gravity(&truth)).norm() < 1e-5);
+        let tilt = |q: &UnitQuaternion<f32>| gravity(q).angle(&Vector3::y());
+        assert!((tilt(&aligned) - tilt(&truth)).abs() < 1e-4);
+        // still the whole time, so the (zero) gyro mean is the bias
+        assert_eq!(filter.gyro_bias(), Vector3::zeros());
+    }
+
+    #[test]
+    fn estimates_a_constant_gyro_bias() {
+        // too large to pass as still during alignment
+        let bias_dps = Vector3::new(4.0, 0.0, -3.0);
+        for gains in [FilterGains::mahony(), FilterGains::madgwick()] {
+            let truth = UnitQuaternion::from_scaled_axis(Vector3::x() * 0.2);
+            let mut filter = ImuFilter::new(gains);
+            for n in 0..120_000 {
+                filter.update(&sample(n, gravity(&truth), bias_dps));
+            }
+
+            // the part along gravity is yaw, which the accelerometer cannot see
+            let error = filter.gyro_bias().map(f32::to_degrees) - bias_dps;
+            let up = gravity(&truth);
+            let observable = error - up * error.dot(&up);
+            assert!(observable.norm() < 0.1, "{:?} {}", gains, error);
+            let orientation = filter.orientation().unwrap();
+            assert!(gravity(&orientation).angle(&gravity(&truth)) < 0.01, "{:?}", gains);
+        }
+    }
+
+    #[test]
+    fn skips_gaps_longer_than_max_dt() {
+        let mut filter = ImuFilter::new(FilterGains::mahony());
+        let mut n = 0;
+        while !filter.is_aligned() {
+            filter.update(&sample(n, Vector3::y(), Vector3::zeros()));
+            n += 1;
+        }
+        let before = filter.orientation().unwrap();
+
+        // 200 ms stall, then a fast turn: the gap itself is not integrated
+        let spin = Vector3::new(0.0, 90.0, 0.0);
+        let gap = (MAX_DT_S * 1e6) as u64 / SAMPLE_US * 4;
+        let after_gap = filter.update(&sample(n + gap, Vector3::y(), spin)).unwrap();
+        assert_eq!(filter.gaps(), 1);
+        assert!(after_gap.angle_to(&before) < 1e-6);
+
+        // the next sample integrates its 1 ms again
+        let next = filter.update(&sample(n + gap + 1, Vector3::y(), spin)).unwrap();
+        assert!((next.angle_to(&before) - 90f32.to_radians() * 1e-3).abs() < 1e-4);
+        assert_eq!(filter.gaps(), 1);
+    }
+}
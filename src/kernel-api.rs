@@ -1,5 +1,8 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::hotplug::{HotplugAction, HotplugEvent};
 // TODO: GET BSD AND LINUX KERNEL APIs WORKING !
 
 #[cfg(target_os = "linux")]
@@ -8,149 +11,1319 @@ use std::os::unix::io::AsRawFd;
 #[cfg(target_os = "linux")]
 use nix::libc;
 
+// linux/hidraw.h
+#[cfg(target_os = "linux")]
+mod hidraw_ioctl {
+    use nix::libc;
+
+    #[repr(C)]
+    pub struct HidrawDevinfo {
+        pub bustype: u32,
+        pub vendor: i16,
+        pub product: i16,
+    }
+
+    pub const HID_MAX_DESCRIPTOR_SIZE: usize = 4096;
+
+    #[repr(C)]
+    pub struct HidrawReportDescriptor {
+        pub size: u32,
+        pub value: [u8; HID_MAX_DESCRIPTOR_SIZE],
+    }
+
+    nix::ioctl_read!(get_rdesc_size, b'H', 0x01, libc::c_int);
+    nix::ioctl_read!(get_rdesc, b'H', 0x02, HidrawReportDescriptor);
+    nix::ioctl_read!(get_raw_info, b'H', 0x03, HidrawDevinfo);
+    nix::ioctl_readwrite_buf!(set_feature, b'H', 0x06, u8);
+    nix::ioctl_readwrite_buf!(get_feature, b'H', 0x07, u8);
+}
+
+// HIDIOCGRAWINFO emaitza
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HidRawInfo {
+    pub bus_type: u32,  // BUS_USB = 3
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+// htc eta valve vendor id-ak (vive familia)
+pub const VIVE_VENDOR_IDS: [u16; 2] = [0x0bb4, 0x28de];
+
 // gailuaren informazioa
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DeviceInfo {
     pub vendor_id: u16,
     pub product_id: u16,
     pub device_path: String,
+    // sysfs-etik, ez badago none
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    // usb interface zenbakia (hidraw gailuentzat)
+    pub interface: Option<u8>,
+}
+
+impl DeviceInfo {
+    pub fn is_vive(&self) -> bool {
+        VIVE_VENDOR_IDS.contains(&self.vendor_id)
+    }
+}
+
+// sysfs bidezko gailu bilaketa
+// root normalean "/" da, testetan sysfs faltsu bat duen direktorio bat
+pub struct SysfsScanner {
+    root: PathBuf,
+}
+
+impl SysfsScanner {
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    pub fn with_root(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    // vive familiako hidraw gailu guztiak (/dev/hidrawN)
+    pub fn find_hidraw_devices(&self) -> Vec<DeviceInfo> {
+        let mut devices: Vec<DeviceInfo> = self
+            .hidraw_devices()
+            .into_iter()
+            .filter(|d| d.is_vive())
+            .collect();
+        devices.sort_by(|a, b| a.device_path.cmp(&b.device_path));
+        devices
+    }
+
+    // vive familiako usb gailu guztiak (/dev/bus/usb/BBB/DDD)
+    pub fn find_usb_devices(&self) -> Vec<DeviceInfo> {
+        let mut devices: Vec<DeviceInfo> = self
+            .usb_devices()
+            .into_iter()
+            .filter(|d| d.is_vive())
+            .collect();
+        devices.sort_by(|a, b| a.device_path.cmp(&b.device_path));
+        devices
+    }
+
+    // /dev/hidrawN bide baten informazioa
+    pub fn hidraw_info(&self, device_path: &str) -> Option<DeviceInfo> {
+        let node = Path::new(device_path).file_name()?.to_str()?;
+        self.hidraw_entry(node)
+    }
+
+    fn hidraw_devices(&self) -> Vec<DeviceInfo> {
+        let Ok(entries) = fs::read_dir(self.root.join("sys/class/hidraw")) else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| self.hidraw_entry(entry.file_name().to_str()?))
+            .collect()
+    }
+
+    // hidraw/<node>/device/uevent:
+    //   HID_ID=0003:000028DE:00002000
+    //   HID_NAME=HTC Vive Pro 2
+    //   HID_UNIQ=LHR-12345678
+    fn hidraw_entry(&self, node: &str) -> Option<DeviceInfo> {
+        let device = self.root.join("sys/class/hidraw").join(node).join("device");
+        let uevent = fs::read_to_string(device.join("uevent")).ok()?;
+
+        let mut ids = None;
+        let mut name = None;
+        let mut serial = None;
+        for line in uevent.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "HID_ID" => {
+                    let mut parts = value.split(':').skip(1);
+                    let vendor = u32::from_str_radix(parts.next()?, 16).ok()?;
+                    let product = u32::from_str_radix(parts.next()?, 16).ok()?;
+                    ids = Some((vendor as u16, product as u16));
+                }
+                "HID_NAME" => name = non_empty(value),
+                "HID_UNIQ" => serial = non_empty(value),
+                _ => {}
+            }
+        }
+        let (vendor_id, product_id) = ids?;
+
+        // hid gailuaren gurasoa usb interface-a da
+        let interface = read_attr(&device.join("../bInterfaceNumber"))
+            .and_then(|v| u8::from_str_radix(&v, 16).ok());
+
+        Some(DeviceInfo {
+            vendor_id,
+            product_id,
+            device_path: self.root.join("dev").join(node).to_string_lossy().into_owned(),
+            name,
+            serial,
+            interface,
+        })
+    }
+
+    // /sys/bus/usb/devices/<bus>-<port>: idVendor, idProduct, busnum, devnum...
+    // interface direktorioak (1-1:1.0) ez dute idVendor, saltatu egiten dira
+    fn usb_devices(&self) -> Vec<DeviceInfo> {
+        let Ok(entries) = fs::read_dir(self.root.join("sys/bus/usb/devices")) else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let dir = entry.path();
+                let vendor_id = u16::from_str_radix(&read_attr(&dir.join("idVendor"))?, 16).ok()?;
+                let product_id = u16::from_str_radix(&read_attr(&dir.join("idProduct"))?, 16).ok()?;
+                let busnum: u32 = read_attr(&dir.join("busnum"))?.parse().ok()?;
+                let devnum: u32 = read_attr(&dir.join("devnum"))?.parse().ok()?;
+
+                Some(DeviceInfo {
+                    vendor_id,
+                    product_id,
+                    device_path: self
+                        .root
+                        .join(format!("dev/bus/usb/{:03}/{:03}", busnum, devnum))
+                        .to_string_lossy()
+                        .into_owned(),
+                    name: read_attr(&dir.join("product")),
+                    serial: read_attr(&dir.join("serial")),
+                    interface: None,
+                })
+            })
+            .collect()
+    }
+}
+
+// sysfs atributu bat, zuriuneak kenduta
+fn read_attr(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().and_then(|v| non_empty(v.trim()))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+// gailuarekiko trafiko gordina: hidraw, usbfs, grabagailua edo erreproduzitzailea
+// KernelApi-k honen bidez bakarrik hitz egiten du gailuarekin
+pub trait DeviceTransport: Send {
+    // report bat irakurri (hidraw-en read bakoitzak report oso bat itzultzen du)
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>>;
+    fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>>;
+    // lehen byte-a report id-a da, emaitzak ere hura darama
+    fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+    // data[0] = report id
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>>;
+    fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>>;
+    fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+    // ioctl gordina, transport guztiek ez dute onartzen
+    fn ioctl(&mut self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>>;
+}
+
+// /dev/hidrawN bidezko transport-a
+pub struct HidrawTransport {
+    file: File,
+}
+
+impl HidrawTransport {
+    pub fn open(device_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(device_path)?;
+        Ok(Self { file })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl DeviceTransport for HidrawTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(self.file.read(buffer)?)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        self.file.write_all(data)?;
+        Ok(data.len())
+    }
+
+    // HIDIOCGFEATURE
+    fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut buffer = vec![0u8; len.max(1)];
+        buffer[0] = report_id;
+        let read = unsafe { hidraw_ioctl::get_feature(self.file.as_raw_fd(), &mut buffer)? };
+        buffer.truncate(read as usize);
+        Ok(buffer)
+    }
+
+    // HIDIOCSFEATURE
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        if data.is_empty() {
+            return Err("feature report hutsa".into());
+        }
+        let mut buffer = data.to_vec();
+        let written = unsafe { hidraw_ioctl::set_feature(self.file.as_raw_fd(), &mut buffer)? };
+        Ok(written as usize)
+    }
+
+    // HIDIOCGRAWINFO
+    fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>> {
+        let mut info = hidraw_ioctl::HidrawDevinfo {
+            bustype: 0,
+            vendor: 0,
+            product: 0,
+        };
+        unsafe { hidraw_ioctl::get_raw_info(self.file.as_raw_fd(), &mut info)? };
+        Ok(HidRawInfo {
+            bus_type: info.bustype,
+            vendor_id: info.vendor as u16,
+            product_id: info.product as u16,
+        })
+    }
+
+    // HIDIOCGRDESCSIZE + HIDIOCGRDESC
+    fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let fd = self.file.as_raw_fd();
+
+        let mut size: libc::c_int = 0;
+        unsafe { hidraw_ioctl::get_rdesc_size(fd, &mut size)? };
+        let size = (size.max(0) as usize).min(hidraw_ioctl::HID_MAX_DESCRIPTOR_SIZE);
+
+        let mut descriptor = Box::new(hidraw_ioctl::HidrawReportDescriptor {
+            size: size as u32,
+            value: [0; hidraw_ioctl::HID_MAX_DESCRIPTOR_SIZE],
+        });
+        unsafe { hidraw_ioctl::get_rdesc(fd, &mut *descriptor)? };
+        Ok(descriptor.value[..size].to_vec())
+    }
+
+    fn ioctl(&mut self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+        let fd = self.file.as_raw_fd();
+        let result = unsafe {
+            libc::ioctl(fd, request as libc::c_ulong, data.as_mut_ptr())
+        };
+
+        if result < 0 {
+            Err(format!("ioctl huts egin du: {}", result).into())
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl DeviceTransport for HidrawTransport {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(self.file.read(buffer)?)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        self.file.write_all(data)?;
+        Ok(data.len())
+    }
+
+    fn get_feature_report(&mut self, _report_id: u8, _len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Err("hidraw linux-en bakarrik".into())
+    }
+
+    fn send_feature_report(&mut self, _data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        Err("hidraw linux-en bakarrik".into())
+    }
+
+    fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>> {
+        Err("hidraw linux-en bakarrik".into())
+    }
+
+    fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Err("hidraw linux-en bakarrik".into())
+    }
+
+    fn ioctl(&mut self, _request: u64, _data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+        Err("ioctl linux-en bakarrik".into())
+    }
+}
+
+// kernel mailako api
+pub struct KernelApi {
+    transport: Option<Box<dyn DeviceTransport>>,
+    device_info: DeviceInfo,
+}
+
+impl KernelApi {
+    // gailua ireki
+    pub fn open(device_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let transport = HidrawTransport::open(device_path)?;
+
+        println!("gailua irekita: {}", device_path);
+
+        let mut api = Self::with_transport(
+            Box::new(transport),
+            DeviceInfo {
+                vendor_id: 0,
+                product_id: 0,
+                device_path: device_path.to_string(),
+                name: None,
+                serial: None,
+                interface: None,
+            },
+        );
+
+        // vid/pid sysfs-etik, bestela HIDIOCGRAWINFO-tik (hidraw ez bada 0 geratzen da)
+        if let Some(info) = SysfsScanner::new().hidraw_info(device_path) {
+            api.device_info = info;
+            api.device_info.device_path = device_path.to_string();
+        } else if let Ok(raw) = api.get_raw_info() {
+            api.device_info.vendor_id = raw.vendor_id;
+            api.device_info.product_id = raw.product_id;
+        }
+
+        Ok(api)
+    }
+
+    // aurkitutako lehen vive hidraw gailua ireki
+    pub fn open_first() -> Result<Self, Box<dyn std::error::Error>> {
+        let devices = SysfsScanner::new().find_hidraw_devices();
+        let device = devices.first().ok_or("ez da vive gailurik aurkitu")?;
+        println!(
+            "aurkituta: {:04x}:{:04x} {}",
+            device.vendor_id,
+            device.product_id,
+            device.name.as_deref().unwrap_or("")
+        );
+        Self::open(&device.device_path)
+    }
+
+    // edozein transport (usbfs, mock, erreproduzitzailea...)
+    pub fn with_transport(transport: Box<dyn DeviceTransport>, device_info: DeviceInfo) -> Self {
+        Self {
+            transport: Some(transport),
+            device_info,
+        }
+    }
+
+    // grabatutako saio bat erreproduzitu, gailurik gabe
+    // realtime = true bada jatorrizko denborak errespetatzen dira
+    pub fn replay(capture_path: &str, realtime: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let replayer = capture::ReplayTransport::open(capture_path, realtime)?;
+        let info = replayer.device_info().clone();
+        println!("grabaketa erreproduzitzen: {} ({} sarrera)", capture_path, replayer.remaining());
+        Ok(Self::with_transport(Box::new(replayer), info))
+    }
+
+    // hemendik aurrera trafiko guztia fitxategi batean grabatu
+    pub fn start_recording(&mut self, capture_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = BufWriter::new(File::create(capture_path)?);
+        capture::write_header(&mut writer, &self.device_info)?;
+
+        let inner = self.transport.take().ok_or("gailua ez dago irekita")?;
+        self.transport = Some(Box::new(capture::RecordingTransport::new(inner, writer)));
+        println!("grabatzen: {}", capture_path);
+        Ok(())
+    }
+
+    fn transport(&mut self) -> Result<&mut Box<dyn DeviceTransport>, Box<dyn std::error::Error>> {
+        self.transport.as_mut().ok_or_else(|| "gailua ez dago irekita".into())
+    }
+
+    // sentsoreen datuak irakurri kernel-etik
+    pub fn read_sensors(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut buffer = vec![0u8; 64];  // ohiko imu frame tamaina
+        let read = self.transport()?.read(&mut buffer)?;
+        buffer.truncate(read);
+        Ok(buffer)
+    }
+
+    // komando bat bidali gailura
+    pub fn send_command(&mut self, command: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.transport()?.write(command)?;
+        Ok(())
+    }
+
+    // ioctl (input/output control) deiak kernel kontrolatzaileari
+    pub fn ioctl(&mut self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+        self.transport()?.ioctl(request, data)
+    }
+
+    // feature report bat irakurri (HIDIOCGFEATURE)
+    // lehen byte-a report id-a da, emaitzak ere hura darama
+    pub fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.transport()?.get_feature_report(report_id, len)
+    }
+
+    // feature report bat bidali (HIDIOCSFEATURE), data[0] = report id
+    pub fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+        self.transport()?.send_feature_report(data)
+    }
+
+    // bus mota eta vid/pid (HIDIOCGRAWINFO)
+    pub fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>> {
+        self.transport()?.get_raw_info()
+    }
+
+    // hid report descriptor gordina (HIDIOCGRDESCSIZE + HIDIOCGRDESC)
+    pub fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.transport()?.get_report_descriptor()
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    // deskonexio baten ondoren berriz ireki
+    // /dev/hidrawN aldatu daiteke, beraz vid/pid, serie zenbakia eta interface-aren bidez bilatzen da
+    // grabaketa bat martxan bazegoen, ez da berriz hasten
+    pub fn reopen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.reopen_with(&SysfsScanner::new())
+    }
+
+    pub fn reopen_with(&mut self, scanner: &SysfsScanner) -> Result<(), Box<dyn std::error::Error>> {
+        self.close();
+
+        let info = scanner
+            .find_hidraw_devices()
+            .into_iter()
+            .find(|d| self.is_same_device(d))
+            .ok_or("gailua ez dago berriz konektatuta")?;
+        let transport = HidrawTransport::open(&info.device_path)?;
+
+        println!("gailua berriz irekita: {}", info.device_path);
+        self.transport = Some(Box::new(transport));
+        self.device_info = info;
+        Ok(())
+    }
+
+    // hotplug gertaera bat aplikatu, true itxi edo berriz ireki bada
+    // udev-ek baimenak gertaeraren ondoren aldatzen ditu, beraz ireki ezin bada
+    // deitzaileak geroago reopen() berriz saiatu behar du
+    pub fn handle_hotplug(&mut self, event: &HotplugEvent) -> Result<bool, Box<dyn std::error::Error>> {
+        match event.action {
+            HotplugAction::Removed if self.is_open() && event.is_device(&self.device_info) => {
+                println!("gailua deskonektatuta: {}", self.device_info.device_path);
+                self.close();
+                Ok(true)
+            }
+            HotplugAction::Added
+                if !self.is_open()
+                    && event.subsystem == "hidraw"
+                    && event.vendor_id == self.device_info.vendor_id
+                    && event.product_id == self.device_info.product_id =>
+            {
+                self.reopen()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // headset-ak hainbat hidraw interface ditu vid/pid berarekin
+    fn is_same_device(&self, other: &DeviceInfo) -> bool {
+        let known = &self.device_info;
+        let same = |a: &Option<String>, b: &Option<String>| a.is_none() || b.is_none() || a == b;
+        other.vendor_id == known.vendor_id
+            && other.product_id == known.product_id
+            && same(&other.serial, &known.serial)
+            && (other.interface.is_none() || known.interface.is_none() || other.interface == known.interface)
+    }
+
+    // gailuaren informazioa lortu
+    pub fn get_device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    // gailua itxi
+    pub fn close(&mut self) {
+        if self.transport.take().is_some() {
+            println!("gailua itxita");
+        }
+    }
 }
 
-// kernel mailako api
-pub struct KernelApi {
-    device_file: Option<File>,
-    device_info: DeviceInfo,
-}
+impl Drop for KernelApi {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// trafikoaren grabaketa eta erreproduzioa
+// fitxategia json lerroak dira: lehena goiburua (gailuaren informazioa),
+// gero dei bakoitzeko sarrera bat, grabaketa hasi zenetik igarotako mikrosegundoekin:
+//   {"version":1,"device":{"vendor_id":2996,...}}
+//   {"time_us":1520,"op":"get_feature","report_id":16,"len":64,"rx":"1000..."}
+//   {"time_us":4013,"op":"read","len":64,"rx":"2001..."}
+pub mod capture {
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+    use std::time::{Duration, Instant};
+    use serde::{Serialize, Deserialize};
+    use super::{DeviceInfo, DeviceTransport, HidRawInfo};
+
+    pub const CAPTURE_VERSION: u32 = 1;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct CaptureHeader {
+        pub version: u32,
+        pub device: DeviceInfo,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    pub enum CaptureOp {
+        Read { len: usize },
+        Write,
+        GetFeature { report_id: u8, len: usize },
+        SendFeature,
+        RawInfo,
+        ReportDescriptor,
+        Ioctl { request: u64 },
+    }
+
+    impl CaptureOp {
+        // erreproduzitzean deia eta sarrera bat datozen (read-en buffer tamaina ez da kontuan hartzen)
+        fn matches(&self, other: &CaptureOp) -> bool {
+            match (self, other) {
+                (CaptureOp::Read { .. }, CaptureOp::Read { .. }) => true,
+                (CaptureOp::GetFeature { report_id: a, .. }, CaptureOp::GetFeature { report_id: b, .. }) => a == b,
+                _ => self == other,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct CaptureEntry {
+        pub time_us: u64,
+        #[serde(flatten)]
+        pub op: CaptureOp,
+        // host-etik gailura (hex)
+        #[serde(default, with = "hex_bytes", skip_serializing_if = "Vec::is_empty")]
+        pub tx: Vec<u8>,
+        // gailutik host-era (hex)
+        #[serde(default, with = "hex_bytes", skip_serializing_if = "Vec::is_empty")]
+        pub rx: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub result: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    pub fn write_header(writer: &mut impl Write, device: &DeviceInfo) -> Result<(), Box<dyn std::error::Error>> {
+        let header = CaptureHeader {
+            version: CAPTURE_VERSION,
+            device: device.clone(),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        Ok(())
+    }
+
+    // raw info 8 byte-etan: bus_type (u32 le), vid, pid (u16 le)
+    fn encode_raw_info(info: &HidRawInfo) -> Vec<u8> {
+        let mut bytes = info.bus_type.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&info.vendor_id.to_le_bytes());
+        bytes.extend_from_slice(&info.product_id.to_le_bytes());
+        bytes
+    }
+
+    fn decode_raw_info(bytes: &[u8]) -> Option<HidRawInfo> {
+        if bytes.len() < 8 {
+            return None;
+        }
+        Some(HidRawInfo {
+            bus_type: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            vendor_id: u16::from_le_bytes([bytes[4], bytes[5]]),
+            product_id: u16::from_le_bytes([bytes[6], bytes[7]]),
+        })
+    }
+
+    // beste transport bat bilduta, dei bakoitza fitxategian idazten du
+    pub struct RecordingTransport {
+        inner: Box<dyn DeviceTransport>,
+        writer: Option<Box<dyn Write + Send>>,
+        start: Instant,
+        entries: usize,
+    }
+
+    impl RecordingTransport {
+        // goiburua lehenago idatzi behar da (write_header)
+        pub fn new(inner: Box<dyn DeviceTransport>, writer: impl Write + Send + 'static) -> Self {
+            Self {
+                inner,
+                writer: Some(Box::new(writer)),
+                start: Instant::now(),
+                entries: 0,
+            }
+        }
+
+        pub fn entries(&self) -> usize {
+            self.entries
+        }
+
+        pub fn into_inner(self) -> Box<dyn DeviceTransport> {
+            self.inner
+        }
+
+        // grabaketak huts egiten badu gailuak funtzionatzen jarraitzen du
+        fn record<T>(
+            &mut self,
+            op: CaptureOp,
+            tx: &[u8],
+            result: &Result<T, Box<dyn std::error::Error>>,
+            rx: impl FnOnce(&T) -> (Vec<u8>, Option<i64>),
+        ) {
+            let Some(writer) = self.writer.as_mut() else {
+                return;
+            };
+
+            let (rx, value, error) = match result {
+                Ok(value) => {
+                    let (rx, value) = rx(value);
+                    (rx, value, None)
+                }
+                Err(err) => (Vec::new(), None, Some(err.to_string())),
+            };
+            let entry = CaptureEntry {
+                time_us: self.start.elapsed().as_micros() as u64,
+                op,
+                tx: tx.to_vec(),
+                rx,
+                result: value,
+                error,
+            };
+
+            // lerro bakoitza berehala hustu, daemon-a seinale batek gelditzen du
+            let written = serde_json::to_string(&entry)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(writer, "{}", line))
+                .and_then(|()| writer.flush());
+            match written {
+                Ok(()) => self.entries += 1,
+                Err(err) => {
+                    println!("grabaketa gelditu da: {}", err);
+                    self.writer = None;
+                }
+            }
+        }
+    }
+
+    impl DeviceTransport for RecordingTransport {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let result = self.inner.read(buffer);
+            let data = buffer.to_vec();
+            self.record(CaptureOp::Read { len: buffer.len() }, &[], &result, |&n| (data[..n].to_vec(), None));
+            result
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let result = self.inner.write(data);
+            self.record(CaptureOp::Write, data, &result, |&n| (Vec::new(), Some(n as i64)));
+            result
+        }
+
+        fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let result = self.inner.get_feature_report(report_id, len);
+            self.record(CaptureOp::GetFeature { report_id, len }, &[], &result, |report| (report.clone(), None));
+            result
+        }
+
+        fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let result = self.inner.send_feature_report(data);
+            self.record(CaptureOp::SendFeature, data, &result, |&n| (Vec::new(), Some(n as i64)));
+            result
+        }
+
+        fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>> {
+            let result = self.inner.get_raw_info();
+            self.record(CaptureOp::RawInfo, &[], &result, |info| (encode_raw_info(info), None));
+            result
+        }
+
+        fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let result = self.inner.get_report_descriptor();
+            self.record(CaptureOp::ReportDescriptor, &[], &result, |descriptor| (descriptor.clone(), None));
+            result
+        }
+
+        fn ioctl(&mut self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+            let tx = data.to_vec();
+            let result = self.inner.ioctl(request, data);
+            let rx = data.to_vec();
+            self.record(CaptureOp::Ioctl { request }, &tx, &result, |&value| (rx, Some(value as i64)));
+            result
+        }
+    }
+
+    // grabaketa bat gailu bat balitz bezala itzultzen du
+    // deiek grabaketaren ordena bera jarraitu behar dute, bestela errorea
+    pub struct ReplayTransport {
+        device: DeviceInfo,
+        entries: VecDeque<CaptureEntry>,
+        realtime: bool,
+        // lehen deiaren unea eta lehen sarreraren denbora
+        clock: Option<(Instant, u64)>,
+        tx_mismatches: usize,
+    }
+
+    impl ReplayTransport {
+        pub fn open(path: &str, realtime: bool) -> Result<Self, Box<dyn std::error::Error>> {
+            Self::from_reader(BufReader::new(File::open(path)?), realtime)
+        }
+
+        pub fn from_reader(mut reader: impl BufRead, realtime: bool) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err("grabaketa hutsa".into());
+            }
+            let header: CaptureHeader = serde_json::from_str(&line)?;
+            if header.version != CAPTURE_VERSION {
+                return Err(format!("grabaketa bertsio ezezaguna: {}", header.version).into());
+            }
+
+            let mut entries = VecDeque::new();
+            for n in 2.. {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    break;
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<CaptureEntry>(&line) {
+                    Ok(entry) => entries.push_back(entry),
+                    // lerro-jauzirik gabeko azken lerroa: grabaketa erdian eten zen
+                    Err(_) if !line.ends_with('\n') => {
+                        println!("grabaketaren azken lerroa osatu gabe dago, baztertuta");
+                        break;
+                    }
+                    Err(e) => return Err(format!("grabaketa lerroa {}: {}", n, e).into()),
+                }
+            }
+
+            Ok(Self {
+                device: header.device,
+                entries,
+                realtime,
+                clock: None,
+                tx_mismatches: 0,
+            })
+        }
+
+        pub fn device_info(&self) -> &DeviceInfo {
+            &self.device
+        }
+
+        pub fn remaining(&self) -> usize {
+            self.entries.len()
+        }
+
+        // grabatutakoaz bestelako datuak idatzi diren aldiak
+        pub fn tx_mismatches(&self) -> usize {
+            self.tx_mismatches
+        }
+
+        // hurrengo sarrera hartu, behar bada jatorrizko unera arte itxaron
+        fn next(&mut self, op: CaptureOp, tx: &[u8]) -> Result<CaptureEntry, Box<dyn std::error::Error>> {
+            let entry = self.entries.pop_front().ok_or("grabaketa amaitu da")?;
+            if !entry.op.matches(&op) {
+                return Err(format!("erreprodukzioa desinkronizatuta: {:?} espero zen, {:?} deitu da", entry.op, op).into());
+            }
+            if entry.tx != tx {
+                self.tx_mismatches += 1;
+            }
+
+            if self.realtime {
+                let (start, first_us) = *self.clock.get_or_insert((Instant::now(), entry.time_us));
+                let due = start + Duration::from_micros(entry.time_us.saturating_sub(first_us));
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+            }
+
+            match entry.error {
+                Some(ref err) => Err(err.clone().into()),
+                None => Ok(entry),
+            }
+        }
+    }
+
+    impl DeviceTransport for ReplayTransport {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let entry = self.next(CaptureOp::Read { len: buffer.len() }, &[])?;
+            let n = entry.rx.len().min(buffer.len());
+            buffer[..n].copy_from_slice(&entry.rx[..n]);
+            Ok(n)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let entry = self.next(CaptureOp::Write, data)?;
+            Ok(entry.result.unwrap_or(data.len() as i64) as usize)
+        }
+
+        fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            Ok(self.next(CaptureOp::GetFeature { report_id, len }, &[])?.rx)
+        }
+
+        fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let entry = self.next(CaptureOp::SendFeature, data)?;
+            Ok(entry.result.unwrap_or(data.len() as i64) as usize)
+        }
+
+        fn get_raw_info(&mut self) -> Result<HidRawInfo, Box<dyn std::error::Error>> {
+            let entry = self.next(CaptureOp::RawInfo, &[])?;
+            decode_raw_info(&entry.rx).ok_or_else(|| "raw info sarrera okerra".into())
+        }
+
+        fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            Ok(self.next(CaptureOp::ReportDescriptor, &[])?.rx)
+        }
+
+        fn ioctl(&mut self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+            let tx = data.to_vec();
+            let entry = self.next(CaptureOp::Ioctl { request }, &tx)?;
+            let n = entry.rx.len().min(data.len());
+            data[..n].copy_from_slice(&entry.rx[..n]);
+            Ok(entry.result.unwrap_or(0) as i32)
+        }
+    }
+
+    // Vec<u8> <-> "0a1b2c" serde-rako
+    mod hex_bytes {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            serializer.serialize_str(&hex)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            let hex = String::deserialize(deserializer)?;
+            if hex.len() % 2 != 0 {
+                return Err(serde::de::Error::custom("hex luzera bakoitia"));
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(serde::de::Error::custom))
+                .collect()
+        }
+    }
+}
+
+// usb bidezko komunikazioa (usbfs, /dev/bus/usb/BBB/DDD)
+// oharra: normalean ez da behar openxr erabiltzen denean
+pub mod usb {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+    use super::{DeviceInfo, SysfsScanner};
+
+    #[cfg(target_os = "linux")]
+    use std::fs::{File, OpenOptions};
+    #[cfg(target_os = "linux")]
+    use std::os::unix::io::AsRawFd;
+
+    // endpoint helbidearen 7. bit-a: 1 = in (gailutik host-era)
+    pub const ENDPOINT_IN: u8 = 0x80;
+
+    // usb transferentzia mota guztiak, usbfs-ek edo mock batek inplementatzen du
+    pub trait UsbTransport {
+        fn claim_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>>;
+        fn release_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>>;
+        // interface-a kernel driver batek hartuta badu (usbhid...), askatu
+        fn detach_kernel_driver(&mut self, interface: u8) -> Result<bool, Box<dyn std::error::Error>>;
+        // in endpoint-etan data betetzen da, out-etan bidali egiten da
+        fn bulk_transfer(&mut self, endpoint: u8, data: &mut [u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>;
+        fn interrupt_transfer(&mut self, endpoint: u8, data: &mut [u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>;
+        fn control_transfer(
+            &mut self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            index: u16,
+            data: &mut [u8],
+            timeout: Duration,
+        ) -> Result<usize, Box<dyn std::error::Error>>;
+    }
+
+    // linux/usbdevice_fs.h
+    #[cfg(target_os = "linux")]
+    mod usbfs_ioctl {
+        use nix::libc;
 
-impl KernelApi {
-    // gailua ireki
-    pub fn open(device_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(device_path)?;
+        #[repr(C)]
+        pub struct CtrlTransfer {
+            pub request_type: u8,
+            pub request: u8,
+            pub value: u16,
+            pub index: u16,
+            pub length: u16,
+            pub timeout: u32,  // ms
+            pub data: *mut libc::c_void,
+        }
 
-        println!("gailua irekita: {}", device_path);
+        #[repr(C)]
+        pub struct BulkTransfer {
+            pub endpoint: libc::c_uint,
+            pub length: libc::c_uint,
+            pub timeout: libc::c_uint,  // ms
+            pub data: *mut libc::c_void,
+        }
 
-        Ok(Self {
-            device_file: Some(file),
-            device_info: DeviceInfo {
-                vendor_id: 0x0BB4,  // htc
-                product_id: 0x0000,  // vive pro 2 (aldatu behar)
-                device_path: device_path.to_string(),
-            },
-        })
+        #[repr(C)]
+        pub struct IoctlRequest {
+            pub interface: libc::c_int,
+            pub ioctl_code: libc::c_int,
+            pub data: *mut libc::c_void,
+        }
+
+        #[repr(C)]
+        pub struct GetDriver {
+            pub interface: libc::c_uint,
+            pub driver: [u8; 256],
+        }
+
+        pub const DISCONNECT: libc::c_int = nix::request_code_none!(b'U', 22) as libc::c_int;
+
+        nix::ioctl_readwrite!(control, b'U', 0, CtrlTransfer);
+        nix::ioctl_readwrite!(bulk, b'U', 2, BulkTransfer);
+        // kernel-ak _IOW gisa definitzen du baina driver-aren izena idazten du: *mut behar da
+        nix::ioctl_readwrite_bad!(
+            get_driver,
+            nix::request_code_write!(b'U', 8, std::mem::size_of::<GetDriver>()),
+            GetDriver
+        );
+        nix::ioctl_read!(claim_interface, b'U', 15, libc::c_uint);
+        nix::ioctl_read!(release_interface, b'U', 16, libc::c_uint);
+        nix::ioctl_readwrite!(ioctl, b'U', 18, IoctlRequest);
     }
 
-    // sentsoreen datuak irakurri kernel-etik
-    pub fn read_sensors(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        if let Some(ref mut file) = self.device_file {
-            let mut buffer = vec![0u8; 64];  // ohiko imu frame tamaina
-            file.read_exact(&mut buffer)?;
-            Ok(buffer)
-        } else {
-            Err("gailua ez dago irekita".into())
+    // usbfs transport, /dev/bus/usb/BBB/DDD ireki behar da (udev araua behar da)
+    #[cfg(target_os = "linux")]
+    pub struct UsbfsTransport {
+        file: File,
+        claimed: Vec<u8>,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl UsbfsTransport {
+        pub fn open(device_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let file = OpenOptions::new().read(true).write(true).open(device_path)?;
+            Ok(Self {
+                file,
+                claimed: Vec::new(),
+            })
+        }
+
+        fn map_error(err: nix::errno::Errno, what: &str) -> Box<dyn std::error::Error> {
+            match err {
+                nix::errno::Errno::ETIMEDOUT => format!("{}: denbora-muga", what).into(),
+                nix::errno::Errno::ENODEV => format!("{}: gailua deskonektatuta", what).into(),
+                _ => format!("{}: {}", what, err).into(),
+            }
         }
     }
 
-    // komando bat bidali gailura
-    pub fn send_command(&mut self, command: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(ref mut file) = self.device_file {
-            file.write_all(command)?;
+    #[cfg(target_os = "linux")]
+    impl UsbTransport for UsbfsTransport {
+        fn claim_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            let mut number = interface as nix::libc::c_uint;
+            unsafe { usbfs_ioctl::claim_interface(self.file.as_raw_fd(), &mut number) }
+                .map_err(|e| Self::map_error(e, "claim interface"))?;
+            self.claimed.push(interface);
             Ok(())
-        } else {
-            Err("gailua ez dago irekita".into())
+        }
+
+        fn release_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            let mut number = interface as nix::libc::c_uint;
+            unsafe { usbfs_ioctl::release_interface(self.file.as_raw_fd(), &mut number) }
+                .map_err(|e| Self::map_error(e, "release interface"))?;
+            self.claimed.retain(|&i| i != interface);
+            Ok(())
+        }
+
+        fn detach_kernel_driver(&mut self, interface: u8) -> Result<bool, Box<dyn std::error::Error>> {
+            let fd = self.file.as_raw_fd();
+            let mut query = usbfs_ioctl::GetDriver {
+                interface: interface as nix::libc::c_uint,
+                driver: [0; 256],
+            };
+            // ENODATA: ez dago driver-ik
+            if unsafe { usbfs_ioctl::get_driver(fd, &mut query) }.is_err() {
+                return Ok(false);
+            }
+
+            let mut request = usbfs_ioctl::IoctlRequest {
+                interface: interface as nix::libc::c_int,
+                ioctl_code: usbfs_ioctl::DISCONNECT,
+                data: std::ptr::null_mut(),
+            };
+            unsafe { usbfs_ioctl::ioctl(fd, &mut request) }
+                .map_err(|e| Self::map_error(e, "detach kernel driver"))?;
+            Ok(true)
+        }
+
+        fn bulk_transfer(&mut self, endpoint: u8, data: &mut [u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
+        {
+            let mut transfer = usbfs_ioctl::BulkTransfer {
+                endpoint: endpoint as nix::libc::c_uint,
+                length: data.len() as nix::libc::c_uint,
+                timeout: timeout.as_millis().min(u32::MAX as u128) as nix::libc::c_uint,
+                data: data.as_mut_ptr() as *mut nix::libc::c_void,
+            };
+            let n = unsafe { usbfs_ioctl::bulk(self.file.as_raw_fd(), &mut transfer) }
+                .map_err(|e| Self::map_error(e, "bulk transfer"))?;
+            Ok(n as usize)
+        }
+
+        // usbfs-en interrupt endpoint-ek bulk ioctl bera erabiltzen dute
+        fn interrupt_transfer(&mut self, endpoint: u8, data: &mut [u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
+        {
+            self.bulk_transfer(endpoint, data, timeout)
+        }
+
+        fn control_transfer(
+            &mut self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            index: u16,
+            data: &mut [u8],
+            timeout: Duration,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
+            let mut transfer = usbfs_ioctl::CtrlTransfer {
+                request_type,
+                request,
+                value,
+                index,
+                length: data.len().min(u16::MAX as usize) as u16,
+                timeout: timeout.as_millis().min(u32::MAX as u128) as u32,
+                data: data.as_mut_ptr() as *mut nix::libc::c_void,
+            };
+            let n = unsafe { usbfs_ioctl::control(self.file.as_raw_fd(), &mut transfer) }
+                .map_err(|e| Self::map_error(e, "control transfer"))?;
+            Ok(n as usize)
         }
     }
 
     #[cfg(target_os = "linux")]
-    // ioctl (input/output control) deiak kernel kontrolatzaileari
-    pub fn ioctl(&self, request: u64, data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
-        if let Some(ref file) = self.device_file {
-            let fd = file.as_raw_fd();
-            let result = unsafe {
-                libc::ioctl(fd, request as libc::c_ulong, data.as_mut_ptr())
-            };
-            
-            if result < 0 {
-                Err(format!("ioctl huts egin du: {}", result).into())
-            } else {
-                Ok(result)
+    impl Drop for UsbfsTransport {
+        fn drop(&mut self) {
+            for interface in std::mem::take(&mut self.claimed) {
+                let _ = self.release_interface(interface);
             }
-        } else {
-            Err("gailua ez dago irekita".into())
         }
     }
 
-    #[cfg(not(target_os = "linux"))]
-    pub fn ioctl(&self, _request: u64, _data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
-        Err("ioctl linux-en bakarrik".into())
+    // mock transport gailurik gabeko testetarako
+    // in transferentziek `responses` ilaratik hartzen dute, dena `calls`-en gordetzen da
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum MockCall {
+        Claim(u8),
+        Release(u8),
+        Detach(u8),
+        Bulk { endpoint: u8, data: Vec<u8> },
+        Interrupt { endpoint: u8, data: Vec<u8> },
+        Control { request_type: u8, request: u8, value: u16, index: u16, data: Vec<u8> },
     }
 
-    // gailuaren informazioa lortu
-    pub fn get_device_info(&self) -> &DeviceInfo {
-        &self.device_info
+    #[derive(Debug, Default)]
+    pub struct MockTransport {
+        pub calls: Vec<MockCall>,
+        // hurrengo in transferentzien erantzunak, Err = denbora-muga
+        pub responses: VecDeque<Result<Vec<u8>, String>>,
+        // detach_kernel_driver-ek true itzultzen du hemen badago
+        pub kernel_drivers: Vec<u8>,
     }
 
-    // gailua itxi
-    pub fn close(&mut self) {
-        self.device_file = None;
-        println!("gailua itxita");
+    impl MockTransport {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn push_response(&mut self, data: &[u8]) {
+            self.responses.push_back(Ok(data.to_vec()));
+        }
+
+        pub fn push_timeout(&mut self) {
+            self.responses.push_back(Err("denbora-muga".to_string()));
+        }
+
+        fn transfer(&mut self, is_in: bool, data: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            if !is_in {
+                return Ok(data.len());
+            }
+            match self.responses.pop_front() {
+                Some(Ok(response)) => {
+                    let n = response.len().min(data.len());
+                    data[..n].copy_from_slice(&response[..n]);
+                    Ok(n)
+                }
+                Some(Err(err)) => Err(err.into()),
+                None => Err("mock: erantzunik ez".into()),
+            }
+        }
     }
-}
 
-impl Drop for KernelApi {
-    fn drop(&mut self) {
-        self.close();
+    impl UsbTransport for MockTransport {
+        fn claim_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            self.calls.push(MockCall::Claim(interface));
+            Ok(())
+        }
+
+        fn release_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            self.calls.push(MockCall::Release(interface));
+            Ok(())
+        }
+
+        fn detach_kernel_driver(&mut self, interface: u8) -> Result<bool, Box<dyn std::error::Error>> {
+            self.calls.push(MockCall::Detach(interface));
+            let attached = self.kernel_drivers.contains(&interface);
+            self.kernel_drivers.retain(|&i| i != interface);
+            Ok(attached)
+        }
+
+        fn bulk_transfer(&mut self, endpoint: u8, data: &mut [u8], _timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
+        {
+            let result = self.transfer(endpoint & ENDPOINT_IN != 0, data);
+            self.calls.push(MockCall::Bulk { endpoint, data: data.to_vec() });
+            result
+        }
+
+        fn interrupt_transfer(&mut self, endpoint: u8, data: &mut [u8], _timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
+        {
+            let result = self.transfer(endpoint & ENDPOINT_IN != 0, data);
+            self.calls.push(MockCall::Interrupt { endpoint, data: data.to_vec() });
+            result
+        }
+
+        fn control_transfer(
+            &mut self,
+            request_type: u8,
+            request: u8,
+            value: u16,
+            index: u16,
+            data: &mut [u8],
+            _timeout: Duration,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
+            let result = self.transfer(request_type & ENDPOINT_IN != 0, data);
+            self.calls.push(MockCall::Control { request_type, request, value, index, data: data.to_vec() });
+            result
+        }
     }
-}
 
-// usb bidezko komunikazioa (libusb erabiliz)
-// oharra: normalean ez da behar openxr erabiltzen denean
-pub mod usb {
-    use std::time::Duration;
+    #[cfg(target_os = "linux")]
+    pub struct UsbDevice<T: UsbTransport = UsbfsTransport> {
+        transport: T,
+        info: Option<DeviceInfo>,
+    }
 
-    pub struct UsbDevice {
-        context: Option<()>,  // libusb context hemen joango litzateke
+    #[cfg(not(target_os = "linux"))]
+    pub struct UsbDevice<T: UsbTransport = MockTransport> {
+        transport: T,
+        info: Option<DeviceInfo>,
     }
 
-    impl UsbDevice {
-        pub fn find_vive_devices() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-            // hau normalean libusb-rekin egingo litzateke
-            // baina openxr erabiltzean ez da beharrezkoa
+    #[cfg(target_os = "linux")]
+    impl UsbDevice<UsbfsTransport> {
+        // /sys/bus/usb/devices-etik, htc (0bb4) eta valve (28de) gailuak
+        pub fn find_vive_devices() -> Result<Vec<DeviceInfo>, Box<dyn std::error::Error>> {
             println!("usb gailuak bilatzen...");
-            
-            // adibide baterako:
-            // lsusb | grep -i htc exekutatuko litzateke
-            
-            Ok(vec![])
+            Ok(SysfsScanner::new().find_usb_devices())
         }
 
+        // vid:pid bat duen lehen gailua ireki
         pub fn open(vendor_id: u16, product_id: u16) -> Result<Self, Box<dyn std::error::Error>> {
             println!("usb gailua irekitzen: {:04x}:{:04x}", vendor_id, product_id);
-            
-            Ok(Self {
-                context: None,
-            })
+
+            let info = SysfsScanner::new()
+                .find_usb_devices()
+                .into_iter()
+                .find(|d| d.vendor_id == vendor_id && d.product_id == product_id)
+                .ok_or_else(|| format!("ez da aurkitu {:04x}:{:04x}", vendor_id, product_id))?;
+
+            let transport = UsbfsTransport::open(&info.device_path)?;
+            let mut device = Self::with_transport(transport);
+            device.info = Some(info);
+            Ok(device)
+        }
+    }
+
+    impl<T: UsbTransport> UsbDevice<T> {
+        pub fn with_transport(transport: T) -> Self {
+            Self {
+                transport,
+                info: None,
+            }
+        }
+
+        pub fn info(&self) -> Option<&DeviceInfo> {
+            self.info.as_ref()
+        }
+
+        pub fn transport(&self) -> &T {
+            &self.transport
+        }
+
+        pub fn transport_mut(&mut self) -> &mut T {
+            &mut self.transport
+        }
+
+        // interface hartu, kernel driver-a (usbhid) lehenik askatuta
+        pub fn claim_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            if self.transport.detach_kernel_driver(interface)? {
+                println!("kernel driver askatuta interface {}", interface);
+            }
+            self.transport.claim_interface(interface)
         }
 
-        pub fn read_bulk(&mut self, endpoint: u8, size: usize, timeout: Duration) 
-            -> Result<Vec<u8>, Box<dyn std::error::Error>> 
+        pub fn release_interface(&mut self, interface: u8) -> Result<(), Box<dyn std::error::Error>> {
+            self.transport.release_interface(interface)
+        }
+
+        pub fn read_bulk(&mut self, endpoint: u8, size: usize, timeout: Duration)
+            -> Result<Vec<u8>, Box<dyn std::error::Error>>
         {
-            // bulk transferentzia irakurri
-            let _timeout_ms = timeout.as_millis();
-            println!("bulk irakurtzen endpoint {}", endpoint);
-            Ok(vec![0; size])
+            let mut buffer = vec![0u8; size];
+            let n = self.transport.bulk_transfer(endpoint | ENDPOINT_IN, &mut buffer, timeout)?;
+            buffer.truncate(n);
+            Ok(buffer)
         }
 
-        pub fn write_bulk(&mut self, endpoint: u8, data: &[u8], timeout: Duration) 
-            -> Result<usize, Box<dyn std::error::Error>> 
+        pub fn write_bulk(&mut self, endpoint: u8, data: &[u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
         {
-            let _timeout_ms = timeout.as_millis();
-            println!("bulk idazten endpoint {}: {} byte", endpoint, data.len());
-            Ok(data.len())
+            let mut buffer = data.to_vec();
+            self.transport.bulk_transfer(endpoint & !ENDPOINT_IN, &mut buffer, timeout)
+        }
+
+        pub fn read_interrupt(&mut self, endpoint: u8, size: usize, timeout: Duration)
+            -> Result<Vec<u8>, Box<dyn std::error::Error>>
+        {
+            let mut buffer = vec![0u8; size];
+            let n = self.transport.interrupt_transfer(endpoint | ENDPOINT_IN, &mut buffer, timeout)?;
+            buffer.truncate(n);
+            Ok(buffer)
         }
 
+        pub fn write_interrupt(&mut self, endpoint: u8, data: &[u8], timeout: Duration)
+            -> Result<usize, Box<dyn std::error::Error>>
+        {
+            let mut buffer = data.to_vec();
+            self.transport.interrupt_transfer(endpoint & !ENDPOINT_IN, &mut buffer, timeout)
+        }
+
+        // request_type-ren 7. bit-ak norabidea ematen du (in bada data betetzen da)
         pub fn control_transfer(
             &mut self,
             request_type: u8,
@@ -160,9 +1333,138 @@ pub mod usb {
             data: &mut [u8],
             timeout: Duration,
         ) -> Result<usize, Box<dyn std::error::Error>> {
-            let _timeout_ms = timeout.as_millis();
-            println!("control transfer: req={:#04x} val={:#06x}", request, value);
-            Ok(0)
+            self.transport.control_transfer(request_type, request, value, index, data, timeout)
+        }
+    }
+
+    // hid class eskaerak (hid 1.11, 7.2)
+    const HID_GET_REPORT: u8 = 0x01;
+    const HID_SET_REPORT: u8 = 0x09;
+    const HID_REPORT_FEATURE: u16 = 0x03;
+    const HID_DESCRIPTOR_REPORT: u16 = 0x22;
+    const USB_GET_DESCRIPTOR: u8 = 0x06;
+    // class | interface, norabidearen bit-a gehituta
+    const REQUEST_CLASS_INTERFACE: u8 = 0x21;
+    const REQUEST_STANDARD_INTERFACE: u8 = 0x01;
+    const BUS_USB: u32 = 0x03;
+
+    // hid interface bat usbfs bidez, hidraw-ek egiten duena kernel driver-ik gabe:
+    // reportak interrupt endpoint-etatik, feature reportak control transfer-ekin
+    pub struct UsbHidTransport<T: UsbTransport> {
+        device: UsbDevice<T>,
+        interface: u8,
+        in_endpoint: u8,
+        out_endpoint: Option<u8>,
+        timeout: Duration,
+    }
+
+    impl<T: UsbTransport> UsbHidTransport<T> {
+        // interface-a hartzen du (usbhid askatuta)
+        pub fn new(
+            mut device: UsbDevice<T>,
+            interface: u8,
+            in_endpoint: u8,
+            out_endpoint: Option<u8>,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            device.claim_interface(interface)?;
+            Ok(Self {
+                device,
+                interface,
+                in_endpoint,
+                out_endpoint,
+                timeout: Duration::from_millis(1000),
+            })
+        }
+
+        pub fn set_timeout(&mut self, timeout: Duration) {
+            self.timeout = timeout;
+        }
+
+        pub fn device(&self) -> &UsbDevice<T> {
+            &self.device
+        }
+    }
+
+    impl<T: UsbTransport + Send> super::DeviceTransport for UsbHidTransport<T> {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            let data = self.device.read_interrupt(self.in_endpoint, buffer.len(), self.timeout)?;
+            buffer[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+
+        // out endpoint-ik ez badago SET_REPORT (output) control transfer bidez
+        fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            match self.out_endpoint {
+                Some(endpoint) => self.device.write_interrupt(endpoint, data, self.timeout),
+                None => {
+                    let report_id = data.first().copied().unwrap_or(0) as u16;
+                    let mut buffer = data.to_vec();
+                    self.device.control_transfer(
+                        REQUEST_CLASS_INTERFACE,
+                        HID_SET_REPORT,
+                        (0x02 << 8) | report_id,
+                        self.interface as u16,
+                        &mut buffer,
+                        self.timeout,
+                    )
+                }
+            }
+        }
+
+        fn get_feature_report(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let mut buffer = vec![0u8; len.max(1)];
+            let n = self.device.control_transfer(
+                REQUEST_CLASS_INTERFACE | ENDPOINT_IN,
+                HID_GET_REPORT,
+                (HID_REPORT_FEATURE << 8) | report_id as u16,
+                self.interface as u16,
+                &mut buffer,
+                self.timeout,
+            )?;
+            buffer.truncate(n);
+            Ok(buffer)
+        }
+
+        fn send_feature_report(&mut self, data: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+            if data.is_empty() {
+                return Err("feature report hutsa".into());
+            }
+            let mut buffer = data.to_vec();
+            self.device.control_transfer(
+                REQUEST_CLASS_INTERFACE,
+                HID_SET_REPORT,
+                (HID_REPORT_FEATURE << 8) | data[0] as u16,
+                self.interface as u16,
+                &mut buffer,
+                self.timeout,
+            )
+        }
+
+        fn get_raw_info(&mut self) -> Result<super::HidRawInfo, Box<dyn std::error::Error>> {
+            let info = self.device.info().ok_or("usb gailuaren informaziorik ez")?;
+            Ok(super::HidRawInfo {
+                bus_type: BUS_USB,
+                vendor_id: info.vendor_id,
+                product_id: info.product_id,
+            })
+        }
+
+        fn get_report_descriptor(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let mut buffer = vec![0u8; 4096];
+            let n = self.device.control_transfer(
+                REQUEST_STANDARD_INTERFACE | ENDPOINT_IN,
+                USB_GET_DESCRIPTOR,
+                HID_DESCRIPTOR_REPORT << 8,
+                self.interface as u16,
+                &mut buffer,
+                self.timeout,
+            )?;
+            buffer.truncate(n);
+            Ok(buffer)
+        }
+
+        fn ioctl(&mut self, _request: u64, _data: &mut [u8]) -> Result<i32, Box<dyn std::error::Error>> {
+            Err("ioctl ez dago usbfs hid transport-ean".into())
         }
     }
 }
@@ -211,4 +1513,363 @@ pub mod helpers {
             radians[2].to_degrees(),
         ]
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sysfs faltsua direktorio tenporal batean, test bakoitzak berea
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("librevr-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        // hidrawN/device symlink bat da hid gailura, haren gurasoa usb interface-a
+        fn hidraw(&self, node: &str, usb_interface: &str, interface: u8, hid: &str, uevent: &str) {
+            let interface_dir = format!("sys/devices/pci0000:00/0000:00:14.0/usb1/1-4/{}", usb_interface);
+            self.write(&format!("{}/bInterfaceNumber", interface_dir), &format!("{:02x}\n", interface));
+            self.write(&format!("{}/{}/uevent", interface_dir, hid), uevent);
+
+            let class_dir = self.root.join("sys/class/hidraw").join(node);
+            fs::create_dir_all(&class_dir).unwrap();
+            std::os::unix::fs::symlink(
+                self.root.join(&interface_dir).join(hid),
+                class_dir.join("device"),
+            )
+            .unwrap();
+        }
+
+        fn usb(&self, name: &str, attrs: &[(&str, &str)]) {
+            for (attr, value) in attrs {
+                self.write(&format!("sys/bus/usb/devices/{}/{}", name, attr), &format!("{}\n", value));
+            }
+        }
+
+        fn path(&self, path: &str) -> String {
+            self.root.join(path).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn sysfs_hidraw_devices() {
+        let sysfs = FakeSysfs::new("sysfs-hidraw");
+        sysfs.hidraw(
+            "hidraw1",
+            "1-4:1.2",
+            2,
+            "0003:28DE:2000.0006",
+            "DRIVER=hid-generic\nHID_ID=0003:000028DE:00002000\nHID_NAME=Valve Corporation Lighthouse FPGA RX\nHID_UNIQ=\n",
+        );
+        sysfs.hidraw(
+            "hidraw0",
+            "1-4:1.0",
+            0,
+            "0003:0BB4:0342.0005",
+            "HID_ID=0003:00000BB4:00000342\nHID_NAME=HTC Vive Pro 2\nHID_UNIQ=LHR-0A1B2C3D\n",
+        );
+        // logitech receiver, ez da vive
+        sysfs.hidraw(
+            "hidraw2",
+            "1-4:1.1",
+            1,
+            "0003:046D:C52B.0007",
+            "HID_ID=0003:0000046D:0000C52B\nHID_NAME=Logitech USB Receiver\n",
+        );
+
+        let scanner = SysfsScanner::with_root(&sysfs.root);
+        let vive_pro = DeviceInfo {
+            vendor_id: 0x0bb4,
+            product_id: 0x0342,
+            device_path: sysfs.path("dev/hidraw0"),
+            name: Some("HTC Vive Pro 2".to_string()),
+            serial: Some("LHR-0A1B2C3D".to_string()),
+            interface: Some(0),
+        };
+        let lighthouse = DeviceInfo {
+            vendor_id: 0x28de,
+            product_id: 0x2000,
+            device_path: sysfs.path("dev/hidraw1"),
+            name: Some("Valve Corporation Lighthouse FPGA RX".to_string()),
+            serial: None,
+            interface: Some(2),
+        };
+        assert_eq!(scanner.find_hidraw_devices(), vec![vive_pro.clone(), lighthouse]);
+        assert_eq!(scanner.hidraw_info("/dev/hidraw0"), Some(vive_pro));
+        assert_eq!(scanner.hidraw_info("/dev/hidraw9"), None);
+
+        // sysfs-ik gabe ez dago gailurik
+        assert!(SysfsScanner::with_root(sysfs.root.join("missing")).find_hidraw_devices().is_empty());
+    }
+
+    #[test]
+    fn sysfs_usb_devices() {
+        let sysfs = FakeSysfs::new("sysfs-usb");
+        sysfs.usb(
+            "1-4",
+            &[
+                ("idVendor", "0bb4"),
+                ("idProduct", "0342"),
+                ("busnum", "1"),
+                ("devnum", "5"),
+                ("product", "HTC Vive Pro 2"),
+                ("serial", "LHR-0A1B2C3D"),
+            ],
+        );
+        sysfs.usb("3-2", &[("idVendor", "28de"), ("idProduct", "2300"), ("busnum", "3"), ("devnum", "12")]);
+        // interface direktorioa, ez du idVendor
+        sysfs.usb("1-4:1.0", &[("bInterfaceNumber", "00"), ("bInterfaceClass", "03")]);
+        // ez da vive
+        sysfs.usb("2-1", &[("idVendor", "046d"), ("idProduct", "c52b"), ("busnum", "2"), ("devnum", "3")]);
+
+        let devices = SysfsScanner::with_root(&sysfs.root).find_usb_devices();
+        assert_eq!(
+            devices,
+            vec![
+                DeviceInfo {
+                    vendor_id: 0x0bb4,
+                    product_id: 0x0342,
+                    device_path: sysfs.path("dev/bus/usb/001/005"),
+                    name: Some("HTC Vive Pro 2".to_string()),
+                    serial: Some("LHR-0A1B2C3D".to_string()),
+                    interface: None,
+                },
+                DeviceInfo {
+                    vendor_id: 0x28de,
+                    product_id: 0x2300,
+                    device_path: sysfs.path("dev/bus/usb/003/012"),
+                    name: None,
+                    serial: None,
+                    interface: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn usb_claim_detaches_kernel_driver_first() {
+        let mut mock = usb::MockTransport::new();
+        mock.kernel_drivers = vec![0];
+        let mut device = usb::UsbDevice::with_transport(mock);
+
+        device.claim_interface(0).unwrap();
+        device.claim_interface(1).unwrap();
+        device.release_interface(0).unwrap();
+        assert_eq!(
+            device.transport().calls,
+            vec![
+                usb::MockCall::Detach(0),
+                usb::MockCall::Claim(0),
+                usb::MockCall::Detach(1),
+                usb::MockCall::Claim(1),
+                usb::MockCall::Release(0),
+            ]
+        );
+        assert!(device.transport().kernel_drivers.is_empty());
+    }
+
+    #[test]
+    fn usb_bulk_endpoint_direction() {
+        let timeout = std::time::Duration::from_millis(10);
+        let mut device = usb::UsbDevice::with_transport(usb::MockTransport::new());
+        device.transport_mut().push_response(&[0xaa, 0xbb, 0xcc]);
+
+        // in bit-a beti gehitzen da irakurtzean eta kentzen idaztean
+        assert_eq!(device.read_bulk(0x01, 8, timeout).unwrap(), [0xaa, 0xbb, 0xcc]);
+        assert_eq!(device.write_bulk(0x81, &[1, 2], timeout).unwrap(), 2);
+        assert_eq!(
+            device.transport().calls,
+            vec![
+                usb::MockCall::Bulk { endpoint: 0x81, data: vec![0xaa, 0xbb, 0xcc, 0, 0, 0, 0, 0] },
+                usb::MockCall::Bulk { endpoint: 0x01, data: vec![1, 2] },
+            ]
+        );
+    }
+
+    #[test]
+    fn usb_timeout_is_an_error() {
+        let timeout = std::time::Duration::from_millis(10);
+        let mut device = usb::UsbDevice::with_transport(usb::MockTransport::new());
+        device.transport_mut().push_timeout();
+        device.transport_mut().push_response(&[0x20]);
+
+        assert!(device.read_interrupt(0x81, 64, timeout).is_err());
+        // ilarako hurrengoa ez da galtzen
+        assert_eq!(device.read_interrupt(0x81, 64, timeout).unwrap(), [0x20]);
+        // erantzunik gabe ere errorea
+        assert!(device.read_bulk(0x82, 64, timeout).is_err());
+    }
+
+    #[test]
+    fn usb_hid_feature_reports() {
+        let mut mock = usb::MockTransport::new();
+        mock.push_response(&[0x10, 0x01, 0x02]);
+        let device = usb::UsbDevice::with_transport(mock);
+        let mut hid = usb::UsbHidTransport::new(device, 0, 0x81, None).unwrap();
+
+        assert_eq!(hid.get_feature_report(0x10, 64).unwrap(), [0x10, 0x01, 0x02]);
+        assert_eq!(hid.send_feature_report(&[0x04, 0x78, 0x29]).unwrap(), 3);
+        // out endpoint-ik gabe SET_REPORT output
+        assert_eq!(hid.write(&[0x05, 0xff]).unwrap(), 2);
+        assert!(hid.send_feature_report(&[]).is_err());
+
+        let calls = &hid.device().transport().calls;
+        assert_eq!(calls[..2], [usb::MockCall::Detach(0), usb::MockCall::Claim(0)]);
+        let usb::MockCall::Control { request_type, request, value, index, data } = &calls[2] else {
+            panic!("expected GET_REPORT, got {:?}", calls[2]);
+        };
+        // class | interface | in, GET_REPORT, feature << 8 | id
+        assert_eq!((*request_type, *request, *value, *index), (0xa1, 0x01, 0x0310, 0));
+        assert_eq!(data.len(), 64);
+        assert_eq!(
+            calls[3..],
+            [
+                // class | interface | out, SET_REPORT, feature << 8 | id
+                usb::MockCall::Control { request_type: 0x21, request: 0x09, value: 0x0304, index: 0, data: vec![0x04, 0x78, 0x29] },
+                // output << 8 | id
+                usb::MockCall::Control { request_type: 0x21, request: 0x09, value: 0x0205, index: 0, data: vec![0x05, 0xff] },
+            ]
+        );
+    }
+
+    // grabaketa memorian, Write + Send + 'static behar da
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture_round_trip() {
+        let mut mock = usb::MockTransport::new();
+        mock.push_response(&[0x10, 0xaa, 0xbb]);
+        mock.push_response(&[0x20, 0x01, 0x02, 0x03]);
+        mock.push_timeout();
+        let hid = usb::UsbHidTransport::new(usb::UsbDevice::with_transport(mock), 0, 0x81, None).unwrap();
+
+        let device = DeviceInfo {
+            vendor_id: 0x0bb4,
+            product_id: 0x0342,
+            device_path: "/dev/hidraw0".to_string(),
+            name: None,
+            serial: Some("LHR-0A1B2C3D".to_string()),
+            interface: Some(0),
+        };
+        let buffer = SharedBuffer::default();
+        capture::write_header(&mut buffer.clone(), &device).unwrap();
+        let mut recorder = capture::RecordingTransport::new(Box::new(hid), buffer.clone());
+
+        let mut report = [0u8; 64];
+        assert_eq!(recorder.get_feature_report(0x10, 64).unwrap(), [0x10, 0xaa, 0xbb]);
+        assert_eq!(recorder.send_feature_report(&[0x04, 0x78, 0x29]).unwrap(), 3);
+        assert_eq!(recorder.read(&mut report).unwrap(), 4);
+        // mock-ak ez du gailuaren informaziorik, errorea ere grabatzen da
+        assert!(recorder.get_raw_info().is_err());
+        assert!(recorder.read(&mut report).is_err());
+        assert_eq!(recorder.entries(), 5);
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        let mut replay = capture::ReplayTransport::from_reader(&bytes[..], false).unwrap();
+        assert_eq!(replay.device_info(), &device);
+        assert_eq!(replay.remaining(), 5);
+
+        let mut replayed = [0u8; 64];
+        assert_eq!(replay.get_feature_report(0x10, 64).unwrap(), [0x10, 0xaa, 0xbb]);
+        // beste datu batzuk idazteak ez du huts egiten, zenbatu egiten da
+        assert_eq!(replay.send_feature_report(&[0x04, 0x78, 0x2a]).unwrap(), 3);
+        assert_eq!(replay.tx_mismatches(), 1);
+        assert_eq!(replay.read(&mut replayed).unwrap(), 4);
+        assert_eq!(replayed[..4], report[..4]);
+        assert!(replay.get_raw_info().is_err());
+        assert!(replay.read(&mut replayed).is_err());
+        assert_eq!(replay.remaining(), 0);
+        assert!(replay.read(&mut replayed).is_err());
+
+        // deiak grabaketaren ordenan ez badatoz
+        let mut replay = capture::ReplayTransport::from_reader(&bytes[..], false).unwrap();
+        let err = replay.read(&mut replayed).unwrap_err().to_string();
+        assert!(err.contains("desinkronizatuta"), "{}", err);
+        let mut replay = capture::ReplayTransport::from_reader(&bytes[..], false).unwrap();
+        assert!(replay.get_feature_report(0x11, 64).is_err());
+    }
+
+    #[test]
+    fn capture_rejects_bad_files() {
+        assert!(capture::ReplayTransport::from_reader(&b""[..], false).is_err());
+        let device = DeviceInfo {
+            vendor_id: 0x28de,
+            product_id: 0x2000,
+            device_path: "/dev/hidraw1".to_string(),
+            name: None,
+            serial: None,
+            interface: None,
+        };
+        let header = |version| serde_json::to_string(&capture::CaptureHeader { version, device: device.clone() }).unwrap();
+        assert!(capture::ReplayTransport::from_reader(header(capture::CAPTURE_VERSION).as_bytes(), false).is_ok());
+        assert!(capture::ReplayTransport::from_reader(header(2).as_bytes(), false).is_err());
+        let garbage = format!("{}\n{{\"time_us\":1,\"op\":\"nope\"}}\n", header(capture::CAPTURE_VERSION));
+        assert!(capture::ReplayTransport::from_reader(garbage.as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn capture_cut_off_mid_line() {
+        let mut mock = usb::MockTransport::new();
+        for sample in 0..3u8 {
+            mock.push_response(&[0x20, sample]);
+        }
+        let hid = usb::UsbHidTransport::new(usb::UsbDevice::with_transport(mock), 0, 0x81, None).unwrap();
+
+        // BufWriter start_recording-en bezala, sarrera bakoitzak berehala iritsi behar du
+        let device = DeviceInfo {
+            vendor_id: 0x28de,
+            product_id: 0x2000,
+            device_path: "/dev/hidraw0".to_string(),
+            name: None,
+            serial: None,
+            interface: Some(0),
+        };
+        let buffer = SharedBuffer::default();
+        let mut writer = std::io::BufWriter::new(buffer.clone());
+        capture::write_header(&mut writer, &device).unwrap();
+        let mut recorder = capture::RecordingTransport::new(Box::new(hid), writer);
+        let mut report = [0u8; 64];
+        for _ in 0..3 {
+            recorder.read(&mut report).unwrap();
+        }
+        let bytes = buffer.0.lock().unwrap().clone();
+        assert_eq!(capture::ReplayTransport::from_reader(&bytes[..], false).unwrap().remaining(), 3);
+
+        // ctrl-c azken lerroa idazten ari zela
+        let cut = bytes.len() - 10;
+        let mut replay = capture::ReplayTransport::from_reader(&bytes[..cut], false).unwrap();
+        assert_eq!(replay.remaining(), 2);
+        assert_eq!(replay.read(&mut report).unwrap(), 2);
+        assert_eq!(report[..2], [0x20, 0x00]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+// lighthouse v1 factory calibration (fcal)
+// each rotor has small mechanical errors that bend the swept plane,
+// without correcting them the decoded angles are 1-3 degrees off
+//
+// same model as libsurvive, for a point (x, y, z) in station space (looking down -z):
+//   tx = x / -z, ty = y / -z
+//   measured_h = atan(tx) - phase_h - tan(tilt_h) * ty - curve_h * ty^2 - gibmag_h * sin(atan(tx) + gibphase_h)
+//   measured_v = atan(ty) - phase_v - tan(tilt_v) * tx - curve_v * tx^2 - gibmag_v * sin(atan(ty) + gibphase_v)
+
+use std::fs;
+use serde::{Serialize, Deserialize};
+use crate::lighthouse_ootx::BaseStationInfo;
+
+// iterations for inverting the model, corrections are small so this converges fast
+const CORRECTION_ITERATIONS: usize = 8;
+
+// calibration of one sweep axis
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct AxisCalibration {
+    pub phase: f32,
+    pub tilt: f32,
+    pub curve: f32,
+    pub gibphase: f32,
+    pub gibmag: f32,
+}
+
+// calibration of a whole base station, serialized like the "fcal"
+// block of steamvr's lighthousedb.json ({"0": {...}, "1": {...}})
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct BaseStationCalibration {
+    #[serde(rename = "0")]
+    pub horizontal: AxisCalibration,
+    #[serde(rename = "1")]
+    pub vertical: AxisCalibration,
+}
+
+impl BaseStationCalibration {
+    pub fn from_ootx(info: &BaseStationInfo) -> Self {
+        let axis = |i: usize| AxisCalibration {
+            phase: info.fcal_phase[i],
+            tilt: info.fcal_tilt[i],
+            curve: info.fcal_curve[i],
+            gibphase: info.fcal_gibphase[i],
+            gibmag: info.fcal_gibmag[i],
+        };
+
+        Self {
+            horizontal: axis(0),
+            vertical: axis(1),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    // ideal angles [h, v] -> what the station actually sweeps
+    pub fn distort(&self, angles: [f32; 2]) -> [f32; 2] {
+        let t = [angles[0].tan(), angles[1].tan()];
+        [
+            angles[0] - axis_error(&self.horizontal, angles[0], t[1]),
+            angles[1] - axis_error(&self.vertical, angles[1], t[0]),
+        ]
+    }
+
+    // measured angles [h, v] -> ideal angles, inverse of distort
+    pub fn correct(&self, measured: [f32; 2]) -> [f32; 2] {
+        let mut angles = measured;
+        for _ in 0..CORRECTION_ITERATIONS {
+            let t = [angles[0].tan(), angles[1].tan()];
+            angles = [
+                measured[0] + axis_error(&self.horizontal, angles[0], t[1]),
+                measured[1] + axis_error(&self.vertical, angles[1], t[0]),
+            ];
+        }
+        angles
+    }
+}
+
+// error the rotor adds on one axis, opposite is tan() of the other axis angle
+fn axis_error(cal: &AxisCalibration, angle: f32, opposite: f32) -> f32 {
+    cal.phase
+        + cal.tilt.tan() * opposite
+        + cal.curve * opposite * opposite
+        + cal.gibmag * (angle + cal.gibphase).sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> BaseStationCalibration {
+        BaseStationCalibration {
+            horizontal: AxisCalibration {
+                phase: 0.0523,
+                tilt: -0.0042,
+                curve: 0.0018,
+                gibphase: 1.27,
+                gibmag: 0.0047,
+            },
+            vertical: AxisCalibration {
+                phase: -0.0310,
+                tilt: 0.0061,
+                curve: -0.0024,
+                gibphase: -2.13,
+                gibmag: 0.0052,
+            },
+        }
+    }
+
+    // (ideal [h, v], measured [h, v]) for station space points (0.3, -0.2, -2.0),
+    // (-0.8, 0.5, -3.1), (0.05, 0.9, -1.5) and (1.2, -0.7, -2.4), computed in double
+    // precision from the model at the top of this file. they pin the f32 code to that
+    // formula, they are not checked against libsurvive output
+    const REFERENCE: [([f32; 2], [f32; 2]); 4] = [
+        ([0.1488899, -0.09966865], [0.09150607, -0.06541811]),
+        ([-0.2525543, 0.1599131], [-0.3082223, 0.1974381]),
+        ([0.033321, 0.5404195], [-0.02163986, 0.5764179]),
+        ([0.4636476, -0.2837941], [0.4053317, -0.251785]),
+    ];
+
+    #[test]
+    fn distort_matches_double_precision_model() {
+        let cal = calibration();
+        for (ideal, measured) in REFERENCE {
+            let distorted = cal.distort(ideal);
+            for axis in 0..2 {
+                assert!(
+                    (distorted[axis] - measured[axis]).abs() < 1e-6,
+                    "{:?} -> {:?}, expected {:?}",
+                    ideal,
+                    distorted,
+                    measured
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn correct_inverts_distort() {
+        let cal = calibration();
+        for (ideal, measured) in REFERENCE {
+            let corrected = cal.correct(measured);
+            assert!((corrected[0] - ideal[0]).abs() < 1e-6, "{:?} vs {:?}", corrected, ideal);
+            assert!((corrected[1] - ideal[1]).abs() < 1e-6, "{:?} vs {:?}", corrected, ideal);
+        }
+
+        // a grid over the usable field of view
+        for h in -10..=10 {
+            for v in -10..=10 {
+                let angles = [h as f32 * 0.1, v as f32 * 0.1];
+                let round_trip = cal.correct(cal.distort(angles));
+                assert!((round_trip[0] - angles[0]).abs() < 1e-6, "{:?} -> {:?}", angles, round_trip);
+                assert!((round_trip[1] - angles[1]).abs() < 1e-6, "{:?} -> {:?}", angles, round_trip);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_calibration_is_identity() {
+        let cal = BaseStationCalibration::default();
+        assert_eq!(cal.distort([0.2, -0.4]), [0.2, -0.4]);
+        assert_eq!(cal.correct([0.2, -0.4]), [0.2, -0.4]);
+    }
+}
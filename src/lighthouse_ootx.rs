@@ -0,0 +1,443 @@
+// lighthouse v1 ootx (omnidirectional optical transmitter) decoder
+// every sync flash carries one data bit, accumulated per station into a packet
+//
+// frame layout on the wire:
+//   preamble  17 zero bits followed by a one
+//   length    u16 little endian (payload bytes)
+//   payload   padded to an even length
+//   crc32     u32 little endian over the unpadded payload
+// every 16 data bits are followed by a stuffing bit that must be 1,
+// so valid data never contains 17 zeros in a row
+
+pub const OOTX_PREAMBLE_ZEROS: u32 = 17;
+pub const OOTX_INFO_V6_LEN: usize = 33;
+const OOTX_MAX_PAYLOAD: usize = 64;
+
+// mode a/b/c switch on the back of the base station
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StationMode {
+    A,
+    B,
+    C,
+}
+
+impl StationMode {
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(StationMode::A),
+            1 => Some(StationMode::B),
+            2 => Some(StationMode::C),
+            _ => None,
+        }
+    }
+}
+
+// decoded base station info block (protocol version 6, same layout as libsurvive)
+//
+//   0-1    fw_version (low 6 bits protocol, high 10 bits firmware)
+//   2-5    id
+//   6-9    fcal phase [h, v]     half floats
+//   10-13  fcal tilt [h, v]
+//   14     sys unlock count
+//   15     hardware version
+//   16-19  fcal curve [h, v]
+//   20-22  accel dir x/y/z      i8
+//   23-26  fcal gibphase [h, v]
+//   27-30  fcal gibmag [h, v]
+//   31     mode
+//   32     fault flags
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseStationInfo {
+    pub protocol_version: u8,
+    pub firmware_version: u16,
+    pub id: u32,
+    pub fcal_phase: [f32; 2],
+    pub fcal_tilt: [f32; 2],
+    pub fcal_curve: [f32; 2],
+    pub fcal_gibphase: [f32; 2],
+    pub fcal_gibmag: [f32; 2],
+    pub unlock_count: u8,
+    pub hardware_version: u8,
+    pub accel_dir: [i8; 3],
+    pub mode: Option<StationMode>,
+    pub fault_flags: u8,
+}
+
+impl BaseStationInfo {
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < OOTX_INFO_V6_LEN {
+            return None;
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+        let f16_at = |i: usize| half_to_f32(u16_at(i));
+        let pair_at = |i: usize| [f16_at(i), f16_at(i + 2)];
+
+        let fw_version = u16_at(0);
+
+        Some(Self {
+            protocol_version: (fw_version & 0x3f) as u8,
+            firmware_version: fw_version >> 6,
+            id: u32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]]),
+            fcal_phase: pair_at(6),
+            fcal_tilt: pair_at(10),
+            unlock_count: payload[14],
+            hardware_version: payload[15],
+            fcal_curve: pair_at(16),
+            accel_dir: [payload[20] as i8, payload[21] as i8, payload[22] as i8],
+            fcal_gibphase: pair_at(23),
+            fcal_gibmag: pair_at(27),
+            mode: StationMode::from_raw(payload[31]),
+            fault_flags: payload[32],
+        })
+    }
+
+    // inverse of parse, fcal values are rounded to half floats
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(OOTX_INFO_V6_LEN);
+        let fw_version = (self.firmware_version << 6) | (self.protocol_version as u16 & 0x3f);
+        let pair = |out: &mut Vec<u8>, values: [f32; 2]| {
+            for value in values {
+                out.extend_from_slice(&f32_to_half(value).to_le_bytes());
+            }
+        };
+
+        out.extend_from_slice(&fw_version.to_le_bytes());
+        out.extend_from_slice(&self.id.to_le_bytes());
+        pair(&mut out, self.fcal_phase);
+        pair(&mut out, self.fcal_tilt);
+        out.push(self.unlock_count);
+        out.push(self.hardware_version);
+        pair(&mut out, self.fcal_curve);
+        out.extend(self.accel_dir.iter().map(|&a| a as u8));
+        pair(&mut out, self.fcal_gibphase);
+        pair(&mut out, self.fcal_gibmag);
+        out.push(match self.mode {
+            Some(StationMode::A) | None => 0,
+            Some(StationMode::B) => 1,
+            Some(StationMode::C) => 2,
+        });
+        out.push(self.fault_flags);
+        out
+    }
+}
+
+// wrap a payload into an ootx frame (preamble, length, stuffing bits, crc)
+pub fn encode_frame(payload: &[u8]) -> Vec<bool> {
+    let mut bytes = (payload.len() as u16).to_le_bytes().to_vec();
+    bytes.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes.extend_from_slice(&crc32(payload).to_le_bytes());
+
+    let mut bits = vec![false; OOTX_PREAMBLE_ZEROS as usize];
+    bits.push(true);
+    for word in bytes.chunks(2) {
+        for byte in word {
+            bits.extend((0..8).rev().map(|i| (byte >> i) & 1 == 1));
+        }
+        bits.push(true);
+    }
+    bits
+}
+
+// bit accumulator for one station
+pub struct OotxDecoder {
+    zero_run: u32,
+    synced: bool,
+    word: u16,
+    bits_in_word: u32,
+    bytes: Vec<u8>,
+    packets_ok: u64,
+    crc_errors: u64,
+}
+
+impl OotxDecoder {
+    pub fn new() -> Self {
+        Self {
+            zero_run: 0,
+            synced: false,
+            word: 0,
+            bits_in_word: 0,
+            bytes: Vec::with_capacity(OOTX_MAX_PAYLOAD + 6),
+            packets_ok: 0,
+            crc_errors: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // feed one bit, returns the payload once a packet passes the crc check
+    pub fn push_bit(&mut self, bit: bool) -> Option<Vec<u8>> {
+        // the preamble can start at any time, always look for it
+        if !bit {
+            self.zero_run += 1;
+        } else {
+            let preamble = self.zero_run >= OOTX_PREAMBLE_ZEROS;
+            self.zero_run = 0;
+            if preamble {
+                self.start_packet();
+                return None;
+            }
+        }
+
+        if !self.synced {
+            return None;
+        }
+
+        // stuffing bit after every 16 data bits
+        if self.bits_in_word == 16 {
+            self.bits_in_word = 0;
+            if !bit {
+                self.synced = false;
+            }
+            return None;
+        }
+
+        self.word = (self.word << 1) | bit as u16;
+        self.bits_in_word += 1;
+        if self.bits_in_word < 16 {
+            return None;
+        }
+
+        self.bytes.extend_from_slice(&self.word.to_be_bytes());
+        self.word = 0;
+        self.finish_packet()
+    }
+
+    fn start_packet(&mut self) {
+        self.synced = true;
+        self.word = 0;
+        self.bits_in_word = 0;
+        self.bytes.clear();
+    }
+
+    fn finish_packet(&mut self) -> Option<Vec<u8>> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+
+        let len = u16::from_le_bytes([self.bytes[0], self.bytes[1]]) as usize;
+        if len > OOTX_MAX_PAYLOAD {
+            self.synced = false;
+            return None;
+        }
+
+        let padded = len + (len & 1);
+        let total = 2 + padded + 4;
+        if self.bytes.len() < total {
+            return None;
+        }
+
+        self.synced = false;
+        let payload = &self.bytes[2..2 + len];
+        let crc_bytes = &self.bytes[2 + padded..total];
+        let crc = u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+
+        if crc32(payload) != crc {
+            self.crc_errors += 1;
+            return None;
+        }
+
+        self.packets_ok += 1;
+        Some(payload.to_vec())
+    }
+
+    pub fn packets_ok(&self) -> u64 {
+        self.packets_ok
+    }
+
+    pub fn crc_errors(&self) -> u64 {
+        self.crc_errors
+    }
+}
+
+// crc-32 (ieee 802.3, same as zlib)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// ieee 754 half precision to f32
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// f32 to ieee 754 half precision (round to nearest)
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if (bits & 0x7fff_ffff) > 0x7f80_0000 {
+        return sign | 0x7e00;
+    }
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = (full >> shift) + ((full >> (shift - 1)) & 1);
+        return sign | half as u16;
+    }
+
+    let half = ((exponent as u32) << 10 | (mantissa >> 13)) + ((mantissa >> 12) & 1);
+    sign | half as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v6 info block: firmware 9, id 0x12345678, mode b
+    const PACKET: [u8; OOTX_INFO_V6_LEN] = [
+        0x46, 0x02, 0x78, 0x56, 0x34, 0x12, // fw_version, id
+        0x00, 0x3c, 0x00, 0xbc, // phase 1.0, -1.0
+        0x00, 0x38, 0x00, 0x00, // tilt 0.5, 0.0
+        0x03, 0x09, // unlock count, hardware version
+        0x00, 0x34, 0x01, 0x00, // curve 0.25, smallest subnormal
+        0x00, 0x7f, 0x80, // accel dir 0, 127, -128
+        0x48, 0x42, 0x00, 0xc0, // gibphase 3.140625, -2.0
+        0x00, 0x14, 0xff, 0x7b, // gibmag 2^-10, 65504
+        0x01, 0x00, // mode, fault flags
+    ];
+
+    fn info() -> BaseStationInfo {
+        BaseStationInfo::parse(&PACKET).unwrap()
+    }
+
+    fn decode(decoder: &mut OotxDecoder, bits: &[bool]) -> Vec<Vec<u8>> {
+        bits.iter().filter_map(|&bit| decoder.push_bit(bit)).collect()
+    }
+
+    // stuffing bits sit after the preamble one and every 16 data bits
+    fn stuffing_positions(frame: &[bool]) -> impl Iterator<Item = usize> + '_ {
+        (OOTX_PREAMBLE_ZEROS as usize + 1 + 16..frame.len()).step_by(17)
+    }
+
+    #[test]
+    fn parses_a_known_packet() {
+        let info = info();
+        assert_eq!(info.protocol_version, 6);
+        assert_eq!(info.firmware_version, 9);
+        assert_eq!(info.id, 0x1234_5678);
+        assert_eq!(info.fcal_phase, [1.0, -1.0]);
+        assert_eq!(info.fcal_tilt, [0.5, 0.0]);
+        assert_eq!(info.unlock_count, 3);
+        assert_eq!(info.hardware_version, 9);
+        assert_eq!(info.fcal_curve, [0.25, 2f32.powi(-24)]);
+        assert_eq!(info.accel_dir, [0, 127, -128]);
+        assert_eq!(info.fcal_gibphase, [3.140625, -2.0]);
+        assert_eq!(info.fcal_gibmag, [2f32.powi(-10), 65504.0]);
+        assert_eq!(info.mode, Some(StationMode::B));
+        assert_eq!(info.fault_flags, 0);
+
+        assert_eq!(info.to_bytes(), PACKET);
+        assert_eq!(BaseStationInfo::parse(&PACKET[..OOTX_INFO_V6_LEN - 1]), None);
+    }
+
+    #[test]
+    fn encoded_frame_decodes_after_noise() {
+        let payload = info().to_bytes();
+        let frame = encode_frame(&payload);
+        assert!(stuffing_positions(&frame).all(|i| frame[i]));
+
+        // the decoder can start anywhere in the bit stream
+        let mut bits = vec![true, false, true, true, false, false, true];
+        bits.extend(&frame);
+        let mut decoder = OotxDecoder::new();
+        assert_eq!(decode(&mut decoder, &bits), vec![payload]);
+        assert_eq!(decoder.packets_ok(), 1);
+        assert_eq!(decoder.crc_errors(), 0);
+
+        // odd length payloads are padded on the wire only
+        let odd = [0xa5, 0x00, 0x5a];
+        assert_eq!(decode(&mut decoder, &encode_frame(&odd)), vec![odd.to_vec()]);
+    }
+
+    #[test]
+    fn rejects_a_crc_mismatch() {
+        let frame = encode_frame(&PACKET);
+        let mut corrupted = frame.clone();
+        // first bit of the payload, after preamble, length and its stuffing bit
+        let payload_bit = OOTX_PREAMBLE_ZEROS as usize + 1 + 17;
+        corrupted[payload_bit] = !corrupted[payload_bit];
+
+        let mut decoder = OotxDecoder::new();
+        assert!(decode(&mut decoder, &corrupted).is_empty());
+        assert_eq!(decoder.crc_errors(), 1);
+        assert_eq!(decoder.packets_ok(), 0);
+
+        // the next clean frame still gets through
+        assert_eq!(decode(&mut decoder, &frame), vec![PACKET.to_vec()]);
+    }
+
+    #[test]
+    fn missing_stuffing_bit_resyncs_on_the_next_preamble() {
+        let frame = encode_frame(&PACKET);
+        let mut corrupted = frame.clone();
+        let third = stuffing_positions(&frame).nth(2).unwrap();
+        corrupted[third] = false;
+
+        let mut decoder = OotxDecoder::new();
+        let mut bits = corrupted;
+        bits.extend(&frame);
+        assert_eq!(decode(&mut decoder, &bits), vec![PACKET.to_vec()]);
+        // dropped at the stuffing bit, never reached the crc check
+        assert_eq!(decoder.crc_errors(), 0);
+        assert_eq!(decoder.packets_ok(), 1);
+    }
+
+    #[test]
+    fn half_float_edge_cases() {
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x03ff), 1023.0 * 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x0400), 2f32.powi(-14));
+        assert_eq!(half_to_f32(0x7bff), 65504.0);
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(half_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+        assert!(half_to_f32(0x8000).is_sign_negative());
+
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(-2f32.powi(-24)), 0x8001);
+        // half way to the smallest subnormal rounds up, below that is zero
+        assert_eq!(f32_to_half(2f32.powi(-25)), 0x0001);
+        assert_eq!(f32_to_half(2f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(1.0e6), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        assert!(half_to_f32(f32_to_half(f32::NAN)).is_nan());
+
+        // every finite half survives the round trip
+        for half in (0..0x7c00).chain(0x8000..0xfc00) {
+            assert_eq!(f32_to_half(half_to_f32(half)), half, "{:#06x}", half);
+        }
+    }
+}
@@ -0,0 +1,713 @@
+// 6dof pose solver for a tracked object (headset, controller, tracker)
+// epnp gives an initial guess from one base station, levenberg-marquardt then
+// refines it against every angle observation from one or two stations
+//
+// internally everything runs in f64, the lighthouse angles are tiny and f32
+// normal equations lose too much precision
+
+use nalgebra::{
+    DMatrix, DVector, Isometry3, Matrix3, Point3, Rotation3, SymmetricEigen, Translation3,
+    UnitQuaternion, Vector2, Vector3, Vector6,
+};
+use crate::lighthouse_tracking::BaseStation;
+
+// epnp needs at least 4 points, lm needs more residuals than parameters
+pub const MIN_SENSORS_EPNP: usize = 4;
+const LM_MAX_ITERATIONS: usize = 30;
+const LM_JACOBIAN_STEP: f64 = 1e-7;
+// sensor normals pointing this far away from their station mean a bad solution
+const BACKFACING_DOT: f32 = -0.2;
+// a refined pose fitting worse than this is stuck in a wrong minimum (rms radians)
+const MAX_REPROJECTION_ERROR: f32 = 0.005;
+
+// one photodiode in the device frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorModel {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+// all photodiodes of a device, index = sensor id
+#[derive(Debug, Clone, Default)]
+pub struct SensorConstellation {
+    pub sensors: Vec<SensorModel>,
+}
+
+impl SensorConstellation {
+    pub fn new(sensors: Vec<SensorModel>) -> Self {
+        Self { sensors }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sensors.is_empty()
+    }
+
+    pub fn get(&self, sensor_id: u8) -> Option<&SensorModel> {
+        self.sensors.get(sensor_id as usize)
+    }
+}
+
+// fcal corrected [horizontal, vertical] angles of one sensor seen from one station
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AngleObservation {
+    pub sensor_id: u8,
+    pub station: usize,
+    pub angles: [f32; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoseSolution {
+    // world_from_device
+    pub pose: Isometry3<f32>,
+    // rms angle error over all observations (radians)
+    pub reprojection_error: f32,
+    pub observations: usize,
+    pub stations: usize,
+    pub iterations: usize,
+}
+
+pub struct PoseSolver {
+    constellation: SensorConstellation,
+}
+
+impl PoseSolver {
+    pub fn new(constellation: SensorConstellation) -> Self {
+        Self { constellation }
+    }
+
+    pub fn constellation(&self) -> &SensorConstellation {
+        &self.constellation
+    }
+
+    // solve from scratch, epnp on the station that sees the most sensors
+    pub fn solve(
+        &self,
+        stations: &[BaseStation],
+        observations: &[AngleObservation],
+    ) -> Option<PoseSolution> {
+        let observations = self.usable(stations, observations);
+
+        let mut per_station = vec![0usize; stations.len()];
+        for obs in &observations {
+            per_station[obs.station] += 1;
+        }
+        let (best_station, &count) = per_station
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, count)| **count)?;
+        if count < MIN_SENSORS_EPNP {
+            return None;
+        }
+
+        let guess = self.epnp_guess(&stations[best_station], best_station, &observations)?;
+        self.refine(stations, &observations, guess)
+    }
+
+    // refine a previous pose, falls back to epnp when it does not converge
+    pub fn solve_with_guess(
+        &self,
+        stations: &[BaseStation],
+        observations: &[AngleObservation],
+        guess: &Isometry3<f32>,
+    ) -> Option<PoseSolution> {
+        let usable = self.usable(stations, observations);
+        if usable.len() < MIN_SENSORS_EPNP {
+            return None;
+        }
+
+        let guess = to_f64(guess);
+        self.refine(stations, &usable, guess)
+            .or_else(|| self.solve(stations, observations))
+    }
+
+    // reprojection of a device pose into one station, [h, v] per sensor
+    pub fn project(
+        &self,
+        station: &BaseStation,
+        pose: &Isometry3<f32>,
+        sensor_id: u8,
+    ) -> Option<[f32; 2]> {
+        let sensor = self.constellation.get(sensor_id)?;
+        let world = pose * sensor.position;
+        let local = station.orientation.inverse() * (world - station.position);
+        if local.z >= 0.0 {
+            return None;
+        }
+        Some([local.x.atan2(-local.z), local.y.atan2(-local.z)])
+    }
+
+    fn usable(&self, stations: &[BaseStation], observations: &[AngleObservation]) -> Vec<AngleObservation> {
+        observations
+            .iter()
+            .filter(|o| o.station < stations.len() && self.constellation.get(o.sensor_id).is_some())
+            .copied()
+            .collect()
+    }
+
+    // epnp in a camera frame looking down +z (station frame rotated 180 deg about x)
+    fn epnp_guess(
+        &self,
+        station: &BaseStation,
+        station_index: usize,
+        observations: &[AngleObservation],
+    ) -> Option<Isometry3<f64>> {
+        let mut points = Vec::new();
+        let mut image = Vec::new();
+        for obs in observations.iter().filter(|o| o.station == station_index) {
+            let sensor = self.constellation.get(obs.sensor_id)?;
+            points.push(sensor.position.coords.cast::<f64>());
+            image.push(Vector2::new(
+                (obs.angles[0] as f64).tan(),
+                -(obs.angles[1] as f64).tan(),
+            ));
+        }
+
+        let (rotation, translation) = epnp(&points, &image)?;
+        let cam_from_device = Isometry3::from_parts(
+            Translation3::from(translation),
+            UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation)),
+        );
+
+        // cam and station frames differ by a 180 deg rotation about x
+        let flip = Isometry3::from_parts(
+            Translation3::identity(),
+            UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI),
+        );
+        Some(station_pose(station) * flip * cam_from_device)
+    }
+
+    // levenberg-marquardt over (rotation vector, translation) in the world frame
+    fn refine(
+        &self,
+        stations: &[BaseStation],
+        observations: &[AngleObservation],
+        guess: Isometry3<f64>,
+    ) -> Option<PoseSolution> {
+        let station_poses: Vec<Isometry3<f64>> = stations.iter().map(station_pose).collect();
+        let sensors: Vec<Point3<f64>> = observations
+            .iter()
+            .map(|o| self.constellation.sensors[o.sensor_id as usize].position.cast::<f64>())
+            .collect();
+
+        let residuals = |pose: &Isometry3<f64>| -> DVector<f64> {
+            let mut r = DVector::zeros(observations.len() * 2);
+            for (i, obs) in observations.iter().enumerate() {
+                let local = station_poses[obs.station].inverse_transform_point(&(pose * sensors[i]));
+                r[2 * i] = local.x.atan2(-local.z) - obs.angles[0] as f64;
+                r[2 * i + 1] = local.y.atan2(-local.z) - obs.angles[1] as f64;
+            }
+            r
+        };
+
+        let fit = levenberg_marquardt(guess, 6, residuals, |pose, delta| {
+            apply_delta(pose, &Vector6::from_iterator(delta.iter().copied()))
+        });
+
+        let reprojection_error = fit.rms_error() as f32;
+        if !fit.converged || reprojection_error > MAX_REPROJECTION_ERROR {
+            return None;
+        }
+
+        let pose = to_f32(&fit.params);
+        if self.backfacing(stations, observations, &pose) {
+            return None;
+        }
+
+        let mut used_stations: Vec<usize> = observations.iter().map(|o| o.station).collect();
+        used_stations.sort_unstable();
+        used_stations.dedup();
+
+        Some(PoseSolution {
+            pose,
+            reprojection_error,
+            observations: observations.len(),
+            stations: used_stations.len(),
+            iterations: fit.iterations,
+        })
+    }
+
+    // true if an observed sensor points clearly away from the station that saw it
+    fn backfacing(&self, stations: &[BaseStation], observations: &[AngleObservation], pose: &Isometry3<f32>) -> bool {
+        observations.iter().any(|obs| {
+            let sensor = &self.constellation.sensors[obs.sensor_id as usize];
+            let normal = pose.rotation * sensor.normal;
+            if normal.norm() < 1e-6 {
+                return false;
+            }
+            let to_station = (stations[obs.station].position - pose * sensor.position).normalize();
+            normal.normalize().dot(&to_station) < BACKFACING_DOT
+        })
+    }
+}
+
+pub struct LmFit<P> {
+    pub params: P,
+    pub cost: f64,
+    pub residuals: usize,
+    pub iterations: usize,
+    // false if LM_MAX_ITERATIONS ran out while the cost was still dropping
+    pub converged: bool,
+}
+
+impl<P> LmFit<P> {
+    // rms of the final residual vector
+    pub fn rms_error(&self) -> f64 {
+        (self.cost / self.residuals.max(1) as f64).sqrt()
+    }
+}
+
+// levenberg-marquardt with a numerical (central difference) jacobian
+// `apply` maps the current parameters plus a `dims` sized delta to new parameters,
+// so rotations can be updated on the manifold instead of additively
+pub fn levenberg_marquardt<P, R, A>(initial: P, dims: usize, residuals: R, apply: A) -> LmFit<P>
+where
+    R: Fn(&P) -> DVector<f64>,
+    A: Fn(&P, &DVector<f64>) -> P,
+{
+    let mut params = initial;
+    let mut r = residuals(&params);
+    let mut cost = r.norm_squared();
+    let mut lambda = 1e-3;
+    let mut iterations = 0;
+    let mut converged = false;
+
+    for _ in 0..LM_MAX_ITERATIONS {
+        iterations += 1;
+
+        let mut jacobian = DMatrix::zeros(r.len(), dims);
+        for k in 0..dims {
+            let mut step = DVector::zeros(dims);
+            step[k] = LM_JACOBIAN_STEP;
+            let plus = residuals(&apply(&params, &step));
+            let minus = residuals(&apply(&params, &(-step)));
+            jacobian.set_column(k, &((plus - minus) / (2.0 * LM_JACOBIAN_STEP)));
+        }
+
+        let jtj = jacobian.transpose() * &jacobian;
+        let jtr = jacobian.transpose() * &r;
+
+        let mut improved = false;
+        while lambda < 1e10 {
+            let mut damped = jtj.clone();
+            for k in 0..dims {
+                damped[(k, k)] += lambda * jtj[(k, k)].max(1e-12);
+            }
+            let Some(step) = damped.cholesky().map(|c| c.solve(&(-&jtr))) else {
+                lambda *= 10.0;
+                continue;
+            };
+
+            let candidate = apply(&params, &step);
+            let candidate_r = residuals(&candidate);
+            let candidate_cost = candidate_r.norm_squared();
+
+            if candidate_cost < cost {
+                let converged = step.norm() < 1e-10 || (cost - candidate_cost) < 1e-14 * cost;
+                params = candidate;
+                r = candidate_r;
+                cost = candidate_cost;
+                lambda = (lambda * 0.1).max(1e-12);
+                improved = !converged;
+                break;
+            }
+            lambda *= 10.0;
+        }
+
+        // either the step became negligible or no damping finds a lower cost,
+        // both mean we sit in a minimum
+        if !improved {
+            converged = true;
+            break;
+        }
+    }
+
+    LmFit {
+        params,
+        cost,
+        residuals: r.len(),
+        iterations,
+        converged,
+    }
+}
+
+// small rotation (scaled axis) and translation update of a pose
+pub fn apply_delta(pose: &Isometry3<f64>, delta: &Vector6<f64>) -> Isometry3<f64> {
+    let rotation = UnitQuaternion::from_scaled_axis(Vector3::new(delta[0], delta[1], delta[2]));
+    Isometry3::from_parts(
+        Translation3::from(pose.translation.vector + Vector3::new(delta[3], delta[4], delta[5])),
+        rotation * pose.rotation,
+    )
+}
+
+pub fn station_pose(station: &BaseStation) -> Isometry3<f64> {
+    Isometry3::from_parts(
+        Translation3::from(station.position.coords.cast::<f64>()),
+        station.orientation.cast::<f64>(),
+    )
+}
+
+pub fn to_f64(pose: &Isometry3<f32>) -> Isometry3<f64> {
+    Isometry3::from_parts(
+        Translation3::from(pose.translation.vector.cast::<f64>()),
+        pose.rotation.cast::<f64>(),
+    )
+}
+
+pub fn to_f32(pose: &Isometry3<f64>) -> Isometry3<f32> {
+    Isometry3::from_parts(
+        Translation3::from(pose.translation.vector.cast::<f32>()),
+        pose.rotation.cast::<f32>(),
+    )
+}
+
+// epnp (lepetit, moreno-noguer, fua 2009) for normalized image coordinates
+// returns cam_from_world rotation and translation
+pub fn epnp(points: &[Vector3<f64>], image: &[Vector2<f64>]) -> Option<(Matrix3<f64>, Vector3<f64>)> {
+    let n = points.len();
+    if n < MIN_SENSORS_EPNP || image.len() != n {
+        return None;
+    }
+
+    // control points: centroid plus principal axes
+    let centroid = points.iter().sum::<Vector3<f64>>() / n as f64;
+    let mut covariance = Matrix3::zeros();
+    for p in points {
+        let d = p - centroid;
+        covariance += d * d.transpose();
+    }
+    covariance /= n as f64;
+    let eigen = SymmetricEigen::new(covariance);
+
+    let mut control = [centroid; 4];
+    for k in 0..3 {
+        let scale = eigen.eigenvalues[k].max(1e-12).sqrt();
+        control[k + 1] = centroid + eigen.eigenvectors.column(k) * scale;
+    }
+
+    // barycentric coordinates of every point
+    let basis = Matrix3::from_columns(&[
+        control[1] - control[0],
+        control[2] - control[0],
+        control[3] - control[0],
+    ]);
+    let basis_inv = basis.try_inverse()?;
+    let alphas: Vec<[f64; 4]> = points
+        .iter()
+        .map(|p| {
+            let a = basis_inv * (p - control[0]);
+            [1.0 - a.x - a.y - a.z, a.x, a.y, a.z]
+        })
+        .collect();
+
+    // m * x = 0, x = control points in camera frame
+    let mut m = DMatrix::zeros(2 * n, 12);
+    for (i, (alpha, uv)) in alphas.iter().zip(image).enumerate() {
+        for j in 0..4 {
+            m[(2 * i, 3 * j)] = alpha[j];
+            m[(2 * i, 3 * j + 2)] = -alpha[j] * uv.x;
+            m[(2 * i + 1, 3 * j + 1)] = alpha[j];
+            m[(2 * i + 1, 3 * j + 2)] = -alpha[j] * uv.y;
+        }
+    }
+    let mtm = m.transpose() * &m;
+    let eigen = SymmetricEigen::new(mtm);
+    let mut order: Vec<usize> = (0..12).collect();
+    order.sort_by(|a, b| eigen.eigenvalues[*a].total_cmp(&eigen.eigenvalues[*b]));
+    let kernel: Vec<DVector<f64>> = order[..4]
+        .iter()
+        .map(|&i| eigen.eigenvectors.column(i).into_owned())
+        .collect();
+
+    // distance constraints between control points
+    const PAIRS: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+    let mut l = DMatrix::zeros(6, 10);
+    let mut rho = DVector::zeros(6);
+    for (row, &(a, b)) in PAIRS.iter().enumerate() {
+        let dv: Vec<Vector3<f64>> = kernel
+            .iter()
+            .map(|v| {
+                Vector3::new(v[3 * a] - v[3 * b], v[3 * a + 1] - v[3 * b + 1], v[3 * a + 2] - v[3 * b + 2])
+            })
+            .collect();
+        let values = [
+            dv[0].dot(&dv[0]),
+            2.0 * dv[0].dot(&dv[1]),
+            dv[1].dot(&dv[1]),
+            2.0 * dv[0].dot(&dv[2]),
+            2.0 * dv[1].dot(&dv[2]),
+            dv[2].dot(&dv[2]),
+            2.0 * dv[0].dot(&dv[3]),
+            2.0 * dv[1].dot(&dv[3]),
+            2.0 * dv[2].dot(&dv[3]),
+            dv[3].dot(&dv[3]),
+        ];
+        for (col, value) in values.iter().enumerate() {
+            l[(row, col)] = *value;
+        }
+        rho[row] = (control[a] - control[b]).norm_squared();
+    }
+
+    let mut best: Option<(f64, Matrix3<f64>, Vector3<f64>)> = None;
+    for approx in [betas_approx_1, betas_approx_2, betas_approx_3] {
+        let Some(mut betas) = approx(&l, &rho) else {
+            continue;
+        };
+        gauss_newton_betas(&l, &rho, &mut betas);
+
+        let Some((rotation, translation)) = rotation_translation(&kernel, &betas, &alphas, points) else {
+            continue;
+        };
+        let error = mean_reprojection_error(points, image, &rotation, &translation);
+        if best.as_ref().is_none_or(|b| error < b.0) {
+            best = Some((error, rotation, translation));
+        }
+    }
+
+    best.map(|(_, rotation, translation)| (rotation, translation))
+}
+
+fn least_squares(a: DMatrix<f64>, b: &DVector<f64>) -> Option<DVector<f64>> {
+    a.svd(true, true).solve(b, 1e-12).ok()
+}
+
+fn select_columns(l: &DMatrix<f64>, columns: &[usize]) -> DMatrix<f64> {
+    DMatrix::from_fn(l.nrows(), columns.len(), |r, c| l[(r, columns[c])])
+}
+
+// betas for the single kernel vector case (b11, b12, b13, b14)
+fn betas_approx_1(l: &DMatrix<f64>, rho: &DVector<f64>) -> Option<[f64; 4]> {
+    let b = least_squares(select_columns(l, &[0, 1, 3, 6]), rho)?;
+    let sign = if b[0] < 0.0 { -1.0 } else { 1.0 };
+    let b0 = (sign * b[0]).sqrt();
+    if b0 < 1e-12 {
+        return None;
+    }
+    Some([b0, sign * b[1] / b0, sign * b[2] / b0, sign * b[3] / b0])
+}
+
+// two kernel vectors (b11, b12, b22)
+fn betas_approx_2(l: &DMatrix<f64>, rho: &DVector<f64>) -> Option<[f64; 4]> {
+    let b = least_squares(select_columns(l, &[0, 1, 2]), rho)?;
+    let (mut b0, b1) = if b[0] < 0.0 {
+        ((-b[0]).sqrt(), if b[2] < 0.0 { (-b[2]).sqrt() } else { 0.0 })
+    } else {
+        (b[0].sqrt(), if b[2] > 0.0 { b[2].sqrt() } else { 0.0 })
+    };
+    if b[1] < 0.0 {
+        b0 = -b0;
+    }
+    Some([b0, b1, 0.0, 0.0])
+}
+
+// three kernel vectors (b11, b12, b22, b13, b23)
+fn betas_approx_3(l: &DMatrix<f64>, rho: &DVector<f64>) -> Option<[f64; 4]> {
+    let b = least_squares(select_columns(l, &[0, 1, 2, 3, 4]), rho)?;
+    let (mut b0, b1) = if b[0] < 0.0 {
+        ((-b[0]).sqrt(), if b[2] < 0.0 { (-b[2]).sqrt() } else { 0.0 })
+    } else {
+        (b[0].sqrt(), if b[2] > 0.0 { b[2].sqrt() } else { 0.0 })
+    };
+    if b[1] < 0.0 {
+        b0 = -b0;
+    }
+    if b0.abs() < 1e-12 {
+        return None;
+    }
+    Some([b0, b1, b[3] / b0, 0.0])
+}
+
+fn gauss_newton_betas(l: &DMatrix<f64>, rho: &DVector<f64>, betas: &mut [f64; 4]) {
+    for _ in 0..5 {
+        let mut a = DMatrix::zeros(6, 4);
+        let mut b = DVector::zeros(6);
+        for i in 0..6 {
+            let r: Vec<f64> = l.row(i).iter().copied().collect();
+            let [b0, b1, b2, b3] = *betas;
+            a[(i, 0)] = 2.0 * r[0] * b0 + r[1] * b1 + r[3] * b2 + r[6] * b3;
+            a[(i, 1)] = r[1] * b0 + 2.0 * r[2] * b1 + r[4] * b2 + r[7] * b3;
+            a[(i, 2)] = r[3] * b0 + r[4] * b1 + 2.0 * r[5] * b2 + r[8] * b3;
+            a[(i, 3)] = r[6] * b0 + r[7] * b1 + r[8] * b2 + 2.0 * r[9] * b3;
+            b[i] = rho[i]
+                - (r[0] * b0 * b0 + r[1] * b0 * b1 + r[2] * b1 * b1 + r[3] * b0 * b2 + r[4] * b1 * b2
+                    + r[5] * b2 * b2 + r[6] * b0 * b3 + r[7] * b1 * b3 + r[8] * b2 * b3 + r[9] * b3 * b3);
+        }
+        let Some(x) = least_squares(a, &b) else {
+            return;
+        };
+        for k in 0..4 {
+            betas[k] += x[k];
+        }
+    }
+}
+
+fn rotation_translation(
+    kernel: &[DVector<f64>],
+    betas: &[f64; 4],
+    alphas: &[[f64; 4]],
+    points: &[Vector3<f64>],
+) -> Option<(Matrix3<f64>, Vector3<f64>)> {
+    let mut control = [Vector3::zeros(); 4];
+    for (j, c) in control.iter_mut().enumerate() {
+        for k in 0..4 {
+            *c += Vector3::new(kernel[k][3 * j], kernel[k][3 * j + 1], kernel[k][3 * j + 2]) * betas[k];
+        }
+    }
+
+    let mut camera: Vec<Vector3<f64>> = alphas
+        .iter()
+        .map(|a| control[0] * a[0] + control[1] * a[1] + control[2] * a[2] + control[3] * a[3])
+        .collect();
+
+    // points must be in front of the camera
+    if camera[0].z < 0.0 {
+        for p in camera.iter_mut() {
+            *p = -*p;
+        }
+    }
+
+    // absolute orientation (kabsch)
+    let n = points.len() as f64;
+    let cam_mean = camera.iter().sum::<Vector3<f64>>() / n;
+    let world_mean = points.iter().sum::<Vector3<f64>>() / n;
+    let mut cross = Matrix3::zeros();
+    for (c, w) in camera.iter().zip(points) {
+        cross += (c - cam_mean) * (w - world_mean).transpose();
+    }
+
+    let svd = cross.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    let mut rotation = u * v_t;
+    if rotation.determinant() < 0.0 {
+        let fix = Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, -1.0));
+        rotation = u * fix * v_t;
+    }
+    let translation = cam_mean - rotation * world_mean;
+
+    Some((rotation, translation))
+}
+
+fn mean_reprojection_error(
+    points: &[Vector3<f64>],
+    image: &[Vector2<f64>],
+    rotation: &Matrix3<f64>,
+    translation: &Vector3<f64>,
+) -> f64 {
+    let total: f64 = points
+        .iter()
+        .zip(image)
+        .map(|(p, uv)| {
+            let c = rotation * p + translation;
+            if c.z <= 0.0 {
+                return 1e3;
+            }
+            (Vector2::new(c.x / c.z, c.y / c.z) - uv).norm()
+        })
+        .sum();
+    total / points.len() as f64
+}
+
+// headset and station layout shared by the solver, simulator and room setup tests
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use nalgebra::{Isometry3, Point3, Vector3};
+    use super::{SensorConstellation, SensorModel};
+    use crate::lighthouse_tracking::BaseStation;
+
+    // 32 sensors spread over an ellipsoid, normals pointing outwards
+    pub fn constellation() -> SensorConstellation {
+        let sensors = (0..32)
+            .map(|i| {
+                let theta = i as f32 * 2.399963;
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / 32.0;
+                let r = (1.0 - y * y).sqrt();
+                let normal = Vector3::new(r * theta.cos(), y, r * theta.sin());
+                SensorModel {
+                    position: Point3::new(normal.x * 0.09, normal.y * 0.06, normal.z * 0.08),
+                    normal,
+                }
+            })
+            .collect();
+        SensorConstellation::new(sensors)
+    }
+
+    // station at `position` looking at the middle of the room, 1 m up
+    pub fn station(id: u8, position: Point3<f32>) -> BaseStation {
+        let mut station = BaseStation::new(id, position);
+        station.orientation = Isometry3::look_at_rh(&position, &Point3::new(0.0, 1.0, 0.0), &Vector3::y())
+            .rotation
+            .inverse();
+        station
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_fixtures::{constellation, station};
+
+    fn observe(solver: &PoseSolver, stations: &[BaseStation], truth: &Isometry3<f32>) -> Vec<AngleObservation> {
+        let mut observations = Vec::new();
+        for (index, station) in stations.iter().enumerate() {
+            for (id, sensor) in solver.constellation().sensors.iter().enumerate() {
+                let to_station = (station.position - truth * sensor.position).normalize();
+                if (truth.rotation * sensor.normal).dot(&to_station) < 0.1 {
+                    continue;
+                }
+                observations.push(AngleObservation {
+                    sensor_id: id as u8,
+                    station: index,
+                    angles: solver.project(station, truth, id as u8).unwrap(),
+                });
+            }
+        }
+        observations
+    }
+
+    #[test]
+    fn lm_reports_convergence() {
+        let apply = |p: &f64, d: &DVector<f64>| p + d[0];
+
+        let fit = levenberg_marquardt(3.0, 1, |p: &f64| DVector::from_element(1, p - 1.0), apply);
+        assert!(fit.converged);
+        assert!((fit.params - 1.0).abs() < 1e-9);
+
+        // exp(p) only reaches zero at -inf, the cost keeps dropping until the iterations run out
+        let fit = levenberg_marquardt(0.0, 1, |p: &f64| DVector::from_element(1, p.exp()), apply);
+        assert!(!fit.converged);
+        assert_eq!(fit.iterations, LM_MAX_ITERATIONS);
+    }
+
+    #[test]
+    fn solve_with_good_guess() {
+        let solver = PoseSolver::new(constellation());
+        let stations = [station(0, Point3::new(-2.0, 2.0, 2.0)), station(1, Point3::new(2.0, 2.0, -2.0))];
+        let truth = Isometry3::new(Vector3::new(0.2, 1.5, -0.1), Vector3::new(0.2, 0.9, -0.3));
+        let observations = observe(&solver, &stations, &truth);
+
+        let guess = Isometry3::new(Vector3::new(0.25, 1.45, -0.05), Vector3::new(0.25, 0.85, -0.3));
+        let solution = solver.solve_with_guess(&stations, &observations, &guess).unwrap();
+        assert_eq!(solution.stations, 2);
+        assert!(solution.reprojection_error < 1e-5);
+        assert!((solution.pose.translation.vector - truth.translation.vector).norm() < 1e-3);
+        assert!(solution.pose.rotation.angle_to(&truth.rotation) < 1e-3);
+    }
+
+    #[test]
+    fn bad_guess_falls_back_to_epnp() {
+        let solver = PoseSolver::new(constellation());
+        let stations = [station(0, Point3::new(-2.0, 2.0, 2.0))];
+        let truth = Isometry3::new(Vector3::new(0.2, 1.5, -0.1), Vector3::new(0.2, 0.9, -0.3));
+        let observations = observe(&solver, &stations, &truth);
+
+        // flipped over and a metre off, lm alone settles in a wrong minimum far away
+        let guess = Isometry3::new(Vector3::new(1.2, 1.5, -0.1), Vector3::new(3.3, 0.9, -0.3));
+        let solution = solver.solve_with_guess(&stations, &observations, &guess).unwrap();
+        assert!(solution.reprojection_error < 1e-5);
+        assert!((solution.pose.translation.vector - truth.translation.vector).norm() < 1e-3);
+        assert!(solution.pose.rotation.angle_to(&truth.rotation) < 1e-3);
+    }
+}
@@ -0,0 +1,408 @@
+// lighthouse v1 pulse decoder
+// native replacement for the old lighthouse-timing.S hooks, see docs/lighthouse.md
+// takes nanosecond timestamps so the 48 mhz light event clock keeps its sub-us
+// resolution (1 us is ~0.02 deg of sweep), the bucket tables below are in microseconds
+
+use std::f32::consts::PI;
+use crate::lighthouse_tracking::PulseType;
+
+// timing constants in microseconds (lighthouse v1 spec)
+pub const SYNC_MIN_US: u64 = 59;
+pub const SYNC_MAX_US: u64 = 139;
+pub const SKIP_MAX_US: u64 = 165;
+pub const SWEEP_MIN_US: u64 = 1222;
+pub const SWEEP_MAX_US: u64 = 6777;
+pub const CYCLE_PERIOD_US: f32 = 8333.0;
+pub const CENTER_OFFSET_US: f32 = 4000.0;
+
+// one sync flash hits every sensor at (almost) the same time, pulses closer
+// than this are treated as the same flash seen by another photodiode
+const SYNC_MERGE_WINDOW_US: u64 = 100;
+
+const NS_PER_US: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+// tdma slot: which station is sweeping and along which axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SweepSlot {
+    AHorizontal,
+    AVertical,
+    BHorizontal,
+    BVertical,
+}
+
+impl SweepSlot {
+    pub const ALL: [SweepSlot; 4] = [
+        SweepSlot::AHorizontal,
+        SweepSlot::AVertical,
+        SweepSlot::BHorizontal,
+        SweepSlot::BVertical,
+    ];
+
+    pub fn new(station: usize, axis: Axis) -> Self {
+        match (station, axis) {
+            (0, Axis::Horizontal) => SweepSlot::AHorizontal,
+            (0, Axis::Vertical) => SweepSlot::AVertical,
+            (_, Axis::Horizontal) => SweepSlot::BHorizontal,
+            (_, Axis::Vertical) => SweepSlot::BVertical,
+        }
+    }
+
+    // 0 = station a, 1 = station b
+    pub fn station(self) -> usize {
+        match self {
+            SweepSlot::AHorizontal | SweepSlot::AVertical => 0,
+            SweepSlot::BHorizontal | SweepSlot::BVertical => 1,
+        }
+    }
+
+    pub fn axis(self) -> Axis {
+        match self {
+            SweepSlot::AHorizontal | SweepSlot::BHorizontal => Axis::Horizontal,
+            SweepSlot::AVertical | SweepSlot::BVertical => Axis::Vertical,
+        }
+    }
+
+    // position in the tdma cycle (a-h, a-v, b-h, b-v)
+    pub fn index(self) -> usize {
+        self.station() * 2 + match self.axis() {
+            Axis::Horizontal => 0,
+            Axis::Vertical => 1,
+        }
+    }
+}
+
+// decoded sync flash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncPulse {
+    pub start_ns: u64,
+    pub duration_ns: u64,
+    // none for skip pulses (the station flashed but will not sweep)
+    pub slot: Option<SweepSlot>,
+    pub ootx_bit: bool,
+}
+
+impl SyncPulse {
+    pub fn is_skip(&self) -> bool {
+        self.slot.is_none()
+    }
+}
+
+// laser sweep hit on one sensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepHit {
+    pub sensor_id: u8,
+    pub slot: SweepSlot,
+    pub timestamp_ns: u64,
+    pub angle: f32,  // radians
+}
+
+impl SweepHit {
+    pub fn timestamp_us(&self) -> u64 {
+        self.timestamp_ns / NS_PER_US
+    }
+}
+
+// sync width bucket decoding (table from docs/lighthouse.md)
+//
+//   59-72     station a, horizontal, ootx bit 0
+//   73-86     station a, horizontal, ootx bit 1
+//   87-100    station a, vertical, ootx bit 0
+//   101-114   station a, vertical, ootx bit 1
+//   115-128   station b, horizontal, ootx bit 0
+//   129-139   station b, horizontal, ootx bit 1
+//   140-165   skip (bit 0 / bit 1, 13us buckets like the old asm table)
+//
+// the table has no station b vertical entry, that slot is recovered from
+// the tdma order in PulseDecoder
+pub fn classify_sync_width(width_us: u64) -> Option<(Option<SweepSlot>, bool)> {
+    match width_us {
+        59..=72 => Some((Some(SweepSlot::AHorizontal), false)),
+        73..=86 => Some((Some(SweepSlot::AHorizontal), true)),
+        87..=100 => Some((Some(SweepSlot::AVertical), false)),
+        101..=114 => Some((Some(SweepSlot::AVertical), true)),
+        115..=128 => Some((Some(SweepSlot::BHorizontal), false)),
+        129..=139 => Some((Some(SweepSlot::BHorizontal), true)),
+        140..=152 => Some((None, false)),
+        153..=SKIP_MAX_US => Some((None, true)),
+        _ => None,
+    }
+}
+
+// angle (radians) = (delta_us - 4000) * pi / 8333
+pub fn sweep_delta_to_angle(delta_us: f32) -> f32 {
+    (delta_us - CENTER_OFFSET_US) * (PI / CYCLE_PERIOD_US)
+}
+
+// same from the sync to hit time in nanoseconds, in f64 until the end so the
+// sub-us part survives (f32 only has ~0.5 ns left at a 4 ms delta)
+pub fn sweep_delta_ns_to_angle(delta_ns: u64) -> f32 {
+    let delta_us = delta_ns as f64 / NS_PER_US as f64;
+    ((delta_us - CENTER_OFFSET_US as f64) * (std::f64::consts::PI / CYCLE_PERIOD_US as f64)) as f32
+}
+
+// inverse of sweep_delta_to_angle, handy for building pulse trains
+pub fn angle_to_sweep_delta(angle: f32) -> f32 {
+    angle * (CYCLE_PERIOD_US / PI) + CENTER_OFFSET_US
+}
+
+// pulse classification state machine
+pub struct PulseDecoder {
+    // sync leds decay slowly, the comparator sees pulses 10-20us too long
+    decay_offset_us: u64,
+    station_sync_ns: [Option<u64>; 2],
+    // last non-skip sync, sweeps are measured against it
+    active_sync: Option<SyncPulse>,
+    // last distinct sync flash (skip or not)
+    last_sync: Option<SyncPulse>,
+    last_sweep: Option<SweepHit>,
+    new_sync: bool,
+}
+
+impl PulseDecoder {
+    pub fn new() -> Self {
+        Self {
+            decay_offset_us: 0,
+            station_sync_ns: [None; 2],
+            active_sync: None,
+            last_sync: None,
+            last_sweep: None,
+            new_sync: false,
+        }
+    }
+
+    // width (us) to subtract from every pulse before bucket decoding
+    pub fn set_decay_offset(&mut self, offset_us: u64) {
+        self.decay_offset_us = offset_us;
+    }
+
+    pub fn reset(&mut self) {
+        let decay_offset_us = self.decay_offset_us;
+        *self = Self::new();
+        self.decay_offset_us = decay_offset_us;
+    }
+
+    // classify one photodiode pulse, start and duration in nanoseconds
+    pub fn process_pulse(&mut self, start_ns: u64, duration_ns: u64, sensor_id: u8) -> PulseType {
+        self.new_sync = false;
+        // buckets are whole microseconds, a 72.9 us flash is still in 59..=72
+        let width_us = (duration_ns / NS_PER_US).saturating_sub(self.decay_offset_us);
+
+        if width_us >= SYNC_MIN_US {
+            self.handle_sync(start_ns, duration_ns, width_us)
+        } else {
+            self.handle_sweep(start_ns, sensor_id)
+        }
+    }
+
+    fn handle_sync(&mut self, start_ns: u64, duration_ns: u64, width_us: u64) -> PulseType {
+        // same flash already decoded from another sensor
+        if let Some(last) = self.last_sync
+            && start_ns.abs_diff(last.start_ns) <= SYNC_MERGE_WINDOW_US * NS_PER_US
+        {
+            return PulseType::Sync;
+        }
+
+        let Some((slot, ootx_bit)) = classify_sync_width(width_us) else {
+            return PulseType::Unknown;
+        };
+
+        // b-v is coded like b-h, it is the b slot right after a b-h slot
+        let slot = slot.map(|slot| match (slot, self.active_sync.and_then(|s| s.slot)) {
+            (SweepSlot::BHorizontal, Some(SweepSlot::BHorizontal)) => SweepSlot::BVertical,
+            _ => slot,
+        });
+
+        let sync = SyncPulse {
+            start_ns,
+            duration_ns,
+            slot,
+            ootx_bit,
+        };
+
+        if let Some(slot) = slot {
+            self.station_sync_ns[slot.station()] = Some(start_ns);
+            self.active_sync = Some(sync);
+        }
+        self.last_sync = Some(sync);
+        self.new_sync = true;
+
+        PulseType::Sync
+    }
+
+    fn handle_sweep(&mut self, start_ns: u64, sensor_id: u8) -> PulseType {
+        let Some(sync) = self.active_sync else {
+            return PulseType::Unknown;
+        };
+        let Some(slot) = sync.slot else {
+            return PulseType::Unknown;
+        };
+
+        let delta_ns = start_ns.saturating_sub(sync.start_ns);
+        if !(SWEEP_MIN_US * NS_PER_US..=SWEEP_MAX_US * NS_PER_US).contains(&delta_ns) {
+            return PulseType::Unknown;
+        }
+
+        self.last_sweep = Some(SweepHit {
+            sensor_id,
+            slot,
+            timestamp_ns: start_ns,
+            angle: sweep_delta_ns_to_angle(delta_ns),
+        });
+
+        PulseType::Sweep
+    }
+
+    // sync decoded by the last process_pulse call, none if it was a duplicate
+    pub fn take_new_sync(&mut self) -> Option<SyncPulse> {
+        if self.new_sync {
+            self.new_sync = false;
+            self.last_sync
+        } else {
+            None
+        }
+    }
+
+    pub fn last_sync(&self) -> Option<SyncPulse> {
+        self.last_sync
+    }
+
+    pub fn last_sweep(&self) -> Option<SweepHit> {
+        self.last_sweep
+    }
+
+    // start of the last sync flash from a station (0 = a, 1 = b), nanoseconds
+    pub fn station_sync_time(&self, station: usize) -> Option<u64> {
+        self.station_sync_ns.get(station).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const US: u64 = NS_PER_US;
+
+    // one full tdma cycle apart, far outside the merge window
+    const CYCLE_NS: u64 = 8333 * US;
+
+    #[test]
+    fn sync_width_bucket_edges() {
+        let a_h = Some(SweepSlot::AHorizontal);
+        let a_v = Some(SweepSlot::AVertical);
+        let b_h = Some(SweepSlot::BHorizontal);
+        let cases = [
+            (58, None),
+            (59, Some((a_h, false))),
+            (72, Some((a_h, false))),
+            (73, Some((a_h, true))),
+            (86, Some((a_h, true))),
+            (87, Some((a_v, false))),
+            (100, Some((a_v, false))),
+            (101, Some((a_v, true))),
+            (114, Some((a_v, true))),
+            (115, Some((b_h, false))),
+            (128, Some((b_h, false))),
+            (129, Some((b_h, true))),
+            (139, Some((b_h, true))),
+            (140, Some((None, false))),
+            (152, Some((None, false))),
+            (153, Some((None, true))),
+            (165, Some((None, true))),
+            (166, None),
+        ];
+        for (width_us, expected) in cases {
+            assert_eq!(classify_sync_width(width_us), expected, "width {} us", width_us);
+        }
+    }
+
+    #[test]
+    fn sync_axis_skip_and_data_bits() {
+        let mut decoder = PulseDecoder::new();
+
+        // a-h, data bit 1; the same flash on a second sensor is merged
+        assert_eq!(decoder.process_pulse(1000 * US, 80 * US, 0), PulseType::Sync);
+        let sync = decoder.take_new_sync().unwrap();
+        assert_eq!(sync.slot, Some(SweepSlot::AHorizontal));
+        assert!(sync.ootx_bit);
+        assert_eq!(decoder.process_pulse(1020 * US, 82 * US, 3), PulseType::Sync);
+        assert_eq!(decoder.take_new_sync(), None);
+
+        // a-v, data bit 0
+        decoder.process_pulse(1000 * US + CYCLE_NS, 90 * US, 0);
+        let sync = decoder.take_new_sync().unwrap();
+        assert_eq!(sync.slot, Some(SweepSlot::AVertical));
+        assert!(!sync.ootx_bit);
+
+        // skip with data bit 1 keeps the active sync for sweeps
+        decoder.process_pulse(1400 * US + CYCLE_NS, 160 * US, 0);
+        let sync = decoder.take_new_sync().unwrap();
+        assert!(sync.is_skip());
+        assert!(sync.ootx_bit);
+        assert_eq!(decoder.process_pulse(5000 * US + CYCLE_NS, 10 * US, 2), PulseType::Sweep);
+        assert_eq!(decoder.last_sweep().unwrap().slot, SweepSlot::AVertical);
+
+        // decay offset shifts the buckets: 75 us is a-h bit 1, minus 15 it is bit 0
+        decoder.set_decay_offset(15);
+        decoder.process_pulse(1000 * US + 2 * CYCLE_NS, 75 * US, 0);
+        let sync = decoder.take_new_sync().unwrap();
+        assert_eq!(sync.slot, Some(SweepSlot::AHorizontal));
+        assert!(!sync.ootx_bit);
+    }
+
+    #[test]
+    fn b_vertical_follows_two_b_horizontal_syncs() {
+        let mut decoder = PulseDecoder::new();
+        let slots: Vec<_> = [70, 90, 120, 130]
+            .iter()
+            .enumerate()
+            .map(|(i, &width_us)| {
+                decoder.process_pulse(i as u64 * CYCLE_NS, width_us * US, 0);
+                decoder.take_new_sync().unwrap().slot
+            })
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                Some(SweepSlot::AHorizontal),
+                Some(SweepSlot::AVertical),
+                Some(SweepSlot::BHorizontal),
+                Some(SweepSlot::BVertical),
+            ]
+        );
+        assert_eq!(decoder.station_sync_time(1), Some(3 * CYCLE_NS));
+        assert_eq!(decoder.station_sync_time(0), Some(CYCLE_NS));
+    }
+
+    #[test]
+    fn sweep_angles_and_window() {
+        let mut decoder = PulseDecoder::new();
+        // sweep before any sync is unknown
+        assert_eq!(decoder.process_pulse(500 * US, 10 * US, 1), PulseType::Unknown);
+
+        decoder.process_pulse(0, 65 * US, 0);
+        assert_eq!(decoder.process_pulse(4000 * US, 10 * US, 1), PulseType::Sweep);
+        let hit = decoder.last_sweep().unwrap();
+        assert_eq!((hit.sensor_id, hit.slot), (1, SweepSlot::AHorizontal));
+        assert!(hit.angle.abs() < 1e-6);
+        assert_eq!(hit.timestamp_us(), 4000);
+
+        // sub-microsecond offsets survive into the angle
+        decoder.process_pulse(4000 * US + 500, 10 * US, 1);
+        let expected = 0.5 * std::f32::consts::PI / CYCLE_PERIOD_US;
+        assert!((decoder.last_sweep().unwrap().angle - expected).abs() < 1e-7);
+
+        // outside 1222..=6777 us after the sync
+        assert_eq!(decoder.process_pulse(1000 * US, 10 * US, 1), PulseType::Unknown);
+        assert_eq!(decoder.process_pulse(7000 * US, 10 * US, 1), PulseType::Unknown);
+
+        let angle = 0.3;
+        let delta_ns = (angle_to_sweep_delta(angle) as f64 * US as f64) as u64;
+        assert!((sweep_delta_ns_to_angle(delta_ns) - angle).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,579 @@
+// base station pose calibration ("room setup")
+// the headset rests on the floor while angles are recorded, which defines the
+// world frame: origin on the floor under the headset, y up. every station pose is
+// found with epnp + lm against the averaged angles, extra frames recorded while
+// the headset is carried around can refine everything together (bundle adjustment)
+//
+// the result is stored like steamvr's lighthousedb.json:
+//   { "lighthouse0": { "id": "0x12345678", "mode": "A",
+//                      "position": [x, y, z], "rotation": [x, y, z, w], "fcal": {...} } }
+
+use std::collections::BTreeMap;
+use std::fs;
+use nalgebra::{DVector, Isometry3, Point3, Rotation3, Translation3, UnitQuaternion, Vector2, Vector3, Vector6};
+use serde::{Serialize, Deserialize};
+use crate::lighthouse_calibration::BaseStationCalibration;
+use crate::lighthouse_ootx::StationMode;
+use crate::lighthouse_pose::{
+    apply_delta, epnp, levenberg_marquardt, to_f32, to_f64, AngleObservation, PoseSolver,
+    SensorConstellation, MIN_SENSORS_EPNP,
+};
+use crate::lighthouse_tracking::BaseStation;
+
+pub const ROOM_SETUP_FILE: &str = "lighthousedb.json";
+// about a third of a second at 30 observation frames per second
+pub const MIN_FLOOR_FRAMES: usize = 10;
+// a sensor whose angles spread more than this moved during the capture (radians)
+const MAX_STILL_SPREAD: f32 = 0.002;
+// moving frames fitting worse than this mix angles from different poses (radians)
+const MAX_FRAME_ERROR: f32 = 0.002;
+const MAX_STATIONS: usize = 2;
+
+// one station entry of lighthousedb.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomStation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    pub position: [f32; 3],
+    // quaternion [x, y, z, w], world_from_station
+    pub rotation: [f32; 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fcal: Option<BaseStationCalibration>,
+}
+
+// whole lighthousedb.json, keyed "lighthouse0", "lighthouse1", ...
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RoomSetup {
+    pub stations: BTreeMap<String, RoomStation>,
+}
+
+impl RoomSetup {
+    pub fn from_stations(stations: &[BaseStation]) -> Self {
+        let stations = stations
+            .iter()
+            .enumerate()
+            .map(|(index, station)| {
+                let q = station.orientation;
+                let entry = RoomStation {
+                    id: station.unique_id.map(|id| format!("0x{:08x}", id)),
+                    mode: station.mode.map(|mode| format!("{:?}", mode)),
+                    position: [station.position.x, station.position.y, station.position.z],
+                    rotation: [q.i, q.j, q.k, q.w],
+                    fcal: station.calibration,
+                };
+                (format!("lighthouse{}", index), entry)
+            })
+            .collect();
+
+        Self { stations }
+    }
+
+    // stations in index order, fcal from the file is used until ootx arrives
+    pub fn to_stations(&self) -> Vec<BaseStation> {
+        let mut entries: Vec<(usize, &RoomStation)> = self
+            .stations
+            .iter()
+            .filter_map(|(key, entry)| {
+                let index = key.strip_prefix("lighthouse")?.parse().ok()?;
+                Some((index, entry))
+            })
+            .collect();
+        entries.sort_by_key(|(index, _)| *index);
+
+        entries
+            .into_iter()
+            .map(|(index, entry)| {
+                let [x, y, z] = entry.position;
+                let [qx, qy, qz, qw] = entry.rotation;
+                let mut station = BaseStation::new(index as u8, Point3::new(x, y, z));
+                station.orientation = UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(qw, qx, qy, qz));
+                station.unique_id = entry
+                    .id
+                    .as_deref()
+                    .and_then(|id| u32::from_str_radix(id.trim_start_matches("0x"), 16).ok());
+                station.mode = entry.mode.as_deref().and_then(|mode| match mode {
+                    "A" => Some(StationMode::A),
+                    "B" => Some(StationMode::B),
+                    "C" => Some(StationMode::C),
+                    _ => None,
+                });
+                station.calibration = entry.fcal;
+                station
+            })
+            .collect()
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomSolution {
+    // index = station (0 = a, 1 = b), pose only
+    pub stations: Vec<BaseStation>,
+    // rms angle error over every observation used (radians)
+    pub reprojection_error: f32,
+    // moving frames that took part in the bundle adjustment
+    pub frames: usize,
+    pub iterations: usize,
+}
+
+// running min/max/mean of one sensor seen from one station
+#[derive(Debug, Clone, Copy)]
+struct AngleStats {
+    sum: [f64; 2],
+    min: [f32; 2],
+    max: [f32; 2],
+    count: usize,
+}
+
+impl AngleStats {
+    fn new() -> Self {
+        Self {
+            sum: [0.0; 2],
+            min: [f32::MAX; 2],
+            max: [f32::MIN; 2],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, angles: [f32; 2]) {
+        for (k, &angle) in angles.iter().enumerate() {
+            self.sum[k] += angle as f64;
+            self.min[k] = self.min[k].min(angle);
+            self.max[k] = self.max[k].max(angle);
+        }
+        self.count += 1;
+    }
+
+    fn mean(&self) -> [f32; 2] {
+        let n = self.count.max(1) as f64;
+        [(self.sum[0] / n) as f32, (self.sum[1] / n) as f32]
+    }
+
+    fn spread(&self) -> f32 {
+        (self.max[0] - self.min[0]).max(self.max[1] - self.min[1])
+    }
+}
+
+pub struct RoomCalibrator {
+    constellation: SensorConstellation,
+    // world_from_device while the headset rests on the floor
+    floor_pose: Isometry3<f32>,
+    floor: BTreeMap<(usize, u8), AngleStats>,
+    floor_frames: usize,
+    frames: Vec<Vec<AngleObservation>>,
+}
+
+impl RoomCalibrator {
+    // floor_pose places the device frame in the room, e.g. a headset lying
+    // face down has its origin a few centimetres above the floor
+    pub fn new(constellation: SensorConstellation, floor_pose: Isometry3<f32>) -> Self {
+        Self {
+            constellation,
+            floor_pose,
+            floor: BTreeMap::new(),
+            floor_frames: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.floor.clear();
+        self.floor_frames = 0;
+        self.frames.clear();
+    }
+
+    // one set of fcal corrected angles while the headset sits still on the floor
+    pub fn add_floor_frame(&mut self, observations: &[AngleObservation]) {
+        for obs in self.usable(observations) {
+            self.floor
+                .entry((obs.station, obs.sensor_id))
+                .or_insert_with(AngleStats::new)
+                .push(obs.angles);
+        }
+        self.floor_frames += 1;
+    }
+
+    // one set of angles with the headset somewhere else, used by the bundle adjustment
+    // the headset should be held still, sweeps of a moving one do not agree with each other
+    pub fn add_frame(&mut self, observations: &[AngleObservation]) {
+        let usable = self.usable(observations);
+        if usable.len() >= MIN_SENSORS_EPNP {
+            self.frames.push(usable);
+        }
+    }
+
+    pub fn floor_frames(&self) -> usize {
+        self.floor_frames
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn usable(&self, observations: &[AngleObservation]) -> Vec<AngleObservation> {
+        observations
+            .iter()
+            .filter(|o| o.station < MAX_STATIONS && self.constellation.get(o.sensor_id).is_some())
+            .copied()
+            .collect()
+    }
+
+    pub fn solve(&self, bundle_adjust: bool) -> Result<RoomSolution, Box<dyn std::error::Error>> {
+        if self.floor_frames < MIN_FLOOR_FRAMES {
+            return Err(format!(
+                "need at least {} floor frames, got {}",
+                MIN_FLOOR_FRAMES, self.floor_frames
+            )
+            .into());
+        }
+        if let Some(((station, sensor), _)) = self
+            .floor
+            .iter()
+            .find(|(_, stats)| stats.spread() > MAX_STILL_SPREAD)
+        {
+            return Err(format!(
+                "headset moved during capture (sensor {} seen from station {})",
+                sensor, station
+            )
+            .into());
+        }
+
+        // averaged angles against known sensor positions in the room
+        let floor_pose = to_f64(&self.floor_pose);
+        let floor_obs: Vec<(AngleObservation, Point3<f64>)> = self
+            .floor
+            .iter()
+            .map(|(&(station, sensor_id), stats)| {
+                let obs = AngleObservation {
+                    sensor_id,
+                    station,
+                    angles: stats.mean(),
+                };
+                let position = self.constellation.sensors[sensor_id as usize].position.cast::<f64>();
+                (obs, floor_pose * position)
+            })
+            .collect();
+
+        let mut station_poses = Vec::new();
+        let mut iterations = 0;
+        for station in 0..MAX_STATIONS {
+            let seen: Vec<(AngleObservation, Point3<f64>)> = floor_obs
+                .iter()
+                .filter(|(obs, _)| obs.station == station)
+                .copied()
+                .collect();
+            if seen.len() < MIN_SENSORS_EPNP {
+                break;
+            }
+
+            let guess = epnp_station(&seen)
+                .ok_or_else(|| format!("epnp failed for station {}", station))?;
+            let fit = levenberg_marquardt(
+                guess,
+                6,
+                |pose| station_residuals(pose, &seen),
+                |pose, delta| apply_delta(pose, &Vector6::from_iterator(delta.iter().copied())),
+            );
+            iterations += fit.iterations;
+            station_poses.push(fit.params);
+        }
+        if station_poses.is_empty() {
+            return Err("station a does not see enough sensors".into());
+        }
+
+        let mut frames = 0;
+        if bundle_adjust && !self.frames.is_empty() {
+            let (poses, used, ba_iterations) = self.bundle_adjust(&station_poses, &floor_obs);
+            station_poses = poses;
+            frames = used;
+            iterations += ba_iterations;
+        }
+
+        // final error over the floor observations
+        let mut squared = 0.0;
+        let mut count = 0;
+        for (index, pose) in station_poses.iter().enumerate() {
+            let seen: Vec<(AngleObservation, Point3<f64>)> = floor_obs
+                .iter()
+                .filter(|(obs, _)| obs.station == index)
+                .copied()
+                .collect();
+            let r = station_residuals(pose, &seen);
+            squared += r.norm_squared();
+            count += r.len();
+        }
+
+        Ok(RoomSolution {
+            stations: station_poses
+                .iter()
+                .enumerate()
+                .map(|(index, pose)| {
+                    let pose = to_f32(pose);
+                    let mut station = BaseStation::new(index as u8, Point3::from(pose.translation.vector));
+                    station.orientation = pose.rotation;
+                    station
+                })
+                .collect(),
+            reprojection_error: (squared / count.max(1) as f64).sqrt() as f32,
+            frames,
+            iterations,
+        })
+    }
+
+    // joint lm over every station pose and every moving frame pose,
+    // the floor frame stays fixed and pins down the world frame
+    fn bundle_adjust(
+        &self,
+        initial: &[Isometry3<f64>],
+        floor_obs: &[(AngleObservation, Point3<f64>)],
+    ) -> (Vec<Isometry3<f64>>, usize, usize) {
+        let stations: Vec<BaseStation> = initial
+            .iter()
+            .enumerate()
+            .map(|(index, pose)| {
+                let pose = to_f32(pose);
+                let mut station = BaseStation::new(index as u8, Point3::from(pose.translation.vector));
+                station.orientation = pose.rotation;
+                station
+            })
+            .collect();
+
+        // starting device poses from the current station estimate
+        let solver = PoseSolver::new(self.constellation.clone());
+        let mut params = initial.to_vec();
+        let mut frames = Vec::new();
+        for frame in &self.frames {
+            let usable: Vec<AngleObservation> = frame
+                .iter()
+                .filter(|o| o.station < stations.len())
+                .copied()
+                .collect();
+            let solution = solver
+                .solve(&stations, &usable)
+                .filter(|s| s.reprojection_error < MAX_FRAME_ERROR);
+            if let Some(solution) = solution {
+                params.push(to_f64(&solution.pose));
+                frames.push(usable);
+            }
+        }
+        if frames.is_empty() {
+            return (initial.to_vec(), 0, 0);
+        }
+
+        let station_count = initial.len();
+        let sensors = &self.constellation.sensors;
+        let residuals = |params: &Vec<Isometry3<f64>>| -> DVector<f64> {
+            let mut r = Vec::new();
+            for (obs, world) in floor_obs.iter().filter(|(o, _)| o.station < station_count) {
+                push_residual(&mut r, &params[obs.station], world, &obs.angles);
+            }
+            for (f, frame) in frames.iter().enumerate() {
+                let device = &params[station_count + f];
+                for obs in frame {
+                    let world = device * sensors[obs.sensor_id as usize].position.cast::<f64>();
+                    push_residual(&mut r, &params[obs.station], &world, &obs.angles);
+                }
+            }
+            DVector::from_vec(r)
+        };
+        let apply = |params: &Vec<Isometry3<f64>>, delta: &DVector<f64>| -> Vec<Isometry3<f64>> {
+            params
+                .iter()
+                .enumerate()
+                .map(|(i, pose)| apply_delta(pose, &Vector6::from_iterator(delta.rows(6 * i, 6).iter().copied())))
+                .collect()
+        };
+
+        let fit = levenberg_marquardt(params, 6 * (station_count + frames.len()), residuals, apply);
+        (fit.params[..station_count].to_vec(), frames.len(), fit.iterations)
+    }
+}
+
+// world_from_station from the room points one station sees
+fn epnp_station(seen: &[(AngleObservation, Point3<f64>)]) -> Option<Isometry3<f64>> {
+    let points: Vec<Vector3<f64>> = seen.iter().map(|(_, p)| p.coords).collect();
+    let image: Vec<Vector2<f64>> = seen
+        .iter()
+        .map(|(obs, _)| Vector2::new((obs.angles[0] as f64).tan(), -(obs.angles[1] as f64).tan()))
+        .collect();
+
+    let (rotation, translation) = epnp(&points, &image)?;
+    let cam_from_world = Isometry3::from_parts(
+        Translation3::from(translation),
+        UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation)),
+    );
+
+    // cam and station frames differ by a 180 deg rotation about x
+    let flip = Isometry3::from_parts(
+        Translation3::identity(),
+        UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI),
+    );
+    Some((flip * cam_from_world).inverse())
+}
+
+fn station_residuals(pose: &Isometry3<f64>, seen: &[(AngleObservation, Point3<f64>)]) -> DVector<f64> {
+    let mut r = Vec::with_capacity(seen.len() * 2);
+    for (obs, world) in seen {
+        push_residual(&mut r, pose, world, &obs.angles);
+    }
+    DVector::from_vec(r)
+}
+
+fn push_residual(r: &mut Vec<f64>, station: &Isometry3<f64>, world: &Point3<f64>, angles: &[f32; 2]) {
+    let local = station.inverse_transform_point(world);
+    r.push(local.x.atan2(-local.z) - angles[0] as f64);
+    r.push(local.y.atan2(-local.z) - angles[1] as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::lighthouse_pose::test_fixtures::constellation;
+    use crate::lighthouse_sim::test_fixtures::{feed, run_until_ootx, two_stations};
+    use crate::lighthouse_sim::{LighthouseSimulator, Trajectory};
+    use crate::lighthouse_tracking::LighthouseTracker;
+
+    // headset face down on the floor, turned away from station a
+    fn floor_pose() -> Isometry3<f32> {
+        Isometry3::new(Vector3::new(0.0, 0.06, 0.0), Vector3::new(0.0, 0.5, 0.0))
+    }
+
+    // held still after the floor capture, a few tens of centimetres apart
+    fn carried_poses() -> Vec<Isometry3<f32>> {
+        (0..8)
+            .map(|i| {
+                let a = i as f32 * 0.8;
+                Isometry3::new(
+                    Vector3::new(0.6 * a.cos(), 1.0 + 0.1 * i as f32, 0.6 * a.sin()),
+                    Vector3::new(0.3 * a.sin(), a, 0.2),
+                )
+            })
+            .collect()
+    }
+
+    const FLOOR_UNTIL_S: f64 = 8.0;
+    const HOLD_S: f64 = 0.3;
+    const MOVE_S: f64 = 0.1;
+
+    // floor capture and carried frames recorded the way the daemon does it,
+    // returns the calibrator and the simulated stations
+    fn record(timing_noise_us: f64) -> (RoomCalibrator, Vec<BaseStation>) {
+        let mut keyframes = vec![(0.0, floor_pose()), (FLOOR_UNTIL_S, floor_pose())];
+        for (i, pose) in carried_poses().into_iter().enumerate() {
+            let start = FLOOR_UNTIL_S + i as f64 * (HOLD_S + MOVE_S) + MOVE_S;
+            keyframes.push((start, pose));
+            keyframes.push((start + HOLD_S, pose));
+        }
+        let mut sim = LighthouseSimulator::new(two_stations(), constellation(), Trajectory::new(keyframes));
+        sim.timing_noise_us = timing_noise_us;
+        sim.decay_skew_us = 15.0;
+
+        // station poses are what is being calibrated, only the count is known
+        let mut tracker = LighthouseTracker::new(32);
+        tracker.set_sync_decay_offset(15);
+        tracker.add_base_station(BaseStation::new(0, Point3::origin()));
+        tracker.add_base_station(BaseStation::new(1, Point3::origin()));
+        tracker.set_constellation(constellation());
+
+        // the simulator runs whole sweep slots, its clock is the one of the sweeps
+        let now = |tracker: &LighthouseTracker| tracker.last_sweep_us().unwrap() as f64 / 1e6;
+        let mut calibrator = RoomCalibrator::new(constellation(), floor_pose());
+        run_until_ootx(&mut sim, &mut tracker);
+        let frame = Duration::from_millis(50);
+        while calibrator.floor_frames() < MIN_FLOOR_FRAMES + 5 {
+            feed(&mut sim, &mut tracker, frame);
+            calibrator.add_floor_frame(&tracker.collect_observations());
+        }
+        assert!(now(&tracker) < FLOOR_UNTIL_S);
+
+        // skip the move, then one frame of fresh sweeps per held pose
+        for i in 0..carried_poses().len() {
+            let settled = FLOOR_UNTIL_S + i as f64 * (HOLD_S + MOVE_S) + MOVE_S + 0.1;
+            let wait = Duration::from_secs_f64(settled - now(&tracker));
+            feed(&mut sim, &mut tracker, wait);
+            feed(&mut sim, &mut tracker, frame);
+            calibrator.add_frame(&tracker.collect_observations());
+        }
+
+        (calibrator, sim.base_stations())
+    }
+
+    fn assert_stations(solved: &[BaseStation], truth: &[BaseStation], tolerance_m: f32, tolerance_rad: f32) {
+        assert_eq!(solved.len(), truth.len());
+        for (solved, truth) in solved.iter().zip(truth) {
+            let distance = (solved.position - truth.position).norm();
+            let angle = solved.orientation.angle_to(&truth.orientation);
+            assert!(distance < tolerance_m, "station {}: {} m off", truth.id, distance);
+            assert!(angle < tolerance_rad, "station {}: {} rad off", truth.id, angle);
+        }
+    }
+
+    #[test]
+    fn floor_capture_recovers_both_stations() {
+        let (calibrator, truth) = record(0.0);
+        let solution = calibrator.solve(false).unwrap();
+        assert_eq!(solution.frames, 0);
+        assert!(solution.reprojection_error < 1e-4, "{}", solution.reprojection_error);
+        assert_stations(&solution.stations, &truth, 0.001, 0.001);
+    }
+
+    #[test]
+    fn bundle_adjustment_recovers_both_stations() {
+        let (calibrator, truth) = record(0.2);
+        assert_eq!(calibrator.frames(), carried_poses().len());
+
+        let floor_only = calibrator.solve(false).unwrap();
+        assert_stations(&floor_only.stations, &truth, 0.01, 0.003);
+
+        let adjusted = calibrator.solve(true).unwrap();
+        assert_eq!(adjusted.frames, carried_poses().len());
+        assert_stations(&adjusted.stations, &truth, 0.01, 0.003);
+    }
+
+    #[test]
+    fn too_few_floor_frames() {
+        let calibrator = RoomCalibrator::new(constellation(), floor_pose());
+        assert!(calibrator.solve(false).is_err());
+    }
+
+    #[test]
+    fn room_setup_round_trip() {
+        let (calibrator, _) = record(0.0);
+        let mut stations = calibrator.solve(false).unwrap().stations;
+        stations[0].unique_id = Some(0x1000);
+        stations[0].mode = Some(StationMode::A);
+        stations[0].calibration = Some(BaseStationCalibration::default());
+        stations[1].mode = Some(StationMode::B);
+
+        let path = std::env::temp_dir().join(format!("librevr-{}-{}", std::process::id(), ROOM_SETUP_FILE));
+        let path = path.to_str().unwrap();
+        RoomSetup::from_stations(&stations).save(path).unwrap();
+        let loaded = RoomSetup::load(path);
+        fs::remove_file(path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.stations["lighthouse0"].id.as_deref(), Some("0x00001000"));
+        assert_eq!(loaded.stations["lighthouse1"].mode.as_deref(), Some("B"));
+        for (loaded, saved) in loaded.to_stations().iter().zip(&stations) {
+            assert_eq!(loaded.id, saved.id);
+            assert_eq!(loaded.position, saved.position);
+            assert!(loaded.orientation.angle_to(&saved.orientation) < 1e-6);
+            assert_eq!(loaded.unique_id, saved.unique_id);
+            assert_eq!(loaded.mode, saved.mode);
+            assert_eq!(loaded.calibration, saved.calibration);
+        }
+    }
+}
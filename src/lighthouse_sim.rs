@@ -0,0 +1,400 @@
+// synthetic lighthouse v1 setup for testing without hardware
+// produces the same pulse stream LighthouseTracker::process_photodiode_pulse
+// consumes: sync flashes with ootx bits, skip flashes, sweep hits, occlusion,
+// timing noise and the sync width skew caused by slow led decay
+
+use std::time::Duration;
+use nalgebra::{Isometry3, Translation3};
+use crate::lighthouse_calibration::BaseStationCalibration;
+use crate::lighthouse_ootx::{encode_frame, BaseStationInfo};
+use crate::lighthouse_pose::SensorConstellation;
+use crate::lighthouse_pulse::{angle_to_sweep_delta, Axis, SweepSlot};
+use crate::lighthouse_tracking::BaseStation;
+
+const SLOT_US: f64 = 8333.0;
+// the station that is not sweeping flashes a skip pulse this long after the other one
+const SKIP_FLASH_DELAY_US: f64 = 400.0;
+const SWEEP_WIDTH_US: f64 = 12.0;
+// sweeps only cover +-60 deg
+const MAX_SWEEP_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+// sensors facing more than ~80 deg away from a station do not see it
+const MIN_FACING_DOT: f32 = 0.17;
+
+// nominal sync widths per tdma slot and ootx bit (centre of the doc buckets)
+fn sync_width_us(slot: SweepSlot, ootx_bit: bool) -> f64 {
+    let base = match slot {
+        SweepSlot::AHorizontal => 65.0,
+        SweepSlot::AVertical => 93.0,
+        SweepSlot::BHorizontal | SweepSlot::BVertical => 121.0,
+    };
+    if ootx_bit { base + 13.0 } else { base }
+}
+const SKIP_WIDTH_US: f64 = 146.0;
+
+// one virtual base station, ootx data is broadcast bit by bit like the real one
+pub struct SimStation {
+    pub station: BaseStation,
+    pub info: BaseStationInfo,
+    // fcal as the tracker will see it after half float rounding
+    calibration: BaseStationCalibration,
+    ootx_bits: Vec<bool>,
+    ootx_pos: usize,
+}
+
+impl SimStation {
+    pub fn new(station: BaseStation, info: BaseStationInfo) -> Self {
+        let payload = info.to_bytes();
+        let rounded = BaseStationInfo::parse(&payload).unwrap_or_else(|| info.clone());
+
+        Self {
+            station,
+            calibration: BaseStationCalibration::from_ootx(&rounded),
+            info,
+            ootx_bits: encode_frame(&payload),
+            ootx_pos: 0,
+        }
+    }
+
+    fn next_ootx_bit(&mut self) -> bool {
+        let bit = self.ootx_bits[self.ootx_pos];
+        self.ootx_pos = (self.ootx_pos + 1) % self.ootx_bits.len();
+        bit
+    }
+}
+
+// keyframed device motion, poses are interpolated (lerp + slerp)
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    keyframes: Vec<(f64, Isometry3<f32>)>,
+}
+
+impl Trajectory {
+    pub fn stationary(pose: Isometry3<f32>) -> Self {
+        Self {
+            keyframes: vec![(0.0, pose)],
+        }
+    }
+
+    // (seconds, world_from_device), sorted by time
+    pub fn new(mut keyframes: Vec<(f64, Isometry3<f32>)>) -> Self {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keyframes }
+    }
+
+    pub fn pose_at(&self, t: f64) -> Isometry3<f32> {
+        let Some(first) = self.keyframes.first() else {
+            return Isometry3::identity();
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+
+        for pair in self.keyframes.windows(2) {
+            let (t0, p0) = pair[0];
+            let (t1, p1) = pair[1];
+            if t <= t1 {
+                let s = ((t - t0) / (t1 - t0).max(1e-9)) as f32;
+                return Isometry3::from_parts(
+                    Translation3::from(p0.translation.vector.lerp(&p1.translation.vector, s)),
+                    p0.rotation.slerp(&p1.rotation, s),
+                );
+            }
+        }
+        self.keyframes[self.keyframes.len() - 1].1
+    }
+}
+
+// photodiode pulse as the hardware would report it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimPulse {
+    pub sensor_id: u8,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+// device pose at the start of every tdma slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimGroundTruth {
+    pub time_us: f64,
+    pub slot: SweepSlot,
+    pub pose: Isometry3<f32>,
+}
+
+pub struct LighthouseSimulator {
+    stations: Vec<SimStation>,
+    constellation: SensorConstellation,
+    trajectory: Trajectory,
+    // gaussian timing jitter on every edge (microseconds)
+    pub timing_noise_us: f64,
+    // chance that a sensor misses a sweep
+    pub occlusion_probability: f64,
+    // extra width the comparator adds to sync pulses
+    pub decay_skew_us: f64,
+    // random short pulses per slot (reflections, ambient light)
+    pub spurious_per_slot: f64,
+    rng: XorShift,
+    time_us: f64,
+    slot_index: usize,
+}
+
+impl LighthouseSimulator {
+    pub fn new(stations: Vec<SimStation>, constellation: SensorConstellation, trajectory: Trajectory) -> Self {
+        Self {
+            stations,
+            constellation,
+            trajectory,
+            timing_noise_us: 0.0,
+            occlusion_probability: 0.0,
+            decay_skew_us: 0.0,
+            spurious_per_slot: 0.0,
+            rng: XorShift::new(0x5eed_1164),
+            time_us: 1000.0,
+            slot_index: 0,
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = XorShift::new(seed);
+    }
+
+    // stations as the tracker should know them (pose only, fcal comes over ootx)
+    pub fn base_stations(&self) -> Vec<BaseStation> {
+        self.stations.iter().map(|s| s.station.clone()).collect()
+    }
+
+    // simulate `duration` of pulses, sorted by start time
+    pub fn run(&mut self, duration: Duration) -> (Vec<SimPulse>, Vec<SimGroundTruth>) {
+        let end_us = self.time_us + duration.as_secs_f64() * 1e6;
+        let mut pulses = Vec::new();
+        let mut truth = Vec::new();
+
+        while self.time_us < end_us {
+            let slot = SweepSlot::ALL[self.slot_index % SweepSlot::ALL.len()];
+            self.slot_index += 1;
+
+            if slot.station() < self.stations.len() {
+                let pose = self.trajectory.pose_at(self.time_us / 1e6);
+                truth.push(SimGroundTruth {
+                    time_us: self.time_us,
+                    slot,
+                    pose,
+                });
+                self.emit_slot(slot, &pose, &mut pulses);
+            }
+            self.time_us += SLOT_US;
+        }
+
+        pulses.sort_by_key(|p| p.start);
+        (pulses, truth)
+    }
+
+    fn emit_slot(&mut self, slot: SweepSlot, pose: &Isometry3<f32>, pulses: &mut Vec<SimPulse>) {
+        let t0 = self.time_us;
+        let active = slot.station();
+        let ootx_bit = self.stations[active].next_ootx_bit();
+
+        // sync flashes, the idle station sends a skip pulse
+        let sync_width = sync_width_us(slot, ootx_bit) + self.decay_skew_us;
+        self.emit_flash(active, t0, sync_width, pose, pulses);
+        for other in 0..self.stations.len() {
+            if other != active {
+                let width = SKIP_WIDTH_US + self.decay_skew_us;
+                self.emit_flash(other, t0 + SKIP_FLASH_DELAY_US, width, pose, pulses);
+            }
+        }
+
+        // laser sweep
+        let station_pose = self.stations[active].station.clone();
+        let calibration = self.stations[active].calibration;
+        let to_local = station_pose.orientation.inverse();
+        for id in 0..self.constellation.len() {
+            let world = pose * self.constellation.sensors[id].position;
+            let local = to_local * (world - station_pose.position);
+            if local.z >= 0.0 || !self.facing(active, pose, id) {
+                continue;
+            }
+
+            let ideal = [local.x.atan2(-local.z), local.y.atan2(-local.z)];
+            if ideal[0].abs() > MAX_SWEEP_ANGLE || ideal[1].abs() > MAX_SWEEP_ANGLE {
+                continue;
+            }
+            if self.rng.next_f64() < self.occlusion_probability {
+                continue;
+            }
+
+            let measured = calibration.distort(ideal);
+            let angle = match slot.axis() {
+                Axis::Horizontal => measured[0],
+                Axis::Vertical => measured[1],
+            };
+            let start = t0 + angle_to_sweep_delta(angle) as f64 + self.noise();
+            let width = SWEEP_WIDTH_US + self.noise().abs();
+            pulses.push(pulse(id as u8, start, width));
+        }
+
+        // spurious hits
+        let mut count = self.spurious_per_slot.floor() as usize;
+        if self.rng.next_f64() < self.spurious_per_slot.fract() {
+            count += 1;
+        }
+        for _ in 0..count {
+            let id = (self.rng.next_u64() % self.constellation.len().max(1) as u64) as u8;
+            let start = t0 + 1300.0 + self.rng.next_f64() * 5400.0;
+            pulses.push(pulse(id, start, 5.0 + self.rng.next_f64() * 20.0));
+        }
+    }
+
+    fn emit_flash(&mut self, station: usize, t: f64, width: f64, pose: &Isometry3<f32>, pulses: &mut Vec<SimPulse>) {
+        for id in 0..self.constellation.len() {
+            if self.facing(station, pose, id) {
+                let start = t + self.noise();
+                pulses.push(pulse(id as u8, start, width + self.noise()));
+            }
+        }
+    }
+
+    fn facing(&self, station: usize, pose: &Isometry3<f32>, id: usize) -> bool {
+        let sensor = &self.constellation.sensors[id];
+        let world = pose * sensor.position;
+        let to_station = self.stations[station].station.position - world;
+        (pose.rotation * sensor.normal).dot(&to_station.normalize()) > MIN_FACING_DOT
+    }
+
+    fn noise(&mut self) -> f64 {
+        if self.timing_noise_us <= 0.0 {
+            0.0
+        } else {
+            self.rng.next_gaussian() * self.timing_noise_us
+        }
+    }
+}
+
+fn pulse(sensor_id: u8, start_us: f64, duration_us: f64) -> SimPulse {
+    SimPulse {
+        sensor_id,
+        start: Duration::from_secs_f64(start_us.max(0.0) / 1e6),
+        duration: Duration::from_secs_f64(duration_us.max(0.0) / 1e6),
+    }
+}
+
+// small deterministic prng so runs are reproducible (xorshift64*)
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // box-muller
+    fn next_gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64().max(1e-12);
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+// stations and pulse feeding shared by the simulator and room setup tests
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use std::time::Duration;
+    use nalgebra::Point3;
+    use super::{LighthouseSimulator, SimStation};
+    use crate::lighthouse_ootx::{BaseStationInfo, StationMode};
+    use crate::lighthouse_pose::test_fixtures;
+    use crate::lighthouse_tracking::LighthouseTracker;
+
+    pub fn station(id: u8, position: Point3<f32>, mode: StationMode, fcal_sign: f32) -> SimStation {
+        let station = test_fixtures::station(id, position);
+        let info = BaseStationInfo {
+            protocol_version: 6,
+            firmware_version: 436,
+            id: 0x1000 + id as u32,
+            fcal_phase: [0.012 * fcal_sign, -0.008],
+            fcal_tilt: [-0.004, 0.006 * fcal_sign],
+            fcal_curve: [0.002, -0.001],
+            fcal_gibphase: [1.7, -0.6],
+            fcal_gibmag: [0.003, 0.002],
+            unlock_count: 0,
+            hardware_version: 9,
+            accel_dir: [0, 127, 0],
+            mode: Some(mode),
+            fault_flags: 0,
+        };
+        SimStation::new(station, info)
+    }
+
+    // a and b in opposite corners of a 4 x 4 m room, different fcal
+    pub fn two_stations() -> Vec<SimStation> {
+        vec![
+            station(0, Point3::new(-2.0, 2.2, 2.0), StationMode::A, 1.0),
+            station(1, Point3::new(2.0, 2.2, -2.0), StationMode::B, -1.0),
+        ]
+    }
+
+    pub fn feed(sim: &mut LighthouseSimulator, tracker: &mut LighthouseTracker, duration: Duration) {
+        let (pulses, _) = sim.run(duration);
+        for p in pulses {
+            tracker.process_photodiode_pulse(p.sensor_id, p.start, p.duration);
+        }
+    }
+
+    // fcal only reaches the tracker over ootx, one bit per sweep
+    pub fn run_until_ootx(sim: &mut LighthouseSimulator, tracker: &mut LighthouseTracker) -> Duration {
+        let mut elapsed = Duration::ZERO;
+        while tracker.get_station_info(0).is_none() || tracker.get_station_info(1).is_none() {
+            assert!(elapsed < Duration::from_secs(20), "ootx not decoded after {:?}", elapsed);
+            feed(sim, tracker, Duration::from_millis(100));
+            elapsed += Duration::from_millis(100);
+        }
+        elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_fixtures::{feed, run_until_ootx, two_stations};
+    use nalgebra::Vector3;
+    use crate::lighthouse_ootx::StationMode;
+    use crate::lighthouse_pose::test_fixtures::constellation;
+    use crate::lighthouse_tracking::LighthouseTracker;
+
+    #[test]
+    fn two_stations_end_to_end() {
+        let truth = Isometry3::new(Vector3::new(0.2, 1.5, -0.1), Vector3::new(0.2, 0.9, -0.3));
+        let mut sim = LighthouseSimulator::new(two_stations(), constellation(), Trajectory::stationary(truth));
+        sim.timing_noise_us = 0.05;
+        sim.decay_skew_us = 15.0;
+
+        let mut tracker = LighthouseTracker::new(32);
+        tracker.set_sync_decay_offset(15);
+        for station in sim.base_stations() {
+            tracker.add_base_station(station);
+        }
+        tracker.set_constellation(constellation());
+
+        run_until_ootx(&mut sim, &mut tracker);
+        assert_eq!(tracker.get_station_info(0).unwrap().id, 0x1000);
+        assert_eq!(tracker.get_station_info(1).unwrap().mode, Some(StationMode::B));
+
+        // a few fresh sweeps with the calibration applied
+        feed(&mut sim, &mut tracker, Duration::from_millis(100));
+
+        let solution = tracker.solve_pose().unwrap();
+        assert_eq!(solution.stations, 2);
+        let error = (solution.pose.translation.vector - truth.translation.vector).norm();
+        assert!(error < 0.001, "position off by {} m", error);
+        assert!(solution.pose.rotation.angle_to(&truth.rotation) < 0.005);
+    }
+}
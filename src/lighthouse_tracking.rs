@@ -1,21 +1,12 @@
 use std::time::{Duration, Instant};
 use nalgebra::{Vector3, Point3};
-
-extern "C" {
-    // fotodiodo pulse bat prozesatu
-    // itzulera: 0=ezezaguna, 1=sync, 2=sweep
-    fn lighthouse_process_pulse(
-        pulse_start_us: u64,
-        pulse_duration_us: u64,
-        sensor_id: u64,
-    ) -> u64;
-    
-    // uneko angeluak lortu (pointer [f32; 2]-ra: [horizontal, vertical])
-    fn lighthouse_decode_angles() -> *const f32;
-    
-    // egoera garbitu
-    fn lighthouse_reset_state();
-}
+use crate::lighthouse_pulse::{PulseDecoder, SweepHit, SweepSlot};
+use crate::lighthouse_ootx::{BaseStationInfo, OotxDecoder, StationMode};
+use crate::lighthouse_calibration::BaseStationCalibration;
+use crate::lighthouse_pose::{AngleObservation, PoseSolution, PoseSolver, SensorConstellation};
+use crate::lighthouse_room::RoomSetup;
+use crate::hid_reports::{LightEvent, LightStream};
+use crate::device_config::DeviceConfig;
 
 // lighthouse base station bat (bi behar dira posizio 3d-rako)
 #[derive(Debug, Clone)]
@@ -23,6 +14,13 @@ pub struct BaseStation {
     pub id: u8,
     pub position: Point3<f32>,
     pub orientation: nalgebra::UnitQuaternion<f32>,
+    // ootx paketetik (none dekodetu arte)
+    pub unique_id: Option<u32>,
+    pub firmware_version: Option<u16>,
+    pub mode: Option<StationMode>,
+    pub fault_flags: u8,
+    // fcal zuzenketa (ootx-tik edo fitxategitik)
+    pub calibration: Option<BaseStationCalibration>,
 }
 
 impl BaseStation {
@@ -31,50 +29,141 @@ impl BaseStation {
             id,
             position,
             orientation: nalgebra::UnitQuaternion::identity(),
+            unique_id: None,
+            firmware_version: None,
+            mode: None,
+            fault_flags: 0,
+            calibration: None,
+        }
+    }
+    
+    // fcal fitxategitik kargatu (lighthousedb.json-eko "fcal" blokea)
+    pub fn load_calibration(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.calibration = Some(BaseStationCalibration::load(path)?);
+        Ok(())
+    }
+    
+    // angelu gordinak fcal-ekin zuzendu
+    pub fn correct_angles(&self, angles: [f32; 2]) -> [f32; 2] {
+        match self.calibration {
+            Some(cal) => cal.correct(angles),
+            None => angles,
         }
     }
+    
+    // ootx paketeko informazioa aplikatu
+    pub fn apply_ootx(&mut self, info: &BaseStationInfo) {
+        self.unique_id = Some(info.id);
+        self.firmware_version = Some(info.firmware_version);
+        self.mode = info.mode;
+        self.fault_flags = info.fault_flags;
+        self.calibration = Some(BaseStationCalibration::from_ootx(info));
+    }
 }
 
 // lighthouse tracker nagusia
 pub struct LighthouseTracker {
     base_stations: Vec<BaseStation>,
+    decoder: PulseDecoder,
+    // 0x21 hid report-en erlojua (48mhz, 64 bit-era hedatuta)
+    light: LightStream,
+    // ootx bit-ak station bakoitzeko (0 = a, 1 = b)
+    ootx: [OotxDecoder; 2],
+    station_info: [Option<BaseStationInfo>; 2],
     sensors: Vec<SensorState>,
+    // zenbat micros-ko angeluak onartzen dira oraindik freskotzat
+    max_sweep_age_us: u64,
+    // gailu osoaren pose-a (sentsoreen kokapenak behar dira)
+    pose_solver: Option<PoseSolver>,
+    last_pose: Option<PoseSolution>,
     start_time: Instant,
     frame_count: u64,
 }
 
+// tdma ziklo bat (a-h, a-v, b-h, b-v) 4 x 8333us da, bi ziklo baino
+// zaharragoak diren angeluak ez dira triangulaziorako erabiltzen
+const DEFAULT_MAX_SWEEP_AGE_US: u64 = 2 * 4 * 8333;
+
+// residual honetan konfiantza 1/e-ra jaisten da (metroak)
+const RESIDUAL_SCALE_M: f32 = 0.01;
+
+// sweep angelu bat eta noiz jaso zen
+#[derive(Debug, Clone, Copy)]
+struct SweepSample {
+    angle: f32,  // radianak, fcal gabe
+    timestamp_us: u64,
+}
+
 // sentsore bakoitzeko egoera
 #[derive(Debug, Clone)]
 struct SensorState {
     sensor_id: u8,
-    last_angles: [f32; 2],  // [horizontal, vertical] radianak
-    position: Option<Point3<f32>>,
+    // tdma slot bakoitzeko azken angelua (SweepSlot::index())
+    sweeps: [Option<SweepSample>; 4],
+    position: Option<Triangulation>,
+}
+
+// triangulazio emaitza
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Triangulation {
+    pub position: Point3<f32>,
+    // bi ray-en arteko distantzia puntu hurbilenean (metroak)
+    pub residual: f32,
+    // 0-1, residual eta ray-en arteko angeluaren arabera
+    pub confidence: f32,
+    pub timestamp_us: u64,
+}
+
+impl SensorState {
+    // station baten [h, v] angeluak, biak freskoak badira
+    fn station_angles(&self, station: usize, now_us: u64, max_age_us: u64) -> Option<[f32; 2]> {
+        let fresh = |slot: SweepSlot| {
+            self.sweeps[slot.index()]
+                .filter(|s| now_us.saturating_sub(s.timestamp_us) <= max_age_us)
+                .map(|s| s.angle)
+        };
+        
+        let (h, v) = match station {
+            0 => (SweepSlot::AHorizontal, SweepSlot::AVertical),
+            _ => (SweepSlot::BHorizontal, SweepSlot::BVertical),
+        };
+        
+        Some([fresh(h)?, fresh(v)?])
+    }
 }
 
 impl LighthouseTracker {
     pub fn new(num_sensors: usize) -> Self {
-        unsafe {
-            lighthouse_reset_state();
-        }
-        
         let sensors = (0..num_sensors)
             .map(|i| SensorState {
                 sensor_id: i as u8,
-                last_angles: [0.0, 0.0],
+                sweeps: [None; 4],
                 position: None,
             })
             .collect();
         
         Self {
             base_stations: Vec::new(),
+            decoder: PulseDecoder::new(),
+            light: LightStream::new(),
+            ootx: [OotxDecoder::new(), OotxDecoder::new()],
+            station_info: [None, None],
             sensors,
+            max_sweep_age_us: DEFAULT_MAX_SWEEP_AGE_US,
+            pose_solver: None,
+            last_pose: None,
             start_time: Instant::now(),
             frame_count: 0,
         }
     }
     
     // base station bat gehitu
-    pub fn add_base_station(&mut self, station: BaseStation) {
+    pub fn add_base_station(&mut self, mut station: BaseStation) {
+        // ootx jada jasota badago, aplikatu
+        if let Some(Some(info)) = self.station_info.get(self.base_stations.len()) {
+            station.apply_ootx(info);
+        }
+        
         println!(
             "base station {} gehituta: pos=[{:.2}, {:.2}, {:.2}]",
             station.id,
@@ -93,91 +182,293 @@ impl LighthouseTracker {
         pulse_start: Duration,
         pulse_duration: Duration,
     ) -> PulseType {
-        let pulse_start_us = pulse_start.as_micros() as u64;
-        let pulse_duration_us = pulse_duration.as_micros() as u64;
-        
-        let result = unsafe {
-            lighthouse_process_pulse(
-                pulse_start_us,
-                pulse_duration_us,
-                sensor_id as u64,
-            )
+        // nanosegundoak: 48 mhz erlojuaren zehaztasuna angelura arte mantentzen da
+        let result = self.decoder.process_pulse(
+            pulse_start.as_nanos() as u64,
+            pulse_duration.as_nanos() as u64,
+            sensor_id,
+        );
+        
+        if let Some(sync) = self.decoder.take_new_sync() {
+            // sync pulse - ootx bit-a station-aren bufferrera
+            if let Some(slot) = sync.slot {
+                self.push_ootx_bit(slot.station(), sync.ootx_bit);
+            }
+        }
+        
+        if result == PulseType::Sweep {
+            // sweep pulse - angeluak eguneratu
+            if let Some(hit) = self.decoder.last_sweep() {
+                self.update_sensor_angles(hit);
+            }
+        }
+        
+        result
+    }
+    
+    // 0x21 light report gordina prozesatu (hidraw-etik zuzenean)
+    pub fn process_light_report(&mut self, report: &[u8]) -> usize {
+        let events = self.light.push_report(report);
+        self.process_light_events(&events);
+        events.len()
+    }
+    
+    // dekodetutako light event-ak pulse decoder-era
+    pub fn process_light_events(&mut self, events: &[LightEvent]) {
+        for event in events {
+            self.process_photodiode_pulse(event.sensor_id, event.start(), event.duration());
+        }
+    }
+    
+    // ootx bit bat gehitu, pakete osoa badago base station eguneratu
+    fn push_ootx_bit(&mut self, station: usize, bit: bool) {
+        let Some(payload) = self.ootx[station].push_bit(bit) else {
+            return;
+        };
+        let Some(info) = BaseStationInfo::parse(&payload) else {
+            println!("station {}: ootx paketea ezezaguna ({} byte)", station, payload.len());
+            return;
         };
         
-        match result {
-            1 => {
-                // sync pulse - ez dago ezer gehiago egitekorik oraingoz
-                PulseType::Sync
+        println!(
+            "station {}: id={:08x} fw={} mode={:?} faults={:#04x}",
+            station,
+            info.id,
+            info.firmware_version,
+            info.mode,
+            info.fault_flags
+        );
+        
+        if let Some(base_station) = self.base_stations.get_mut(station) {
+            // room setup beste station batekin egin bada, pose-ak ez datoz bat
+            if let Some(known) = base_station.unique_id.filter(|&id| id != info.id) {
+                println!(
+                    "station {}: id={:08x} baina room setup-ean {:08x}, berriz kalibratu",
+                    station, info.id, known
+                );
             }
-            2 => {
-                // sweep pulse - angeluak eguneratu
-                self.update_sensor_angles(sensor_id);
-                PulseType::Sweep
+            base_station.apply_ootx(&info);
+        }
+        self.station_info[station] = Some(info);
+    }
+    
+    // station baten ootx informazioa (0 = a, 1 = b)
+    pub fn get_station_info(&self, station: usize) -> Option<&BaseStationInfo> {
+        self.station_info.get(station).and_then(|i| i.as_ref())
+    }
+    
+    // sentsoreen kokapenak eta normalak gailuaren koordenatuetan
+    pub fn set_constellation(&mut self, constellation: SensorConstellation) {
+        self.pose_solver = Some(PoseSolver::new(constellation));
+        self.last_pose = None;
+    }
+    
+    // sentsoreen kokapenak (none gailuaren konfigurazioa irakurri arte)
+    pub fn constellation(&self) -> Option<&SensorConstellation> {
+        self.pose_solver.as_ref().map(|solver| solver.constellation())
+    }
+    
+    // gailuaren konfiguraziotik (firmware-ko json) sentsoreak hartu
+    // sentsore kopurua ere konfiguraziora egokitzen da
+    pub fn apply_device_config(&mut self, config: &DeviceConfig) -> bool {
+        let Some(constellation) = config.constellation() else {
+            return false;
+        };
+        
+        for i in self.sensors.len()..constellation.len() {
+            self.sensors.push(SensorState {
+                sensor_id: i as u8,
+                sweeps: [None; 4],
+                position: None,
+            });
+        }
+        println!("{} sentsore gailuaren konfiguraziotik", constellation.len());
+        self.set_constellation(constellation);
+        true
+    }
+    
+    // angelu fresko guztiak, fcal zuzenduta, pose solver-erako
+    pub fn collect_observations(&self) -> Vec<AngleObservation> {
+        let Some(now_us) = self.decoder.last_sweep().map(|h| h.timestamp_us()) else {
+            return Vec::new();
+        };
+        
+        let mut observations = Vec::new();
+        for sensor in &self.sensors {
+            for (station, bs) in self.base_stations.iter().take(2).enumerate() {
+                if let Some(raw) = sensor.station_angles(station, now_us, self.max_sweep_age_us) {
+                    observations.push(AngleObservation {
+                        sensor_id: sensor.sensor_id,
+                        station,
+                        angles: bs.correct_angles(raw),
+                    });
+                }
             }
-            _ => PulseType::Unknown,
         }
+        observations
     }
     
-    // sentsore baten angeluak eguneratu asm-tik irakurrita
-    fn update_sensor_angles(&mut self, sensor_id: u8) {
-        let angles_ptr = unsafe { lighthouse_decode_angles() };
-        let angles = unsafe { std::slice::from_raw_parts(angles_ptr, 2) };
+    // gailuaren 6dof pose-a kalkulatu (station bat nahikoa da 4+ sentsorekin)
+    pub fn solve_pose(&mut self) -> Option<PoseSolution> {
+        let solver = self.pose_solver.as_ref()?;
+        let observations = self.collect_observations();
         
-        if let Some(sensor) = self.sensors.get_mut(sensor_id as usize) {
-            sensor.last_angles = [angles[0], angles[1]];
-            
-            // bi base station badaude, 3d posizioa kalkulatu
-            if self.base_stations.len() >= 2 {
-                if let Some(pos) = self.triangulate_position(sensor) {
-                    sensor.position = Some(pos);
+        let solution = match self.last_pose {
+            Some(last) => solver.solve_with_guess(&self.base_stations, &observations, &last.pose),
+            None => solver.solve(&self.base_stations, &observations),
+        };
+        
+        if solution.is_some() {
+            self.last_pose = solution;
+            self.frame_count += 1;
+        }
+        solution
+    }
+    
+    // azken pose-a
+    pub fn get_pose(&self) -> Option<PoseSolution> {
+        self.last_pose
+    }
+    
+    // azken sweep-aren unea (gailuaren erlojua, micros), imu-ko denbora bera
+    pub fn last_sweep_us(&self) -> Option<u64> {
+        self.decoder.last_sweep().map(|h| h.timestamp_us())
+    }
+    
+    // angelu zaharren muga aldatu (micros)
+    pub fn set_max_sweep_age(&mut self, max_age: Duration) {
+        self.max_sweep_age_us = max_age.as_micros() as u64;
+    }
+    
+    // gailua deskonektatu eta berriz konektatu da: bere erlojua zerotik hasten da
+    // angeluak, ootx bit-ak eta pose-a ahazten dira, station-ak eta kalibrazioa ez
+    pub fn reset_device_clock(&mut self) {
+        self.decoder.reset();
+        self.light.reset();
+        for ootx in &mut self.ootx {
+            ootx.reset();
+        }
+        for sensor in &mut self.sensors {
+            sensor.sweeps = [None; 4];
+            sensor.position = None;
+        }
+        self.last_pose = None;
+    }
+    
+    // sync led-en deskarga motela konpentsatu (micros)
+    pub fn set_sync_decay_offset(&mut self, offset_us: u64) {
+        self.decoder.set_decay_offset(offset_us);
+    }
+    
+    // room setup fitxategia kargatu (lighthousedb.json), abiaraztean deitzen da
+    // aurreko station-ak ordezkatzen ditu, ootx-ko fcal-ak fitxategikoa gainidazten du
+    pub fn load_room_setup(&mut self, path: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let stations = RoomSetup::load(path)?.to_stations();
+        self.base_stations.clear();
+        self.last_pose = None;
+        for station in stations {
+            self.add_base_station(station);
+        }
+        Ok(self.base_stations.len())
+    }
+    
+    // uneko station-ak gorde (pose, ootx id, mode eta fcal)
+    pub fn save_room_setup(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        RoomSetup::from_stations(&self.base_stations).save(path)
+    }
+    
+    // kalibrazioaren pose-ak aplikatu, falta diren station-ak gehitu
+    pub fn set_station_poses(&mut self, stations: &[BaseStation]) {
+        for (index, station) in stations.iter().enumerate() {
+            match self.base_stations.get_mut(index) {
+                Some(existing) => {
+                    existing.position = station.position;
+                    existing.orientation = station.orientation;
                 }
+                None => self.add_base_station(station.clone()),
             }
         }
+        self.last_pose = None;
+    }
+    
+    // base station-ak (index = station, 0 = a)
+    pub fn base_stations(&self) -> &[BaseStation] {
+        &self.base_stations
+    }
+    
+    // sentsore baten angeluak eguneratu sweep hit-etik
+    fn update_sensor_angles(&mut self, hit: SweepHit) {
+        let Some(sensor) = self.sensors.get(hit.sensor_id as usize) else {
+            return;
+        };
+        
+        let mut sensor = sensor.clone();
+        sensor.sweeps[hit.slot.index()] = Some(SweepSample {
+            angle: hit.angle,
+            timestamp_us: hit.timestamp_us(),
+        });
+        
+        // bi base station badaude, 3d posizioa kalkulatu
+        if self.base_stations.len() >= 2
+            && let Some(result) = self.triangulate_position(&sensor, hit.timestamp_us())
+        {
+            sensor.position = Some(result);
+        }
+        
+        self.sensors[hit.sensor_id as usize] = sensor;
     }
     
     // sentsore baten 3d posizioa kalkulatu triangulazio bidez
-    // bi base station-etik angeluak erabiliz
-    fn triangulate_position(&self, sensor: &SensorState) -> Option<Point3<f32>> {
+    // bi base station-en angelu freskoak bakarrik konbinatzen dira
+    fn triangulate_position(&self, sensor: &SensorState, now_us: u64) -> Option<Triangulation> {
         if self.base_stations.len() < 2 {
             return None;
         }
         
-        let [angle_h, angle_v] = sensor.last_angles;
+        let mut rays = Vec::with_capacity(2);
+        for (station, bs) in self.base_stations.iter().take(2).enumerate() {
+            let raw = sensor.station_angles(station, now_us, self.max_sweep_age_us)?;
+            
+            // fcal zuzenketa station-aren koordenatuetan, gero mundura
+            let [angle_h, angle_v] = bs.correct_angles(raw);
+            rays.push(Ray {
+                origin: bs.position,
+                direction: bs.orientation * self.angle_to_ray_direction(angle_h, angle_v),
+            });
+        }
         
-        // base station 1-etik ray bat kalkulatu
-        let bs1 = &self.base_stations[0];
-        let ray1_dir = self.angle_to_ray_direction(angle_h, angle_v);
-        let ray1 = Ray {
-            origin: bs1.position,
-            direction: bs1.orientation * ray1_dir,
-        };
+        // bi ray-en intersekzio puntua kalkulatu (ray-ray closest point)
+        let (position, residual) = self.ray_ray_closest_point(&rays[0], &rays[1])?;
         
-        // base station 2-tik ere berdina (praktikan, bi base station-ak
-        // sweep desberdinak ikusten dira, baina sinplifikatzeko kasu honetan...)
-        let bs2 = &self.base_stations[1];
-        let ray2 = Ray {
-            origin: bs2.position,
-            direction: bs2.orientation * ray1_dir,
-        };
+        // ray paraleloak edo residual handia = konfiantza txikia
+        let sin_angle = rays[0].direction.cross(&rays[1].direction).norm();
+        let confidence = sin_angle * (-residual / RESIDUAL_SCALE_M).exp();
         
-        // bi ray-en intersekzio puntua kalkulatu (ray-ray closest point)
-        self.ray_ray_closest_point(&ray1, &ray2)
+        Some(Triangulation {
+            position,
+            residual,
+            confidence,
+            timestamp_us: now_us,
+        })
     }
     
     // angeluak ray direction-era bihurtu
+    // sweep bakoitzak plano bat definitzen du, ray-a bi planoen ebakidura da
+    // (station-a -z norabidera begira dago)
     fn angle_to_ray_direction(&self, angle_h: f32, angle_v: f32) -> Vector3<f32> {
-        // horizontal angelua: x inguruan biraketa
-        // vertical angelua: y inguruan biraketa
+        // horizontal angelua: y inguruan biratzen den planoa (x/-z)
+        // vertical angelua: x inguruan biratzen den planoa (y/-z)
         
-        let x = angle_h.sin() * angle_v.cos();
-        let y = angle_v.sin();
-        let z = -angle_h.cos() * angle_v.cos();
+        let x = angle_h.tan();
+        let y = angle_v.tan();
+        let z = -1.0;
         
         Vector3::new(x, y, z).normalize()
     }
     
     // bi ray-en arteko puntu hurbilena
-    fn ray_ray_closest_point(&self, ray1: &Ray, ray2: &Ray) -> Option<Point3<f32>> {
+    // itzulera: erdiko puntua eta bi puntuen arteko distantzia
+    fn ray_ray_closest_point(&self, ray1: &Ray, ray2: &Ray) -> Option<(Point3<f32>, f32)> {
         let w0 = ray1.origin - ray2.origin;
         let a = ray1.direction.dot(&ray1.direction);
         let b = ray1.direction.dot(&ray2.direction);
@@ -192,16 +483,24 @@ impl LighthouseTracker {
         
         let t1 = (b * e - c * d) / denom;
         let t2 = (a * e - b * d) / denom;
+        if t1 <= 0.0 || t2 <= 0.0 {
+            return None;  // station-en atzean
+        }
         
         let p1 = ray1.origin + ray1.direction * t1;
         let p2 = ray2.origin + ray2.direction * t2;
         
         // bi puntuen erdiko puntua itzuli
-        Some(Point3::from((p1 + p2.coords) / 2.0))
+        Some((Point3::from((p1 + p2.coords) / 2.0), (p1 - p2).norm()))
     }
     
     // uneko tracking egoera lortu
     pub fn get_tracked_position(&self, sensor_id: u8) -> Option<Point3<f32>> {
+        self.get_triangulation(sensor_id).map(|t| t.position)
+    }
+    
+    // posizioa residual eta konfiantzarekin
+    pub fn get_triangulation(&self, sensor_id: u8) -> Option<Triangulation> {
         self.sensors
             .get(sensor_id as usize)
             .and_then(|s| s.position)
@@ -214,14 +513,32 @@ impl LighthouseTracker {
         println!("sensors: {}", self.sensors.len());
         println!("frames: {}", self.frame_count);
         
+        if let Some(solution) = self.last_pose {
+            let t = solution.pose.translation.vector;
+            println!(
+                "pose: pos=[{:.3}, {:.3}, {:.3}] error={:.5} rad ({} obs, {} station)",
+                t.x, t.y, t.z,
+                solution.reprojection_error,
+                solution.observations,
+                solution.stations
+            );
+        }
+        
         for (i, sensor) in self.sensors.iter().enumerate() {
-            if let Some(pos) = sensor.position {
+            if let Some(tri) = sensor.position {
+                let deg = |slot: SweepSlot| {
+                    sensor.sweeps[slot.index()].map_or(f32::NAN, |s| s.angle.to_degrees())
+                };
                 println!(
-                    "  sensor {}: pos=[{:.3}, {:.3}, {:.3}] angles=[{:.3}, {:.3}]",
+                    "  sensor {}: pos=[{:.3}, {:.3}, {:.3}] residual={:.4}m conf={:.2} angles a=[{:.3}, {:.3}] b=[{:.3}, {:.3}]",
                     i,
-                    pos.x, pos.y, pos.z,
-                    sensor.last_angles[0].to_degrees(),
-                    sensor.last_angles[1].to_degrees()
+                    tri.position.x, tri.position.y, tri.position.z,
+                    tri.residual,
+                    tri.confidence,
+                    deg(SweepSlot::AHorizontal),
+                    deg(SweepSlot::AVertical),
+                    deg(SweepSlot::BHorizontal),
+                    deg(SweepSlot::BVertical)
                 );
             } else {
                 println!("  sensor {}: ez dago posiziorik", i);
@@ -0,0 +1,467 @@
+// lighthouse v2 sweep decoding, see docs/lighthouse.md
+//
+// every sweep beam carries a 6mhz biphase mark coded bitstream produced by a
+// 17-bit lfsr. the polynomial tells the channel (and the ootx bit), the lfsr
+// position tells how far the rotor has turned since the start of the revolution.
+// one rotor carries two slits tilted +-45 deg, both hits together give the same
+// [horizontal, vertical] angles as a v1 station
+
+use std::f32::consts::PI;
+use crate::lighthouse_pose::AngleObservation;
+
+pub const LFSR_BITS: u32 = 17;
+pub const LFSR_MASK: u32 = (1 << LFSR_BITS) - 1;
+pub const LFSR_PERIOD: u32 = LFSR_MASK;
+// state the lfsr is reset to at the start of every revolution
+pub const LFSR_SEED: u32 = 0x00001;
+pub const BMC_BIT_RATE_HZ: f32 = 6_000_000.0;
+pub const CHANNELS: usize = 16;
+
+// slit tilt relative to the rotor axis
+pub const SLIT_TILT: f32 = PI / 4.0;
+
+// extra bits past the first 17 needed before trusting a polynomial match
+const MIN_VERIFY_BITS: usize = 8;
+
+// 2 polynomials per channel (ootx bit 0 / 1), from bitcraze lighthouse-fpga
+pub const POLYNOMIALS: [u32; 32] = [
+    0x0001d258, 0x00017e04, // channel 1
+    0x0001ff6b, 0x00013f67, // channel 2
+    0x0001b9ee, 0x000198d1, // channel 3
+    0x000178c7, 0x00018a55, // channel 4
+    0x00015777, 0x0001d911, // channel 5
+    0x00015769, 0x0001991f, // channel 6
+    0x00012bd0, 0x0001cf73, // channel 7
+    0x0001365d, 0x000197f5, // channel 8
+    0x000194a0, 0x0001b279, // channel 9
+    0x00013a34, 0x0001ae41, // channel 10
+    0x000180d4, 0x00017891, // channel 11
+    0x00012e64, 0x00017c72, // channel 12
+    0x00019c6d, 0x00013f32, // channel 13
+    0x0001ae14, 0x00014e76, // channel 14
+    0x00013c97, 0x000130cb, // channel 15
+    0x00013750, 0x0001cb8d, // channel 16
+];
+
+// rotor period per channel in 48mhz ticks (~20ms, each channel slightly different)
+pub const ROTOR_PERIOD_TICKS: [u32; CHANNELS] = [
+    959000, 957000, 953000, 949000, 947000, 943000, 941000, 939000,
+    937000, 929000, 919000, 911000, 907000, 901000, 893000, 887000,
+];
+// one lfsr bit at 6mhz = 8 ticks at 48mhz
+const TICKS_PER_BIT: u32 = 8;
+
+// one lfsr step (fibonacci form, the state is the last 17 output bits)
+pub fn lfsr_step(state: u32, poly: u32) -> u32 {
+    let bit = (state & poly).count_ones() & 1;
+    ((state << 1) | bit) & LFSR_MASK
+}
+
+// output bits of an lfsr starting from `state`
+pub fn lfsr_sequence(poly: u32, mut state: u32, count: usize) -> Vec<bool> {
+    (0..count)
+        .map(|_| {
+            state = lfsr_step(state, poly);
+            state & 1 == 1
+        })
+        .collect()
+}
+
+// lfsr state after `steps` steps from the seed
+pub fn lfsr_state_at(poly: u32, steps: u32) -> u32 {
+    (0..steps % LFSR_PERIOD).fold(LFSR_SEED, |state, _| lfsr_step(state, poly))
+}
+
+// pack the first 17 bits (oldest first) into an lfsr state word
+pub fn bits_to_word(bits: &[bool]) -> Option<u32> {
+    if bits.len() < LFSR_BITS as usize {
+        return None;
+    }
+    Some(
+        bits[..LFSR_BITS as usize]
+            .iter()
+            .fold(0, |word, &bit| (word << 1) | bit as u32),
+    )
+}
+
+// biphase mark decoding of a sampled photodiode envelope
+// every bit starts with a transition, a 1 has an extra transition in the middle
+pub fn bmc_decode(samples: &[bool], samples_per_bit: f32) -> Vec<bool> {
+    // run lengths between transitions, the part before the first edge is dropped
+    let mut runs = Vec::new();
+    let mut run = 0usize;
+    let mut started = false;
+    for pair in samples.windows(2) {
+        run += 1;
+        if pair[0] != pair[1] {
+            if started {
+                runs.push(run);
+            }
+            started = true;
+            run = 0;
+        }
+    }
+
+    let threshold = samples_per_bit * 0.75;
+    let mut bits = Vec::with_capacity(runs.len());
+    let mut half_pending = false;
+    for run in runs {
+        let short = (run as f32) < threshold;
+        match (short, half_pending) {
+            (true, false) => half_pending = true,
+            (true, true) => {
+                bits.push(true);
+                half_pending = false;
+            }
+            (false, false) => bits.push(false),
+            // lone half bit, drop it and resync on the long one
+            (false, true) => {
+                half_pending = false;
+                bits.push(false);
+            }
+        }
+    }
+    bits
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LfsrMatch {
+    pub poly_index: usize,
+    // 1-16
+    pub channel: u8,
+    pub ootx_bit: bool,
+    // state after the first 17 bits
+    pub state: u32,
+}
+
+impl LfsrMatch {
+    fn new(poly_index: usize, state: u32) -> Self {
+        Self {
+            poly_index,
+            channel: (poly_index / 2 + 1) as u8,
+            ootx_bit: poly_index & 1 == 1,
+            state,
+        }
+    }
+}
+
+// find the polynomial that generated a bit sequence (17 + verification bits)
+pub fn match_bits(bits: &[bool]) -> Option<LfsrMatch> {
+    if bits.len() < LFSR_BITS as usize + MIN_VERIFY_BITS {
+        return None;
+    }
+    let state = bits_to_word(bits)?;
+    let rest = &bits[LFSR_BITS as usize..];
+
+    let mut found = None;
+    for (index, &poly) in POLYNOMIALS.iter().enumerate() {
+        if lfsr_sequence(poly, state, rest.len()) == rest {
+            if found.is_some() {
+                return None;  // ambiguous
+            }
+            found = Some(LfsrMatch::new(index, state));
+        }
+    }
+    found
+}
+
+// find the polynomial from two 17-bit words captured `distance` bits apart
+pub fn match_words(first: u32, second: u32, distance: u32) -> Option<LfsrMatch> {
+    let mut found = None;
+    for (index, &poly) in POLYNOMIALS.iter().enumerate() {
+        let state = (0..distance).fold(first & LFSR_MASK, |s, _| lfsr_step(s, poly));
+        if state == second & LFSR_MASK {
+            if found.is_some() {
+                return None;
+            }
+            found = Some(LfsrMatch::new(index, first & LFSR_MASK));
+        }
+    }
+    found
+}
+
+// state -> steps from the seed, built once per polynomial (512 kb each)
+pub struct LfsrOffsetTable {
+    offsets: Vec<u32>,
+}
+
+impl LfsrOffsetTable {
+    pub fn new(poly: u32) -> Self {
+        let mut offsets = vec![u32::MAX; 1 << LFSR_BITS];
+        let mut state = LFSR_SEED;
+        for step in 0..LFSR_PERIOD {
+            offsets[state as usize] = step;
+            state = lfsr_step(state, poly);
+        }
+        Self { offsets }
+    }
+
+    pub fn offset(&self, state: u32) -> Option<u32> {
+        self.offsets
+            .get((state & LFSR_MASK) as usize)
+            .copied()
+            .filter(|&o| o != u32::MAX)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slit {
+    First,
+    Second,
+}
+
+// rotor angle of a sweep hit, converted to the angle of the slit that caused it
+// (first slit centred at 2pi/3 of the revolution, second at 4pi/3)
+pub fn offset_to_slit_angle(channel: u8, offset_bits: u32) -> Option<(Slit, f32)> {
+    let period = *ROTOR_PERIOD_TICKS.get(channel.checked_sub(1)? as usize)?;
+    let ticks = offset_bits.checked_mul(TICKS_PER_BIT)?;
+    if ticks >= period {
+        return None;
+    }
+
+    let rotor = ticks as f32 / period as f32 * 2.0 * PI;
+    if rotor < PI {
+        Some((Slit::First, rotor - PI + PI / 3.0))
+    } else {
+        Some((Slit::Second, rotor - PI - PI / 3.0))
+    }
+}
+
+// both slit angles -> v1 style [horizontal, vertical]
+//   first  = h + asin(tan(tilt) * tan(v) * cos(h))
+//   second = h - asin(tan(tilt) * tan(v) * cos(h))
+pub fn slit_angles_to_v1(first: f32, second: f32) -> [f32; 2] {
+    let horizontal = (first + second) / 2.0;
+    let vertical = ((first - second) / 2.0).sin() / (SLIT_TILT.tan() * horizontal.cos());
+    [horizontal, vertical.atan()]
+}
+
+// inverse of slit_angles_to_v1
+pub fn v1_to_slit_angles(angles: [f32; 2]) -> [f32; 2] {
+    let [h, v] = angles;
+    let spread = (SLIT_TILT.tan() * v.tan() * h.cos()).clamp(-1.0, 1.0).asin();
+    [h + spread, h - spread]
+}
+
+// decoded v2 sweep hit on one sensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct V2SweepHit {
+    pub sensor_id: u8,
+    pub channel: u8,
+    pub ootx_bit: bool,
+    pub slit: Slit,
+    pub angle: f32,
+    pub timestamp_us: u64,
+}
+
+// turns envelopes / words into hits and pairs the two slits into observations
+pub struct V2Decoder {
+    tables: Vec<Option<LfsrOffsetTable>>,
+    // channel -> station index used in AngleObservation
+    stations: [Option<usize>; CHANNELS],
+    next_station: usize,
+    // last first-slit hit per (sensor, channel)
+    pending: Vec<V2SweepHit>,
+    // hits older than this are not paired
+    max_pair_us: u64,
+}
+
+impl V2Decoder {
+    pub fn new() -> Self {
+        Self {
+            tables: (0..POLYNOMIALS.len()).map(|_| None).collect(),
+            stations: [None; CHANNELS],
+            next_station: 0,
+            pending: Vec::new(),
+            max_pair_us: 25_000,
+        }
+    }
+
+    // pin a channel to a station index (otherwise assigned in order of appearance)
+    pub fn set_station(&mut self, channel: u8, station: usize) {
+        if let Some(slot) = self.stations.get_mut((channel as usize).wrapping_sub(1)) {
+            *slot = Some(station);
+            self.next_station = self.next_station.max(station + 1);
+        }
+    }
+
+    pub fn station_for_channel(&mut self, channel: u8) -> Option<usize> {
+        let slot = self.stations.get_mut((channel as usize).wrapping_sub(1))?;
+        if slot.is_none() {
+            *slot = Some(self.next_station);
+            self.next_station += 1;
+        }
+        *slot
+    }
+
+    fn offset(&mut self, m: &LfsrMatch) -> Option<u32> {
+        let table = self.tables[m.poly_index]
+            .get_or_insert_with(|| LfsrOffsetTable::new(POLYNOMIALS[m.poly_index]));
+        table.offset(m.state)
+    }
+
+    // sampled envelope starting at `timestamp_us`
+    pub fn process_envelope(
+        &mut self,
+        sensor_id: u8,
+        timestamp_us: u64,
+        samples: &[bool],
+        samples_per_bit: f32,
+    ) -> Option<V2SweepHit> {
+        let bits = bmc_decode(samples, samples_per_bit);
+        let m = match_bits(&bits)?;
+        self.process_match(sensor_id, timestamp_us, &m)
+    }
+
+    // already demodulated word, `state` is the lfsr state after its 17th bit
+    pub fn process_match(&mut self, sensor_id: u8, timestamp_us: u64, m: &LfsrMatch) -> Option<V2SweepHit> {
+        // offset of the first bit of the word
+        let offset = self.offset(m)?.checked_sub(LFSR_BITS - 1)?;
+        let (slit, angle) = offset_to_slit_angle(m.channel, offset)?;
+
+        Some(V2SweepHit {
+            sensor_id,
+            channel: m.channel,
+            ootx_bit: m.ootx_bit,
+            slit,
+            angle,
+            timestamp_us,
+        })
+    }
+
+    // pair a hit with the other slit of the same revolution
+    pub fn push_hit(&mut self, hit: V2SweepHit) -> Option<AngleObservation> {
+        self.pending.retain(|p| hit.timestamp_us.saturating_sub(p.timestamp_us) <= self.max_pair_us);
+
+        match hit.slit {
+            Slit::First => {
+                self.pending.retain(|p| !(p.sensor_id == hit.sensor_id && p.channel == hit.channel));
+                self.pending.push(hit);
+                None
+            }
+            Slit::Second => {
+                let index = self
+                    .pending
+                    .iter()
+                    .position(|p| p.sensor_id == hit.sensor_id && p.channel == hit.channel)?;
+                let first = self.pending.swap_remove(index);
+                let station = self.station_for_channel(hit.channel)?;
+
+                Some(AngleObservation {
+                    sensor_id: hit.sensor_id,
+                    station,
+                    angles: slit_angles_to_v1(first.angle, hit.angle),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES_PER_BIT: usize = 8;
+
+    // `count` lfsr bits whose first bit sits `offset` steps after the seed
+    fn bits_at(poly: u32, offset: u32, count: usize) -> Vec<bool> {
+        lfsr_sequence(poly, lfsr_state_at(poly, offset - 1), count)
+    }
+
+    // biphase mark envelope with idle samples on both ends
+    fn bmc_encode(bits: &[bool]) -> Vec<bool> {
+        let half = SAMPLES_PER_BIT / 2;
+        let mut level = false;
+        let mut samples = vec![level; SAMPLES_PER_BIT];
+        for &bit in bits {
+            level = !level;
+            samples.extend(std::iter::repeat_n(level, half));
+            if bit {
+                level = !level;
+            }
+            samples.extend(std::iter::repeat_n(level, half));
+        }
+        samples.extend(std::iter::repeat_n(!level, SAMPLES_PER_BIT));
+        samples
+    }
+
+    // lfsr offset at which a slit sweeps `angle`, inverse of offset_to_slit_angle
+    fn slit_offset(channel: u8, slit: Slit, angle: f32) -> u32 {
+        let rotor = match slit {
+            Slit::First => angle + PI - PI / 3.0,
+            Slit::Second => angle + PI + PI / 3.0,
+        };
+        let period = ROTOR_PERIOD_TICKS[channel as usize - 1] as f32;
+        (rotor / (2.0 * PI) * period / TICKS_PER_BIT as f32).round() as u32
+    }
+
+    #[test]
+    fn bmc_round_trip() {
+        let bits = bits_at(POLYNOMIALS[0], 1, 64);
+        assert_eq!(bmc_decode(&bmc_encode(&bits), SAMPLES_PER_BIT as f32), bits);
+    }
+
+    #[test]
+    fn every_polynomial_recovers_index_and_offset() {
+        for (index, &poly) in POLYNOMIALS.iter().enumerate() {
+            let offset = 1_000 + 3_517 * index as u32;
+            let bits = bits_at(poly, offset, 40);
+
+            let decoded = bmc_decode(&bmc_encode(&bits), SAMPLES_PER_BIT as f32);
+            let m = match_bits(&decoded).unwrap_or_else(|| panic!("polynomial {} not matched", index));
+            assert_eq!(m.poly_index, index);
+            assert_eq!(m.channel as usize, index / 2 + 1);
+            assert_eq!(m.ootx_bit, index % 2 == 1);
+
+            let table = LfsrOffsetTable::new(poly);
+            assert_eq!(table.offset(m.state), Some(offset + LFSR_BITS - 1));
+        }
+    }
+
+    #[test]
+    fn decoder_recovers_slit_angles() {
+        let mut decoder = V2Decoder::new();
+        for (index, &poly) in POLYNOMIALS.iter().enumerate() {
+            let channel = (index / 2 + 1) as u8;
+            for (slit, angle) in [(Slit::First, 0.42), (Slit::Second, -0.17)] {
+                let offset = slit_offset(channel, slit, angle);
+                let envelope = bmc_encode(&bits_at(poly, offset, 40));
+                let hit = decoder
+                    .process_envelope(3, 1_000, &envelope, SAMPLES_PER_BIT as f32)
+                    .unwrap();
+
+                assert_eq!(hit.channel, channel);
+                assert_eq!(hit.slit, slit);
+                // one lfsr bit is ~53 microradians of rotor
+                assert!((hit.angle - angle).abs() < 1e-4, "channel {} {:?}: {}", channel, slit, hit.angle);
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_pairs_slits_into_v1_angles() {
+        let mut decoder = V2Decoder::new();
+        let poly_index = 4;
+        let channel = 3;
+        let angles = [0.3, -0.2];
+        let slits = v1_to_slit_angles(angles);
+
+        let mut timestamp_us = 10_000;
+        let mut observation = None;
+        for (slit, angle) in [(Slit::First, slits[0]), (Slit::Second, slits[1])] {
+            let offset = slit_offset(channel, slit, angle);
+            let envelope = bmc_encode(&bits_at(POLYNOMIALS[poly_index], offset, 40));
+            let hit = decoder
+                .process_envelope(7, timestamp_us, &envelope, SAMPLES_PER_BIT as f32)
+                .unwrap();
+            observation = decoder.push_hit(hit);
+            timestamp_us += 6_000;
+        }
+
+        let observation = observation.unwrap();
+        assert_eq!(observation.sensor_id, 7);
+        assert_eq!(observation.station, 0);
+        assert!((observation.angles[0] - angles[0]).abs() < 1e-3, "{:?}", observation.angles);
+        assert!((observation.angles[1] - angles[1]).abs() < 1e-3, "{:?}", observation.angles);
+    }
+}
@@ -1,13 +1,39 @@
 mod session;
+mod hid_reports;
+mod controller_input;
+mod xr_input;
+mod device_config;
+mod hotplug;
+mod daemon;
+mod display_mode;
+mod imu_filter;
+mod sensor_fusion;
+mod haptics;
 mod tracking;
 mod metrics;
 mod output;
+mod lighthouse_pulse;
+mod lighthouse_ootx;
+mod lighthouse_calibration;
+mod lighthouse_pose;
+mod lighthouse_v2;
+mod lighthouse_sim;
+mod lighthouse_room;
+mod lighthouse_tracking;
 mod vr_renderer;
+#[path = "kernel-api.rs"]
+mod kernel_api;
 
+use std::sync::mpsc;
 use std::time::{Duration, Instant};
-use session::VrSession;
+use controller_input::Hand;
+use device_config::DeviceConfig;
+use hid_reports::{ImuSample, ImuStream};
+use kernel_api::KernelApi;
+use session::{BaseSpace, VrSession};
 use tracking::TrackingCollector;
-use metrics::SessionMetrics;
+use metrics::{HapticPulse, SessionMetrics};
+use haptics::{HapticPattern, HapticPlayer};
 use output::DataExporter;
 use vr_renderer::VrRenderer;
 
@@ -15,13 +41,59 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TODO:
     // --3dof : enable orientation-only tracking
     // --video <path> : optional video file to play to the hmd (decoder not implemented here)
+    // --daemon : read the headset over hidraw and survive reconnects (no openxr)
+    // --record <file> : with --daemon, capture all headset traffic to a file
+    // --replay <file> : run the daemon from a capture instead of the headset
+    // --space <stage|local|local-floor> : reference space poses are recorded in (default stage)
+    // --haptics <pattern.json> : play a scripted vibration pattern on the controllers
     let mut enable_3dof = false;
+    let mut haptic_pattern: Option<HapticPattern> = None;
+    let mut base_space = BaseSpace::Stage;
+    let mut daemon_mode = false;
+    let mut daemon_options = daemon::DaemonOptions::default();
     let mut _video_path: Option<String> = None;
     let args: Vec<String> = std::env::args().collect();
+
+    // room-setup : solve the base station poses with the headset on the floor
+    if args.get(1).is_some_and(|arg| arg == "room-setup") {
+        return daemon::room_setup();
+    }
+
+    // set-mode <90hz|120hz|WxH@RATE> / panel <on|off> / brightness <0-100>
+    if let Some(result) = display_mode::run_cli(&args[1..]) {
+        return result;
+    }
+
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "--3dof" => enable_3dof = true,
+            "--daemon" => daemon_mode = true,
+            "--record" => {
+                if i + 1 < args.len() {
+                    daemon_options.record = Some(args[i+1].clone());
+                    i += 1;
+                }
+            }
+            "--replay" => {
+                if i + 1 < args.len() {
+                    daemon_options.replay = Some(args[i+1].clone());
+                    daemon_mode = true;
+                    i += 1;
+                }
+            }
+            "--space" => {
+                if i + 1 < args.len() {
+                    base_space = args[i+1].parse()?;
+                    i += 1;
+                }
+            }
+            "--haptics" => {
+                if i + 1 < args.len() {
+                    haptic_pattern = Some(HapticPattern::load(&args[i+1])?);
+                    i += 1;
+                }
+            }
             "--video" => {
                 if i + 1 < args.len() {
                     _video_path = Some(args[i+1].clone());
@@ -36,8 +108,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("librevr starting...");
     println!("================================\n");
 
+    if daemon_mode {
+        return daemon::run(&daemon_options);
+    }
+
     // create xr + vulkan session
     let mut vr_session = VrSession::new()?;
+    if base_space == BaseSpace::LocalFloor && vr_session.local_floor.is_none() {
+        println!("runtime has no local-floor space, recording in local");
+    }
 
     // create a renderer (vulkan context is moved/cloned as needed)
     // in real code use Arc for shared ownership
@@ -46,8 +125,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // tracking and metrics
     let mut tracker = TrackingCollector::new();
     tracker.set_3dof(enable_3dof);
+    let imu_samples = if enable_3dof { spawn_imu_reader(&mut tracker) } else { None };
     let mut metrics = SessionMetrics::new();
     let start_time = Instant::now();
+    let mut haptic_player = haptic_pattern.map(|pattern| HapticPlayer::new(pattern, 0));
 
     println!("collecting 10 seconds of tracking data...\n");
 
@@ -55,17 +136,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     vr_session.run_loop(Duration::from_secs(10), |session, time| {
         let timestamp_ms = start_time.elapsed().as_millis() as u64;
 
+        // controller buttons from the openxr actions (synced by run_loop)
+        for hand in Hand::ALL {
+            if let Some(state) = session.input.controller_state(&session.session, hand)? {
+                tracker.update_controller_state(hand, state);
+            }
+        }
+
+        // headset imu for 3dof orientation, read on its own thread at ~1 khz
+        if let Some(receiver) = imu_samples.as_ref() {
+            for samples in receiver.try_iter() {
+                tracker.push_imu_samples(&samples);
+            }
+        }
+
         // collect tracking frame
         let frame = tracker.collect_frame(
-            &session.stage,
-            &session.hand_space_left,
-            &session.hand_space_right,
+            session.base_space(base_space),
+            &session.view,
+            &session.input,
             time,
             timestamp_ms,
         )?;
 
         metrics.add_frame(frame.clone());
 
+        // scripted vibration, every pulse is logged with its scheduled and issued time
+        if let Some(player) = haptic_player.as_mut() {
+            for due in player.due(timestamp_ms) {
+                let issued = start_time.elapsed();
+                session.vibrate(due.pulse.hand, due.pulse.duration(), due.pulse.frequency_hz, due.pulse.amplitude)?;
+                metrics.add_haptic_pulse(HapticPulse {
+                    hand: due.pulse.hand,
+                    pattern: Some(player.pattern().name.clone()),
+                    scheduled_ms: Some(due.scheduled_ms),
+                    issued_ms: issued.as_secs_f64() * 1000.0,
+                    call_us: (start_time.elapsed() - issued).as_micros() as u64,
+                    display_time_ns: time.as_nanos(),
+                    duration_ms: due.pulse.duration_ms,
+                    frequency_hz: due.pulse.frequency_hz,
+                    amplitude: due.pulse.amplitude,
+                });
+            }
+        }
+
         if tracker.get_frame_count() % 30 == 0 {
             tracker.print_live_stats(&frame);
         }
@@ -96,4 +210,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("run `python3 analyze_tracking.py` to view graphs");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// read 0x20 imu reports from the headset hidraw node until it goes away
+// None when no headset is found, 3dof then keeps the runtime orientation
+fn spawn_imu_reader(tracker: &mut TrackingCollector) -> Option<mpsc::Receiver<Vec<ImuSample>>> {
+    let mut api = match KernelApi::open_first() {
+        Ok(api) => api,
+        Err(err) => {
+            println!("headset imu not available, using runtime orientation: {}", err);
+            return None;
+        }
+    };
+    let mut stream = ImuStream::new();
+    match DeviceConfig::read(&mut api) {
+        Ok(config) => {
+            stream.set_calibration(config.imu_calibration());
+            tracker.set_imu_axes(config.head_from_imu());
+        }
+        Err(err) => println!("device config not available: {}", err),
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let report = match api.read_sensors() {
+            Ok(report) => report,
+            Err(err) => {
+                println!("headset imu read failed: {}", err);
+                return;
+            }
+        };
+        let samples = stream.push_report(&report);
+        // the receiver is gone once the session loop has finished
+        if !samples.is_empty() && sender.send(samples).is_err() {
+            return;
+        }
+    });
+    Some(receiver)
+}
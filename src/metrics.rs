@@ -1,4 +1,5 @@
 use serde::{Serialize, Deserialize};
+use crate::controller_input::{ButtonEvent, ControllerInputState, Hand};
 
 // frame bakoitzeko sentsoreen datuak
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -6,10 +7,113 @@ pub struct SensorFrame {
     pub timestamp_ms: u64,
     pub head_position: [f32; 3],
     pub head_orientation: [f32; 4],  // quaternion
-    pub left_controller_pos: Option<[f32; 3]>,
-    pub right_controller_pos: Option<[f32; 3]>,
+    // xrLocateSpace egoera, jarraipenik gabeko frame-ak datuetan ikusteko
+    #[serde(default)]
+    pub head_tracking: TrackingFlags,
+    // kontroladoreen grip eta aim poseak, none kontroladorerik ez badago
+    #[serde(default)]
+    pub left_controller: Option<ControllerPoses>,
+    #[serde(default)]
+    pub right_controller: Option<ControllerPoses>,
     pub angular_velocity: [f32; 3],
     pub linear_velocity: [f32; 3],
+    // abiadurak nondik datozen (runtime-a, diferentzia finituak, imu-a)
+    #[serde(default)]
+    pub linear_velocity_source: VelocitySource,
+    #[serde(default)]
+    pub angular_velocity_source: VelocitySource,
+    // kontroladoreen botoiak (0x24 report-a), none jaso ez bada
+    #[serde(default)]
+    pub left_controller_input: Option<ControllerInputState>,
+    #[serde(default)]
+    pub right_controller_input: Option<ControllerInputState>,
+    // aurreko frame-tik izandako sakatze/askatzeak
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_events: Vec<ButtonEvent>,
+}
+
+// XrSpaceLocationFlags: valid = balioa erabilgarria, tracked = uneko neurketa (ez asmatua)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TrackingFlags {
+    pub position_valid: bool,
+    pub orientation_valid: bool,
+    pub position_tracked: bool,
+    pub orientation_tracked: bool,
+}
+
+impl TrackingFlags {
+    pub fn is_tracked(&self) -> bool {
+        self.position_tracked && self.orientation_tracked
+    }
+}
+
+// kontroladore espazio baten pose osoa, buruaren eremu berdinekin
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ControllerPose {
+    pub position: [f32; 3],
+    pub orientation: [f32; 4],  // quaternion
+    pub tracking: TrackingFlags,
+    pub linear_velocity: [f32; 3],
+    pub angular_velocity: [f32; 3],
+    pub linear_velocity_source: VelocitySource,
+    pub angular_velocity_source: VelocitySource,
+}
+
+// grip: eskuaren pose-a, aim: seinalatzeko izpia
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ControllerPoses {
+    pub grip: ControllerPose,
+    pub aim: ControllerPose,
+}
+
+// abiaduraren iturria frame bakoitzean
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocitySource {
+    // xrLocateSpace + XrSpaceVelocity, runtime-ak balioztatua
+    Runtime,
+    // posearen historiatik kalkulatua eta leundua
+    FiniteDifference,
+    // buruko imu iragazkitik (--3dof)
+    Imu,
+    #[default]
+    Unavailable,
+}
+
+impl VelocitySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VelocitySource::Runtime => "runtime",
+            VelocitySource::FiniteDifference => "finite_difference",
+            VelocitySource::Imu => "imu",
+            VelocitySource::Unavailable => "unavailable",
+        }
+    }
+}
+
+// bidalitako bibrazio bakoitza; denborak saio hasieratik, frame-en timestamp_ms erloju berean
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HapticPulse {
+    pub hand: Hand,
+    // patroiaren izena, none zuzeneko vibrate deia bada
+    pub pattern: Option<String>,
+    // patroiak eskatutako unea
+    pub scheduled_ms: Option<u64>,
+    // xrApplyHapticFeedback deitu zen unea eta deiak iraun zuena
+    pub issued_ms: f64,
+    pub call_us: u64,
+    // frame-aren predicted display time (xr denbora, ns)
+    pub display_time_ns: i64,
+    pub duration_ms: f32,
+    pub frequency_hz: f32,
+    pub amplitude: f32,
+}
+
+impl HapticPulse {
+    // eskatutako unetik benetan bidali arte
+    pub fn latency_ms(&self) -> Option<f64> {
+        self.scheduled_ms.map(|scheduled| self.issued_ms - scheduled as f64)
+    }
 }
 
 // saio osoaren metrikak
@@ -23,6 +127,8 @@ pub struct SessionMetrics {
     pub avg_fps: f32,
     pub position_drift_cm: f32,
     pub frames: Vec<SensorFrame>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub haptic_pulses: Vec<HapticPulse>,
 }
 
 impl SessionMetrics {
@@ -36,6 +142,7 @@ impl SessionMetrics {
             avg_fps: 0.0,
             position_drift_cm: 0.0,
             frames: Vec::new(),
+            haptic_pulses: Vec::new(),
         }
     }
 
@@ -44,6 +151,10 @@ impl SessionMetrics {
         self.total_frames = self.frames.len();
     }
 
+    pub fn add_haptic_pulse(&mut self, pulse: HapticPulse) {
+        self.haptic_pulses.push(pulse);
+    }
+
     pub fn finalize(&mut self, duration_secs: f32, drift_cm: f32) {
         self.duration_secs = duration_secs;
         self.position_drift_cm = drift_cm;
@@ -70,6 +181,17 @@ impl SessionMetrics {
             
             println!("guztizko mugimendua: {:.2} m", total_movement);
         }
+
+        if !self.haptic_pulses.is_empty() {
+            let latencies: Vec<f64> = self.haptic_pulses.iter().filter_map(|p| p.latency_ms()).collect();
+            print!("bibrazioak: {}", self.haptic_pulses.len());
+            if !latencies.is_empty() {
+                let mean = latencies.iter().sum::<f64>() / latencies.len() as f64;
+                let max = latencies.iter().cloned().fold(0.0, f64::max);
+                print!(" (atzerapena: batez beste {:.2} ms, gehienez {:.2} ms)", mean, max);
+            }
+            println!();
+        }
     }
 
     pub fn calculate_statistics(&self) -> Statistics {
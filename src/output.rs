@@ -1,4 +1,5 @@
-use crate::metrics::SessionMetrics;
+use crate::metrics::{ControllerPose, ControllerPoses, SessionMetrics};
+use crate::controller_input::ControllerInputState;
 use std::fs::File;
 use std::io::Write;
 
@@ -30,13 +31,21 @@ impl DataExporter {
         let mut file = File::create(&filename)?;
         
         // goiburua
-        writeln!(file, "timestamp_ms,pos_x,pos_y,pos_z,ori_x,ori_y,ori_z,ori_w,vel_x,vel_y,vel_z,angvel_x,angvel_y,angvel_z")?;
+        writeln!(
+            file,
+            "timestamp_ms,pos_x,pos_y,pos_z,ori_x,ori_y,ori_z,ori_w,pos_valid,ori_valid,pos_tracked,ori_tracked,vel_x,vel_y,vel_z,angvel_x,angvel_y,angvel_z,vel_source,angvel_source,\
+             l_buttons,l_trigger,l_pad_x,l_pad_y,r_buttons,r_trigger,r_pad_x,r_pad_y,{},{},{},{}",
+            pose_header("l_grip"),
+            pose_header("l_aim"),
+            pose_header("r_grip"),
+            pose_header("r_aim"),
+        )?;
 
         // frame bakoitzeko lerroa
         for frame in &metrics.frames {
             writeln!(
                 file,
-                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
                 frame.timestamp_ms,
                 frame.head_position[0],
                 frame.head_position[1],
@@ -45,12 +54,22 @@ impl DataExporter {
                 frame.head_orientation[1],
                 frame.head_orientation[2],
                 frame.head_orientation[3],
+                frame.head_tracking.position_valid as u8,
+                frame.head_tracking.orientation_valid as u8,
+                frame.head_tracking.position_tracked as u8,
+                frame.head_tracking.orientation_tracked as u8,
                 frame.linear_velocity[0],
                 frame.linear_velocity[1],
                 frame.linear_velocity[2],
                 frame.angular_velocity[0],
                 frame.angular_velocity[1],
                 frame.angular_velocity[2],
+                frame.linear_velocity_source.as_str(),
+                frame.angular_velocity_source.as_str(),
+                input_columns(&frame.left_controller_input),
+                input_columns(&frame.right_controller_input),
+                controller_columns(&frame.left_controller),
+                controller_columns(&frame.right_controller),
             )?;
         }
 
@@ -116,11 +135,18 @@ axes[1, 0].grid(True)
 
 # 3d ibilbidea
 ax = fig.add_subplot(2, 2, 4, projection='3d')
-ax.plot(df['pos_x'], df['pos_y'], df['pos_z'])
+ax.plot(df['pos_x'], df['pos_y'], df['pos_z'], label='burua')
+# kontroladoreak, jarraitutako frame-ak bakarrik
+for hand, name in (('l', 'ezkerra'), ('r', 'eskuina')):
+    tracked = df[hand + '_grip_pos_valid'] == 1
+    if tracked.any():
+        ax.plot(df.loc[tracked, hand + '_grip_pos_x'], df.loc[tracked, hand + '_grip_pos_y'],
+                df.loc[tracked, hand + '_grip_pos_z'], label=name)
 ax.set_xlabel('x (m)')
 ax.set_ylabel('y (m)')
 ax.set_zlabel('z (m)')
-ax.set_title('buruaren ibilbidea 3d-n')
+ax.set_title('ibilbidea 3d-n')
+ax.legend()
 
 plt.tight_layout()
 plt.savefig('vr_analysis.png', dpi=300)
@@ -145,4 +171,67 @@ plt.show()
 
         Ok(())
     }
+}
+
+// botoien zutabeak (bitmap, trigger, trackpad x/y), hutsik report-ik ez badago
+fn input_columns(input: &Option<ControllerInputState>) -> String {
+    match input {
+        Some(state) => format!(
+            "{},{},{},{}",
+            state.bitmap(),
+            state.trigger,
+            state.trackpad[0],
+            state.trackpad[1]
+        ),
+        None => ",,,".to_string(),
+    }
+}
+
+// kontroladore pose baten zutabe izenak, buruaren zutabeen ordena berean
+fn pose_header(prefix: &str) -> String {
+    [
+        "pos_x", "pos_y", "pos_z", "ori_x", "ori_y", "ori_z", "ori_w",
+        "pos_valid", "ori_valid", "pos_tracked", "ori_tracked",
+        "vel_x", "vel_y", "vel_z", "angvel_x", "angvel_y", "angvel_z",
+        "vel_source", "angvel_source",
+    ]
+    .iter()
+    .map(|column| format!("{}_{}", prefix, column))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+// grip eta aim zutabeak, hutsik kontroladorerik ez badago
+fn controller_columns(poses: &Option<ControllerPoses>) -> String {
+    match poses {
+        Some(poses) => format!("{},{}", pose_columns(&poses.grip), pose_columns(&poses.aim)),
+        None => vec![""; 2 * POSE_COLUMNS].join(","),
+    }
+}
+
+const POSE_COLUMNS: usize = 19;
+
+fn pose_columns(pose: &ControllerPose) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        pose.position[0],
+        pose.position[1],
+        pose.position[2],
+        pose.orientation[0],
+        pose.orientation[1],
+        pose.orientation[2],
+        pose.orientation[3],
+        pose.tracking.position_valid as u8,
+        pose.tracking.orientation_valid as u8,
+        pose.tracking.position_tracked as u8,
+        pose.tracking.orientation_tracked as u8,
+        pose.linear_velocity[0],
+        pose.linear_velocity[1],
+        pose.linear_velocity[2],
+        pose.angular_velocity[0],
+        pose.angular_velocity[1],
+        pose.angular_velocity[2],
+        pose.linear_velocity_source.as_str(),
+        pose.angular_velocity_source.as_str(),
+    )
 }
\ No newline at end of file
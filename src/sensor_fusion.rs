@@ -0,0 +1,723 @@
+// error-state ekf: 1 khz imu prediction, lighthouse pose corrections (~120 hz)
+// nominal state: position, velocity, orientation (world_from_imu), accel and gyro bias
+// error state (15): dp, dv, dtheta (local, q_true = q * exp(dtheta)), dba, dbg
+//
+// both measurement kinds carry device clock timestamps (µs, same 48 mhz clock for
+// imu and light reports). the filter keeps ~200 ms of imu history so a pose that
+// arrives after newer imu samples (the solver runs behind the imu) is applied at
+// its own time and the later samples (and poses) are re-propagated on top of it
+//
+// world frame is the room setup one (+y up), gravity -9.81 m/s² along y
+
+use std::collections::VecDeque;
+use nalgebra::{Isometry3, Matrix3, SMatrix, SVector, Translation3, UnitQuaternion, Vector3};
+use crate::hid_reports::ImuSample;
+use crate::lighthouse_pose::PoseSolution;
+
+type Covariance = SMatrix<f64, 15, 15>;
+type ErrorState = SVector<f64, 15>;
+
+const STANDARD_GRAVITY: f64 = 9.80665;
+const HISTORY_US: u64 = 200_000;
+const MAX_DT_S: f64 = 0.05;
+
+// state block offsets
+const POS: usize = 0;
+const VEL: usize = 3;
+const ROT: usize = 6;
+const ACC_BIAS: usize = 9;
+const GYRO_BIAS: usize = 12;
+
+// 6 dof chi² at 99.9 %, larger innovations are treated as bad solves
+const GATE_CHI2: f64 = 22.46;
+// this many gated poses in a row (~250 ms) means the filter diverged, restart from the pose
+const MAX_CONSECUTIVE_REJECTIONS: u32 = 30;
+// degraded above this position std deviation or without a correction for this long
+const DEGRADED_POSITION_STD_M: f64 = 0.05;
+const DEGRADED_AFTER_US: u64 = 500_000;
+
+// imu noise densities and bias random walks (continuous time)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FusionNoise {
+    pub accel: f64,      // m/s² / sqrt(hz)
+    pub gyro: f64,       // rad/s / sqrt(hz)
+    pub accel_bias: f64, // m/s³ / sqrt(hz)
+    pub gyro_bias: f64,  // rad/s² / sqrt(hz)
+}
+
+impl FusionNoise {
+    // bmi0xx class consumer imu
+    pub fn consumer_imu() -> Self {
+        Self {
+            accel: 2.0e-2,
+            gyro: 1.0e-3,
+            accel_bias: 1.0e-3,
+            gyro_bias: 1.0e-5,
+        }
+    }
+}
+
+// one lighthouse pose, world_from_device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoseMeasurement {
+    pub timestamp_us: u64,
+    pub pose: Isometry3<f64>,
+    pub position_std: f64, // m
+    pub rotation_std: f64, // rad
+}
+
+impl PoseMeasurement {
+    // noise from the solver's reprojection error: stations are a few metres away,
+    // so 1 mrad of angle error is a few mm of position; the constellation is ~10 cm
+    // across which turns the same error into a larger rotation error
+    pub fn from_solution(solution: &PoseSolution, timestamp_us: u64) -> Self {
+        let angle_error = (solution.reprojection_error as f64).max(5.0e-4);
+        let position_std = (angle_error * 3.0).max(0.002);
+        Self {
+            timestamp_us,
+            pose: solution.pose.cast::<f64>(),
+            position_std,
+            rotation_std: (position_std / 0.1).max(0.002),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FusedPose {
+    pub timestamp_us: u64,
+    pub position: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub orientation: UnitQuaternion<f64>,
+    // bias corrected, world frame
+    pub angular_velocity: Vector3<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NominalState {
+    position: Vector3<f64>,
+    velocity: Vector3<f64>,
+    orientation: UnitQuaternion<f64>,
+    accel_bias: Vector3<f64>,
+    gyro_bias: Vector3<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ImuInput {
+    timestamp_us: u64,
+    accel: Vector3<f64>, // m/s², specific force
+    gyro: Vector3<f64>,  // rad/s
+}
+
+impl ImuInput {
+    fn from_sample(sample: &ImuSample) -> Self {
+        Self {
+            timestamp_us: sample.timestamp_us(),
+            accel: Vector3::from(sample.accel).cast::<f64>() * STANDARD_GRAVITY,
+            gyro: Vector3::from(sample.gyro).cast::<f64>().map(f64::to_radians),
+        }
+    }
+}
+
+// filter state after an imu sample and the poses applied at it, for rewinding
+// (no state before the first pose)
+#[derive(Debug, Clone)]
+struct Snapshot {
+    input: ImuInput,
+    poses: Vec<PoseMeasurement>,
+    state: Option<NominalState>,
+    covariance: Covariance,
+}
+
+pub struct SensorFusion {
+    noise: FusionNoise,
+    // device_from_imu (device config "imu" frame), identity when unknown
+    imu_extrinsics: Isometry3<f64>,
+    state: Option<NominalState>,
+    covariance: Covariance,
+    history: VecDeque<Snapshot>,
+    last_imu: Option<ImuInput>,
+    last_correction_us: Option<u64>,
+    corrections: u64,
+    rejected: u64,
+    consecutive_rejections: u32,
+    restarts: u64,
+    late: u64,
+    replayed: u64,
+}
+
+impl SensorFusion {
+    pub fn new(noise: FusionNoise) -> Self {
+        Self {
+            noise,
+            imu_extrinsics: Isometry3::identity(),
+            state: None,
+            covariance: Covariance::zeros(),
+            history: VecDeque::new(),
+            last_imu: None,
+            last_correction_us: None,
+            corrections: 0,
+            rejected: 0,
+            consecutive_rejections: 0,
+            restarts: 0,
+            late: 0,
+            replayed: 0,
+        }
+    }
+
+    pub fn set_imu_extrinsics(&mut self, device_from_imu: Isometry3<f64>) {
+        self.imu_extrinsics = device_from_imu;
+    }
+
+    pub fn reset(&mut self) {
+        let (noise, extrinsics) = (self.noise, self.imu_extrinsics);
+        *self = Self::new(noise);
+        self.imu_extrinsics = extrinsics;
+    }
+
+    // device clock restarted (reconnect): keep the state, drop the timeline
+    pub fn reset_clock(&mut self) {
+        self.history.clear();
+        self.last_imu = None;
+        self.last_correction_us = None;
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.state.is_some()
+    }
+
+    // prediction step, samples must come in device clock order
+    pub fn push_imu(&mut self, sample: &ImuSample) {
+        let input = ImuInput::from_sample(sample);
+        if self.last_imu.is_some_and(|last| input.timestamp_us <= last.timestamp_us) {
+            return;
+        }
+        self.step(input);
+        while self
+            .history
+            .front()
+            .is_some_and(|s| input.timestamp_us - s.input.timestamp_us > HISTORY_US)
+        {
+            self.history.pop_front();
+        }
+    }
+
+    fn step(&mut self, input: ImuInput) {
+        self.propagate(input);
+        self.last_imu = Some(input);
+        self.history.push_back(Snapshot {
+            input,
+            poses: Vec::new(),
+            state: self.state,
+            covariance: self.covariance,
+        });
+    }
+
+    // the newest snapshot holds the corrected state, so a later rewind to it keeps the pose
+    fn save_correction(&mut self, measurement: &PoseMeasurement) {
+        let (state, covariance) = (self.state, self.covariance);
+        if let Some(snapshot) = self.history.back_mut() {
+            snapshot.poses.push(*measurement);
+            snapshot.state = state;
+            snapshot.covariance = covariance;
+        }
+    }
+
+    pub fn push_imu_samples(&mut self, samples: &[ImuSample]) {
+        for sample in samples {
+            self.push_imu(sample);
+        }
+    }
+
+    // correction step, returns false when the measurement was gated out or too old
+    // the first pose initialises the filter
+    pub fn push_pose(&mut self, measurement: &PoseMeasurement) -> bool {
+        // rewind to the last imu sample at or before the measurement
+        let newest_us = self.last_imu.map_or(0, |i| i.timestamp_us);
+        let mut replay = Vec::new();
+        if measurement.timestamp_us < newest_us {
+            let Some(index) = self
+                .history
+                .iter()
+                .rposition(|s| s.input.timestamp_us <= measurement.timestamp_us)
+            else {
+                self.late += 1;
+                return false;
+            };
+            replay = self.history.drain(index + 1..).collect::<Vec<_>>();
+            let snapshot = &self.history[index];
+            self.state = snapshot.state;
+            self.covariance = snapshot.covariance;
+            self.last_imu = Some(snapshot.input);
+            self.replayed += replay.len() as u64;
+        }
+
+        let accepted = self.update(measurement);
+
+        // replayed poses were already counted when they arrived
+        let counters = (self.corrections, self.rejected, self.restarts);
+        for snapshot in replay {
+            self.step(snapshot.input);
+            for pose in &snapshot.poses {
+                self.update(pose);
+            }
+        }
+        (self.corrections, self.rejected, self.restarts) = counters;
+        accepted
+    }
+
+    fn update(&mut self, measurement: &PoseMeasurement) -> bool {
+        let measured = measurement.pose * self.imu_extrinsics;
+        let accepted = match self.state {
+            None => {
+                self.initialize(&measured, measurement);
+                true
+            }
+            Some(_) => self.correct(&measured, measurement),
+        };
+        if accepted {
+            self.save_correction(measurement);
+        }
+        accepted
+    }
+
+    fn initialize(&mut self, measured: &Isometry3<f64>, measurement: &PoseMeasurement) {
+        // a restart after divergence keeps the learned biases
+        let (accel_bias, gyro_bias) = self
+            .state
+            .map_or((Vector3::zeros(), Vector3::zeros()), |s| (s.accel_bias, s.gyro_bias));
+        self.state = Some(NominalState {
+            position: measured.translation.vector,
+            velocity: Vector3::zeros(),
+            orientation: measured.rotation,
+            accel_bias,
+            gyro_bias,
+        });
+
+        let mut covariance = Covariance::zeros();
+        let block = |c: &mut Covariance, at: usize, variance: f64| {
+            c.fixed_view_mut::<3, 3>(at, at).copy_from(&(Matrix3::identity() * variance));
+        };
+        block(&mut covariance, POS, measurement.position_std.powi(2));
+        block(&mut covariance, VEL, 0.5f64.powi(2));
+        block(&mut covariance, ROT, measurement.rotation_std.powi(2));
+        block(&mut covariance, ACC_BIAS, 0.2f64.powi(2));
+        block(&mut covariance, GYRO_BIAS, 0.02f64.powi(2));
+        self.covariance = covariance;
+
+        self.last_correction_us = Some(measurement.timestamp_us);
+        self.consecutive_rejections = 0;
+        self.corrections += 1;
+    }
+
+    fn propagate(&mut self, input: ImuInput) {
+        let Some(last) = self.last_imu else {
+            return;
+        };
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        let dt = input.timestamp_us.saturating_sub(last.timestamp_us) as f64 * 1e-6;
+        if dt <= 0.0 || dt > MAX_DT_S {
+            return;
+        }
+
+        let gravity = Vector3::new(0.0, -STANDARD_GRAVITY, 0.0);
+        let rotation = state.orientation.to_rotation_matrix().into_inner();
+        let accel = input.accel - state.accel_bias;
+        let gyro = input.gyro - state.gyro_bias;
+        let world_accel = rotation * accel + gravity;
+
+        state.position += state.velocity * dt + world_accel * (0.5 * dt * dt);
+        state.velocity += world_accel * dt;
+        state.orientation *= UnitQuaternion::from_scaled_axis(gyro * dt);
+        state.orientation.renormalize();
+
+        // error state transition, first order
+        let mut phi = Covariance::identity();
+        phi.fixed_view_mut::<3, 3>(POS, VEL).copy_from(&(Matrix3::identity() * dt));
+        phi.fixed_view_mut::<3, 3>(VEL, ROT).copy_from(&(-rotation * skew(&accel) * dt));
+        phi.fixed_view_mut::<3, 3>(VEL, ACC_BIAS).copy_from(&(-rotation * dt));
+        phi.fixed_view_mut::<3, 3>(ROT, ROT)
+            .copy_from(UnitQuaternion::from_scaled_axis(-gyro * dt).to_rotation_matrix().matrix());
+        phi.fixed_view_mut::<3, 3>(ROT, GYRO_BIAS).copy_from(&(-Matrix3::identity() * dt));
+
+        let mut q = Covariance::zeros();
+        let noise = self.noise;
+        for (at, density) in [
+            (VEL, noise.accel),
+            (ROT, noise.gyro),
+            (ACC_BIAS, noise.accel_bias),
+            (GYRO_BIAS, noise.gyro_bias),
+        ] {
+            q.fixed_view_mut::<3, 3>(at, at)
+                .copy_from(&(Matrix3::identity() * density * density * dt));
+        }
+
+        self.covariance = phi * self.covariance * phi.transpose() + q;
+    }
+
+    fn correct(&mut self, measured: &Isometry3<f64>, measurement: &PoseMeasurement) -> bool {
+        let Some(state) = self.state.as_mut() else {
+            return false;
+        };
+
+        let mut residual = SVector::<f64, 6>::zeros();
+        residual
+            .fixed_rows_mut::<3>(0)
+            .copy_from(&(measured.translation.vector - state.position));
+        residual
+            .fixed_rows_mut::<3>(3)
+            .copy_from(&(state.orientation.inverse() * measured.rotation).scaled_axis());
+
+        let mut h = SMatrix::<f64, 6, 15>::zeros();
+        h.fixed_view_mut::<3, 3>(0, POS).copy_from(&Matrix3::identity());
+        h.fixed_view_mut::<3, 3>(3, ROT).copy_from(&Matrix3::identity());
+
+        let mut r = SMatrix::<f64, 6, 6>::zeros();
+        r.fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&(Matrix3::identity() * measurement.position_std.powi(2)));
+        r.fixed_view_mut::<3, 3>(3, 3)
+            .copy_from(&(Matrix3::identity() * measurement.rotation_std.powi(2)));
+
+        let s = h * self.covariance * h.transpose() + r;
+        let s_inv = s
+            .try_inverse()
+            .filter(|s_inv| (residual.transpose() * s_inv * residual)[0] <= GATE_CHI2);
+        let Some(s_inv) = s_inv else {
+            self.rejected += 1;
+            self.consecutive_rejections += 1;
+            if self.consecutive_rejections >= MAX_CONSECUTIVE_REJECTIONS {
+                self.restarts += 1;
+                self.initialize(measured, measurement);
+                return true;
+            }
+            return false;
+        };
+
+        let k = self.covariance * h.transpose() * s_inv;
+        let dx: ErrorState = k * residual;
+
+        // joseph form keeps the covariance symmetric positive
+        let i_kh = Covariance::identity() - k * h;
+        self.covariance = i_kh * self.covariance * i_kh.transpose() + k * r * k.transpose();
+
+        state.position += dx.fixed_rows::<3>(POS);
+        state.velocity += dx.fixed_rows::<3>(VEL);
+        state.orientation *= UnitQuaternion::from_scaled_axis(dx.fixed_rows::<3>(ROT).into_owned());
+        state.accel_bias += dx.fixed_rows::<3>(ACC_BIAS);
+        state.gyro_bias += dx.fixed_rows::<3>(GYRO_BIAS);
+
+        self.last_correction_us = Some(
+            self.last_correction_us
+                .map_or(measurement.timestamp_us, |t| t.max(measurement.timestamp_us)),
+        );
+        self.consecutive_rejections = 0;
+        self.corrections += 1;
+        true
+    }
+
+    // world_from_device at the newest imu sample
+    pub fn pose(&self) -> Option<FusedPose> {
+        let state = self.state?;
+        let world_from_imu = Isometry3::from_parts(Translation3::from(state.position), state.orientation);
+        let world_from_device = world_from_imu * self.imu_extrinsics.inverse();
+        let gyro = self.last_imu.map_or(Vector3::zeros(), |i| i.gyro - state.gyro_bias);
+
+        Some(FusedPose {
+            timestamp_us: self.last_imu.map_or(0, |i| i.timestamp_us),
+            position: world_from_device.translation.vector,
+            velocity: state.velocity,
+            orientation: world_from_device.rotation,
+            angular_velocity: state.orientation * gyro,
+        })
+    }
+
+    // 15x15 error state covariance: dp, dv, dtheta, dba, dbg
+    pub fn covariance(&self) -> &SMatrix<f64, 15, 15> {
+        &self.covariance
+    }
+
+    // 1 sigma per axis
+    pub fn position_std(&self) -> Vector3<f64> {
+        self.covariance.fixed_view::<3, 3>(POS, POS).diagonal().map(f64::sqrt)
+    }
+
+    pub fn orientation_std(&self) -> Vector3<f64> {
+        self.covariance.fixed_view::<3, 3>(ROT, ROT).diagonal().map(f64::sqrt)
+    }
+
+    pub fn accel_bias(&self) -> Option<Vector3<f64>> {
+        self.state.map(|s| s.accel_bias)
+    }
+
+    pub fn gyro_bias(&self) -> Option<Vector3<f64>> {
+        self.state.map(|s| s.gyro_bias)
+    }
+
+    // imu only since this long (occlusion), None before the first pose
+    pub fn time_since_correction_us(&self) -> Option<u64> {
+        let last = self.last_correction_us?;
+        Some(self.last_imu.map_or(0, |i| i.timestamp_us.saturating_sub(last)))
+    }
+
+    // not initialised, uncertain or dead reckoning for too long
+    pub fn is_degraded(&self) -> bool {
+        !self.is_initialized()
+            || self.position_std().max() > DEGRADED_POSITION_STD_M
+            || self.time_since_correction_us().is_some_and(|t| t > DEGRADED_AFTER_US)
+    }
+
+    pub fn corrections(&self) -> u64 {
+        self.corrections
+    }
+
+    // gated out (innovation too large for the current covariance)
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    // re-initialisations after a run of gated poses
+    pub fn restarts(&self) -> u64 {
+        self.restarts
+    }
+
+    // older than the imu history
+    pub fn late_measurements(&self) -> u64 {
+        self.late
+    }
+
+    // imu samples propagated again after an out of order pose
+    pub fn replayed_samples(&self) -> u64 {
+        self.replayed
+    }
+}
+
+fn skew(v: &Vector3<f64>) -> Matrix3<f64> {
+    Matrix3::new(0.0, -v.z, v.y, v.z, 0.0, -v.x, -v.y, v.x, 0.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_config::DeviceConfig;
+    use crate::hid_reports::TICKS_PER_US;
+
+    const HEAD: Vector3<f64> = Vector3::new(0.2, 1.7, -0.4);
+
+    // still or spinning about +y, the specific force stays on imu +y
+    fn imu(timestamp_us: u64, accel: Vector3<f64>, yaw_rate_dps: f32) -> ImuSample {
+        ImuSample {
+            sequence: (timestamp_us / 1000) as u8,
+            timestamp_ticks: timestamp_us * TICKS_PER_US,
+            accel: accel.cast::<f32>().into(),
+            gyro: [0.0, yaw_rate_dps, 0.0],
+            dropped_before: 0,
+        }
+    }
+
+    fn pose(timestamp_us: u64, pose: Isometry3<f64>) -> PoseMeasurement {
+        PoseMeasurement {
+            timestamp_us,
+            pose,
+            position_std: 0.002,
+            rotation_std: 0.01,
+        }
+    }
+
+    // device turning about +y at 20 deg/s
+    const YAW_RATE_DPS: f32 = 20.0;
+
+    // solves scatter by a few mm around the truth
+    fn turning(timestamp_us: u64) -> Isometry3<f64> {
+        let yaw = (YAW_RATE_DPS as f64).to_radians() * timestamp_us as f64 * 1e-6;
+        let phase = timestamp_us as f64 * 1e-4;
+        let noise = Vector3::new(phase.sin(), phase.cos(), (2.0 * phase).sin()) * 0.003;
+        Isometry3::from_parts(
+            Translation3::from(HEAD + noise),
+            UnitQuaternion::from_scaled_axis(Vector3::y() * yaw + noise),
+        )
+    }
+
+    fn push_turning_imu(fusion: &mut SensorFusion, from_us: u64, to_us: u64) {
+        for t in (from_us..=to_us).step_by(1000) {
+            fusion.push_imu(&imu(t, Vector3::y(), YAW_RATE_DPS));
+        }
+    }
+
+    fn assert_same(a: &SensorFusion, b: &SensorFusion) {
+        assert!((a.covariance() - b.covariance()).norm() < 1e-12);
+        let (a, b) = (a.pose().unwrap(), b.pose().unwrap());
+        assert_eq!(a.timestamp_us, b.timestamp_us);
+        assert!((a.position - b.position).norm() < 1e-9, "{} {}", a.position, b.position);
+        assert!(a.orientation.angle_to(&b.orientation) < 1e-9);
+        assert!((a.velocity - b.velocity).norm() < 1e-9);
+    }
+
+    #[test]
+    fn static_imu_and_poses_converge() {
+        let truth = Isometry3::from_parts(Translation3::from(HEAD), UnitQuaternion::identity());
+        let mut fusion = SensorFusion::new(FusionNoise::consumer_imu());
+        assert!(fusion.is_degraded());
+
+        // first solve 3 cm off and uncertain, then good poses at 125 hz
+        let mut first = pose(0, Translation3::new(0.03, 0.0, 0.0) * truth);
+        first.position_std = 0.05;
+        for t in (0..=2_000_000).step_by(1000) {
+            fusion.push_imu(&imu(t, Vector3::y(), 0.0));
+            if t == 0 {
+                assert!(fusion.push_pose(&first));
+            } else if t % 8000 == 0 {
+                assert!(fusion.push_pose(&pose(t, truth)));
+            }
+        }
+
+        let fused = fusion.pose().unwrap();
+        assert!((fused.position - HEAD).norm() < 0.002, "{}", fused.position);
+        assert!(fused.orientation.angle() < 0.002);
+        assert!(fused.velocity.norm() < 0.01);
+        assert!(fusion.position_std().max() < 0.005);
+        assert!(!fusion.is_degraded());
+        assert_eq!(fusion.rejected(), 0);
+        assert_eq!(fusion.corrections(), 251);
+    }
+
+    #[test]
+    fn late_pose_matches_in_order_pose() {
+        let mut in_order = SensorFusion::new(FusionNoise::consumer_imu());
+        push_turning_imu(&mut in_order, 0, 0);
+        in_order.push_pose(&pose(0, turning(0)));
+        push_turning_imu(&mut in_order, 1000, 100_000);
+        in_order.push_pose(&pose(100_000, turning(100_000)));
+        push_turning_imu(&mut in_order, 101_000, 150_000);
+
+        // the solver delivers the 100 ms pose 50 ms late
+        let mut late = SensorFusion::new(FusionNoise::consumer_imu());
+        push_turning_imu(&mut late, 0, 0);
+        late.push_pose(&pose(0, turning(0)));
+        push_turning_imu(&mut late, 1000, 150_000);
+        assert!(late.push_pose(&pose(100_000, turning(100_000))));
+
+        assert_eq!(late.replayed_samples(), 50);
+        assert_same(&in_order, &late);
+    }
+
+    #[test]
+    fn swapped_poses_match_in_order_poses() {
+        let mut in_order = SensorFusion::new(FusionNoise::consumer_imu());
+        push_turning_imu(&mut in_order, 0, 0);
+        in_order.push_pose(&pose(0, turning(0)));
+        push_turning_imu(&mut in_order, 1000, 60_000);
+        in_order.push_pose(&pose(60_000, turning(60_000)));
+        push_turning_imu(&mut in_order, 61_000, 70_000);
+        in_order.push_pose(&pose(70_000, turning(70_000)));
+        push_turning_imu(&mut in_order, 71_000, 120_000);
+
+        // the newer pose arrives first, the older one must not throw it away
+        let mut swapped = SensorFusion::new(FusionNoise::consumer_imu());
+        push_turning_imu(&mut swapped, 0, 0);
+        swapped.push_pose(&pose(0, turning(0)));
+        push_turning_imu(&mut swapped, 1000, 120_000);
+        assert!(swapped.push_pose(&pose(70_000, turning(70_000))));
+        assert!(swapped.push_pose(&pose(60_000, turning(60_000))));
+
+        assert_eq!(swapped.corrections(), in_order.corrections());
+        assert_same(&in_order, &swapped);
+
+    }
+
+    #[test]
+    fn late_pose_at_a_corrected_sample_keeps_both() {
+        let late_poses = |count: usize| {
+            let mut fusion = SensorFusion::new(FusionNoise::consumer_imu());
+            push_turning_imu(&mut fusion, 0, 0);
+            fusion.push_pose(&pose(0, turning(0)));
+            push_turning_imu(&mut fusion, 1000, 120_000);
+            for _ in 0..count {
+                assert!(fusion.push_pose(&pose(70_000, turning(70_000))));
+            }
+            fusion
+        };
+
+        let (once, twice) = (late_poses(1), late_poses(2));
+        assert_eq!(twice.corrections(), 3);
+        assert!(twice.position_std().max() < once.position_std().max() * 0.9);
+    }
+
+    #[test]
+    fn degraded_during_occlusion() {
+        let truth = Isometry3::from_parts(Translation3::from(HEAD), UnitQuaternion::identity());
+        let mut fusion = SensorFusion::new(FusionNoise::consumer_imu());
+        for t in (0..=1_000_000).step_by(1000) {
+            fusion.push_imu(&imu(t, Vector3::y(), 0.0));
+            if t % 8000 == 0 {
+                fusion.push_pose(&pose(t, truth));
+            }
+        }
+        assert!(!fusion.is_degraded());
+        let tracked_std = fusion.position_std().max();
+
+        // no light for a second, imu only
+        let mut degraded_at = None;
+        for t in (1_001_000..=2_000_000).step_by(1000) {
+            fusion.push_imu(&imu(t, Vector3::y(), 0.0));
+            if degraded_at.is_none() && fusion.is_degraded() {
+                degraded_at = Some(t);
+            }
+        }
+        let degraded_at = degraded_at.expect("still tracking after 1 s without poses");
+        assert!(degraded_at > 1_400_000, "{}", degraded_at);
+        assert!(fusion.time_since_correction_us().unwrap() > DEGRADED_AFTER_US);
+        assert!(fusion.position_std().max() > tracked_std);
+
+        fusion.push_imu(&imu(2_001_000, Vector3::y(), 0.0));
+        assert!(fusion.push_pose(&pose(2_001_000, truth)));
+        assert!(!fusion.is_degraded());
+    }
+
+    #[test]
+    fn imu_extrinsics_from_device_config() {
+        // imu x along device -z, imu z along device +x, 5 cm behind the origin
+        let config = DeviceConfig::parse(
+            r#"{"imu": {"plus_x": [0, 0, -1], "plus_z": [1, 0, 0], "position": [0, 0, 0.05]}}"#,
+        )
+        .unwrap();
+        let device_from_imu = config.imu_extrinsics().unwrap().cast::<f64>();
+        assert!((device_from_imu.rotation * Vector3::x() - Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-6);
+        assert!((device_from_imu.rotation * Vector3::z() - Vector3::x()).norm() < 1e-6);
+        assert!((device_from_imu.translation.vector - Vector3::new(0.0, 0.0, 0.05)).norm() < 1e-6);
+
+        // device pitched 30 deg, gravity as the imu sees it
+        let world_from_device = Isometry3::from_parts(
+            Translation3::from(HEAD),
+            UnitQuaternion::from_scaled_axis(Vector3::x() * 30f64.to_radians()),
+        );
+        let world_from_imu = world_from_device * device_from_imu;
+        let accel = world_from_imu.rotation.inverse() * Vector3::y();
+
+        let run = |extrinsics: Option<Isometry3<f64>>| {
+            let mut fusion = SensorFusion::new(FusionNoise::consumer_imu());
+            if let Some(extrinsics) = extrinsics {
+                fusion.set_imu_extrinsics(extrinsics);
+            }
+            for t in (0..=1_000_000).step_by(1000) {
+                fusion.push_imu(&imu(t, accel, 0.0));
+                if t % 8000 == 0 {
+                    fusion.push_pose(&pose(t, world_from_device));
+                }
+            }
+            fusion
+        };
+
+        let fusion = run(Some(device_from_imu));
+        let fused = fusion.pose().unwrap();
+        assert!((fused.position - HEAD).norm() < 0.002, "{}", fused.position);
+        assert!(fused.orientation.angle_to(&world_from_device.rotation) < 0.002);
+        assert_eq!(fusion.rejected(), 0);
+        assert!(fusion.accel_bias().unwrap().norm() < 0.05);
+
+        // treating the imu frame as the device frame leaves gravity and poses disagreeing
+        let fusion = run(None);
+        assert!(fusion.rejected() > 0 || fusion.accel_bias().unwrap().norm() > 1.0);
+    }
+}
@@ -3,6 +3,10 @@ use std::time::{Duration, Instant};
 use ash::{vk, Entry as AshEntry, Instance as AshInstance};
 use ash::version::{EntryV1_0, InstanceV1_0};
 use std::ffi::CStr;
+use std::fmt;
+use std::str::FromStr;
+use crate::controller_input::Hand;
+use crate::xr_input::XrInput;
 
 pub struct VulkanContext {
     pub entry: AshEntry,
@@ -19,6 +23,40 @@ pub struct SwapchainInfo {
     // other per-swapchain bookkeeping
 }
 
+// reference space the recorded poses are expressed in (--space)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseSpace {
+    // room scale, origin on the floor at the play area centre
+    Stage,
+    // seated, origin at the head position when the session started
+    Local,
+    // local origin dropped to the floor (XR_EXT_local_floor or openxr 1.1)
+    LocalFloor,
+}
+
+impl fmt::Display for BaseSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BaseSpace::Stage => "stage",
+            BaseSpace::Local => "local",
+            BaseSpace::LocalFloor => "local-floor",
+        })
+    }
+}
+
+impl FromStr for BaseSpace {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "stage" => Ok(BaseSpace::Stage),
+            "local" => Ok(BaseSpace::Local),
+            "local-floor" | "local_floor" => Ok(BaseSpace::LocalFloor),
+            _ => Err(format!("unknown space '{}', expected stage, local or local-floor", value)),
+        }
+    }
+}
+
 pub struct VrSession {
     pub xr_instance: xr::Instance,
     pub system: xr::SystemId,
@@ -26,9 +64,13 @@ pub struct VrSession {
     pub frame_wait: xr::FrameWaiter,
     pub frame_stream: xr::FrameStream<xr::Vulkan>,
     pub stage: xr::Space,
-    pub action_set: xr::ActionSet,
-    pub hand_space_left: xr::Space,
-    pub hand_space_right: xr::Space,
+    // the headset itself, located against the base space every frame
+    pub view: xr::Space,
+    pub local: xr::Space,
+    // none when the runtime does not offer it
+    pub local_floor: Option<xr::Space>,
+    // controller actions, grip/aim spaces per hand
+    pub input: XrInput,
 
     // our Vulkan pieces (kept so we can operate on swapchain images)
     pub vk: VulkanContext,
@@ -46,6 +88,7 @@ impl VrSession {
         println!("OpenXR runtime reports {} extensions", available_exts.len());
         let mut enabled = xr::ExtensionSet::default();
         enabled.khr_vulkan_enable = true;
+        enabled.ext_local_floor = available_exts.ext_local_floor;
 
         let xr_instance = entry.create_instance(
             &xr::ApplicationInfo {
@@ -106,26 +149,27 @@ impl VrSession {
             xr::ReferenceSpaceType::STAGE,
             xr::Posef::IDENTITY,
         )?;
-
-        let action_set = xr_instance.create_action_set("input", "input", 0)?;
-        let hand_pose = action_set.create_action::<xr::Posef>(
-            "hand_pose",
-            "hand pose",
-            &[],
-        )?;
-        session.attach_action_sets(&[&action_set])?;
-
-        let hand_space_left = hand_pose.create_space(
-            session.clone(),
-            xr::Path::NULL,
+        let view = session.create_reference_space(
+            xr::ReferenceSpaceType::VIEW,
             xr::Posef::IDENTITY,
         )?;
-
-        let hand_space_right = hand_pose.create_space(
-            session.clone(),
-            xr::Path::NULL,
+        let local = session.create_reference_space(
+            xr::ReferenceSpaceType::LOCAL,
             xr::Posef::IDENTITY,
         )?;
+        let local_floor = if session
+            .enumerate_reference_spaces()?
+            .contains(&xr::ReferenceSpaceType::LOCAL_FLOOR)
+        {
+            Some(session.create_reference_space(
+                xr::ReferenceSpaceType::LOCAL_FLOOR,
+                xr::Posef::IDENTITY,
+            )?)
+        } else {
+            None
+        };
+
+        let input = XrInput::new(&xr_instance, &session)?;
 
         // create swapchains for each view
         let mut swapchains = Vec::new();
@@ -165,14 +209,46 @@ impl VrSession {
             frame_wait,
             frame_stream,
             stage,
-            action_set,
-            hand_space_left,
-            hand_space_right,
+            view,
+            local,
+            local_floor,
+            input,
             vk: vk_ctx,
             swapchains,
         })
     }
 
+    // vibrate one controller; frequency in hz (0 = runtime default), amplitude 0-1
+    // returns once the runtime accepted the request, the pulse itself plays asynchronously
+    pub fn vibrate(
+        &self,
+        hand: Hand,
+        duration: Duration,
+        frequency: f32,
+        amplitude: f32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.input.apply_haptic(
+            &self.session,
+            hand,
+            xr::Duration::from_nanos(duration.as_nanos().min(i64::MAX as u128) as i64),
+            frequency.max(0.0),
+            amplitude.clamp(0.0, 1.0),
+        )
+    }
+
+    pub fn stop_vibration(&self, hand: Hand) -> Result<(), Box<dyn std::error::Error>> {
+        self.input.stop_haptic(&self.session, hand)
+    }
+
+    // space to record poses in; local-floor falls back to local when missing
+    pub fn base_space(&self, kind: BaseSpace) -> &xr::Space {
+        match kind {
+            BaseSpace::Stage => &self.stage,
+            BaseSpace::Local => &self.local,
+            BaseSpace::LocalFloor => self.local_floor.as_ref().unwrap_or(&self.local),
+        }
+    }
+
     // Create a Vulkan instance/device suitable for OpenXR.
     // This is simplified; in production you must choose physical device and queue family carefully.
     fn create_vulkan_for_openxr(xr_instance: &xr::Instance) -> Result<VulkanContext, Box<dyn std::error::Error>> {
@@ -273,6 +349,9 @@ impl VrSession {
                 continue;
             }
 
+            // action states and hand spaces only update on sync
+            self.input.sync(&self.session)?;
+
             let should_continue = callback(self, frame_state.predicted_display_time)?;
 
             if !should_continue {
@@ -1,6 +1,74 @@
+use std::collections::VecDeque;
 use openxr as xr;
-use nalgebra::Vector3;
-use crate::metrics::SensorFrame;
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+use crate::metrics::{ControllerPose, ControllerPoses, SensorFrame, TrackingFlags, VelocitySource};
+use crate::controller_input::{ButtonEvent, ControllerInput, ControllerInputState, Hand};
+use crate::hid_reports::{ButtonReport, ImuSample};
+use crate::imu_filter::{FilterGains, ImuFilter};
+use crate::xr_input::XrInput;
+
+// finite difference span, long enough to average out 90hz pose jitter
+const VELOCITY_WINDOW_S: f64 = 0.05;
+// exponential smoothing of the differenced velocity (0..1, higher follows faster)
+const VELOCITY_SMOOTHING: f32 = 0.5;
+
+// velocity from the pose history when the runtime does not report one
+pub struct VelocityEstimator {
+    // (seconds, position, orientation), oldest first
+    history: VecDeque<(f64, Vector3<f32>, UnitQuaternion<f32>)>,
+    linear: Option<Vector3<f32>>,
+    angular: Option<Vector3<f32>>,
+}
+
+impl VelocityEstimator {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+            linear: None,
+            angular: None,
+        }
+    }
+
+    // untracked frames break the history, the next pose starts over
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.linear = None;
+        self.angular = None;
+    }
+
+    // returns (linear, angular) in the base space, None until two poses are in
+    pub fn update(
+        &mut self,
+        time_s: f64,
+        position: Vector3<f32>,
+        orientation: UnitQuaternion<f32>,
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        if self.history.back().is_some_and(|&(t, _, _)| time_s <= t) {
+            return self.linear.zip(self.angular);
+        }
+        self.history.push_back((time_s, position, orientation));
+        // keep the newest sample at or beyond the window as the reference
+        while self.history.len() > 2 && time_s - self.history[1].0 >= VELOCITY_WINDOW_S {
+            self.history.pop_front();
+        }
+
+        let &(t0, p0, q0) = self.history.front()?;
+        let dt = (time_s - t0) as f32;
+        if self.history.len() < 2 || dt <= 0.0 {
+            return None;
+        }
+
+        let linear = (position - p0) / dt;
+        let angular = (orientation * q0.inverse()).scaled_axis() / dt;
+        let smooth = |old: Option<Vector3<f32>>, new: Vector3<f32>| match old {
+            Some(old) => old + (new - old) * VELOCITY_SMOOTHING,
+            None => new,
+        };
+        self.linear = Some(smooth(self.linear, linear));
+        self.angular = Some(smooth(self.angular, angular));
+        self.linear.zip(self.angular)
+    }
+}
 
 // tracking collector stores simple state and supports 3dof mode
 pub struct TrackingCollector {
@@ -8,7 +76,21 @@ pub struct TrackingCollector {
     total_drift_cm: f32,
     frame_count: u64,
     // when true, only use orientation (no positional tracking)
+    // the orientation then comes from the headset imu instead of the runtime
     pub force_3dof: bool,
+    imu_filter: ImuFilter,
+    // imu chip axes in the head frame (device config), identity when unknown
+    head_from_imu: UnitQuaternion<f32>,
+    // base_from_filter, the filter has yaw 0 at startup; taken from the runtime's
+    // head orientation the first time it is valid
+    imu_yaw: Option<UnitQuaternion<f32>>,
+    head_velocity: VelocityEstimator,
+    // [hand][grip, aim]
+    controller_velocity: [[VelocityEstimator; 2]; 2],
+    // button state per hand, indexed by Hand::index()
+    controllers: [ControllerInput; 2],
+    // edges seen since the last collected frame
+    pending_events: Vec<ButtonEvent>,
 }
 
 impl TrackingCollector {
@@ -19,6 +101,13 @@ impl TrackingCollector {
             total_drift_cm: 0.0,
             frame_count: 0,
             force_3dof: false,
+            imu_filter: ImuFilter::new(FilterGains::mahony()),
+            head_from_imu: UnitQuaternion::identity(),
+            imu_yaw: None,
+            head_velocity: VelocityEstimator::new(),
+            controller_velocity: std::array::from_fn(|_| std::array::from_fn(|_| VelocityEstimator::new())),
+            controllers: [ControllerInput::new(Hand::Left), ControllerInput::new(Hand::Right)],
+            pending_events: Vec::new(),
         }
     }
 
@@ -27,19 +116,63 @@ impl TrackingCollector {
         self.force_3dof = on;
     }
 
+    // feed parsed 0x20 imu samples (from hid_reports::ImuStream)
+    pub fn push_imu_samples(&mut self, samples: &[ImuSample]) {
+        self.imu_filter.update_all(samples);
+    }
+
+    pub fn imu_filter(&self) -> &ImuFilter {
+        &self.imu_filter
+    }
+
+    // see DeviceConfig::head_from_imu
+    pub fn set_imu_axes(&mut self, head_from_imu: UnitQuaternion<f32>) {
+        self.head_from_imu = head_from_imu;
+        self.imu_yaw = None;
+    }
+
+    // imu head orientation in the base space (+y up like the filter world), None
+    // until the filter has aligned to gravity. until the runtime reports a valid
+    // orientation the yaw stays the filter's startup yaw
+    pub fn imu_head_orientation(&mut self, runtime: Option<UnitQuaternion<f32>>) -> Option<UnitQuaternion<f32>> {
+        let filter_from_head = self.imu_filter.orientation()? * self.head_from_imu.inverse();
+        if self.imu_yaw.is_none() {
+            self.imu_yaw = runtime.map(|base_from_head| yaw_between(&filter_from_head, &base_from_head));
+        }
+        Some(self.imu_yaw.unwrap_or_else(UnitQuaternion::identity) * filter_from_head)
+    }
+
+    // feed a 0x24 button report from one controller
+    // press/release edges are kept until the next collected frame
+    pub fn update_controller_input(&mut self, hand: Hand, report: &ButtonReport) -> Vec<ButtonEvent> {
+        let events = self.controllers[hand.index()].update(report);
+        self.pending_events.extend_from_slice(&events);
+        events
+    }
+
+    // same for a state read from the openxr actions (xr_input::XrInput)
+    pub fn update_controller_state(&mut self, hand: Hand, state: ControllerInputState) -> Vec<ButtonEvent> {
+        let events = self.controllers[hand.index()].apply(state);
+        self.pending_events.extend_from_slice(&events);
+        events
+    }
+
     // collect a single sensor frame from openxr spaces
+    // the head (view space) and the controller grip/aim spaces are located in
+    // `base` (stage, local...), input must have been synced for this frame
     // returns a sensor frame ready to store in metrics
     pub fn collect_frame(
         &mut self,
-        stage: &xr::Space,
-        hand_left: &xr::Space,
-        hand_right: &xr::Space,
+        base: &xr::Space,
+        view: &xr::Space,
+        input: &XrInput,
         time: xr::Time,
         timestamp_ms: u64,
     ) -> Result<SensorFrame, Box<dyn std::error::Error>> {
 
-        // locate the head pose in the stage space
-        let view_location = stage.locate(stage, time)?;
+        // locate the head pose in the base space, with velocity when the runtime has it
+        let (view_location, view_velocity) = view.relate(base, time)?;
+        let head_tracking = tracking_flags(view_location.location_flags);
 
         let pos = view_location.pose.position;
         let ori = view_location.pose.orientation;
@@ -51,37 +184,90 @@ impl TrackingCollector {
             [pos.x, pos.y, pos.z]
         };
 
-        // velocity is not available here; set zeros for now
-        let vel = [0.0f32, 0.0, 0.0];
-        let ang_vel = [0.0f32, 0.0, 0.0];
+        // in 3dof mode prefer the imu filter once it has aligned to gravity
+        let runtime_orientation = head_tracking
+            .orientation_valid
+            .then(|| UnitQuaternion::from_quaternion(Quaternion::new(ori.w, ori.x, ori.y, ori.z)));
+        let imu_orientation = if self.force_3dof {
+            self.imu_head_orientation(runtime_orientation)
+        } else {
+            None
+        };
+        let head_orientation = match imu_orientation {
+            Some(q) => [q.i, q.j, q.k, q.w],
+            None => [ori.x, ori.y, ori.z, ori.w],
+        };
+
+        let (vel, linear_source, ang_vel, angular_source) =
+            space_velocity(&mut self.head_velocity, &view_location, &view_velocity, time);
+
+        // the imu is what 3dof mode tracks with, its rate wins over the runtime's
+        let (ang_vel, angular_source) = match imu_orientation {
+            Some(_) => {
+                let yaw = self.imu_yaw.unwrap_or_else(UnitQuaternion::identity);
+                ((yaw * self.imu_filter.world_angular_velocity()).into(), VelocitySource::Imu)
+            }
+            None => (ang_vel, angular_source),
+        };
+        // no position in 3dof mode, so no linear velocity either
+        let (vel, linear_source) = if self.force_3dof {
+            ([0.0f32, 0.0, 0.0], VelocitySource::Unavailable)
+        } else {
+            (vel, linear_source)
+        };
 
         // update simple drift estimate using positional changes (if any)
+        // positions the runtime only guessed would count as jumps, skip them
         let current_pos = Vector3::new(head_position[0], head_position[1], head_position[2]);
-        if self.last_position.norm() > 0.0001 {
-            let drift = (current_pos - self.last_position).norm() * 100.0;
-            self.total_drift_cm += drift;
+        if head_tracking.position_tracked {
+            if self.last_position.norm() > 0.0001 {
+                let drift = (current_pos - self.last_position).norm() * 100.0;
+                self.total_drift_cm += drift;
+            }
+            self.last_position = current_pos;
         }
-        self.last_position = current_pos;
 
-        // try to read controller locations; ignore errors
-        let left_pos = hand_left.locate(stage, time)
-            .ok()
-            .map(|loc| [loc.pose.position.x, loc.pose.position.y, loc.pose.position.z]);
-
-        let right_pos = hand_right.locate(stage, time)
-            .ok()
-            .map(|loc| [loc.pose.position.x, loc.pose.position.y, loc.pose.position.z]);
+        // controllers that are off or not bound come back with no valid flags
+        let [left_controller, right_controller] =
+            Hand::ALL.map(|hand| self.locate_controller(input, hand, base, time));
 
         self.frame_count += 1;
 
         Ok(SensorFrame {
             timestamp_ms,
             head_position,
-            head_orientation: [ori.x, ori.y, ori.z, ori.w],
-            left_controller_pos: left_pos,
-            right_controller_pos: right_pos,
+            head_orientation,
+            head_tracking,
+            left_controller,
+            right_controller,
             angular_velocity: ang_vel,
             linear_velocity: vel,
+            linear_velocity_source: linear_source,
+            angular_velocity_source: angular_source,
+            left_controller_input: self.controllers[Hand::Left.index()].state(),
+            right_controller_input: self.controllers[Hand::Right.index()].state(),
+            input_events: std::mem::take(&mut self.pending_events),
+        })
+    }
+
+    // grip and aim pose of one hand, None when neither is valid or locating failed
+    fn locate_controller(
+        &mut self,
+        input: &XrInput,
+        hand: Hand,
+        base: &xr::Space,
+        time: xr::Time,
+    ) -> Option<ControllerPoses> {
+        let [grip_velocity, aim_velocity] = &mut self.controller_velocity[hand.index()];
+        let grip = locate_pose(input.grip_space(hand), base, time, grip_velocity);
+        let aim = locate_pose(input.aim_space(hand), base, time, aim_velocity);
+        let valid = |pose: &ControllerPose| pose.tracking.position_valid || pose.tracking.orientation_valid;
+        if !grip.iter().chain(aim.iter()).any(valid) {
+            return None;
+        }
+        Some(ControllerPoses {
+            grip: grip.unwrap_or_default(),
+            aim: aim.unwrap_or_default(),
         })
     }
 
@@ -113,4 +299,282 @@ impl TrackingCollector {
             self.total_drift_cm
         );
     }
+}
+
+// rotation about +y only that turns `from` towards `to` (twist of to * from⁻¹)
+fn yaw_between(from: &UnitQuaternion<f32>, to: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+    let delta = to * from.inverse();
+    let twist = Quaternion::new(delta.w, 0.0, delta.j, 0.0);
+    if twist.norm() < 1e-6 {
+        return UnitQuaternion::identity();
+    }
+    UnitQuaternion::from_quaternion(twist)
+}
+
+fn tracking_flags(flags: xr::SpaceLocationFlags) -> TrackingFlags {
+    TrackingFlags {
+        position_valid: flags.contains(xr::SpaceLocationFlags::POSITION_VALID),
+        orientation_valid: flags.contains(xr::SpaceLocationFlags::ORIENTATION_VALID),
+        position_tracked: flags.contains(xr::SpaceLocationFlags::POSITION_TRACKED),
+        orientation_tracked: flags.contains(xr::SpaceLocationFlags::ORIENTATION_TRACKED),
+    }
+}
+
+fn locate_pose(
+    space: &xr::Space,
+    base: &xr::Space,
+    time: xr::Time,
+    estimator: &mut VelocityEstimator,
+) -> Option<ControllerPose> {
+    let (location, velocity) = space.relate(base, time).ok()?;
+    let (linear_velocity, linear_velocity_source, angular_velocity, angular_velocity_source) =
+        space_velocity(estimator, &location, &velocity, time);
+    let p = location.pose.position;
+    let o = location.pose.orientation;
+    Some(ControllerPose {
+        position: [p.x, p.y, p.z],
+        orientation: [o.x, o.y, o.z, o.w],
+        tracking: tracking_flags(location.location_flags),
+        linear_velocity,
+        angular_velocity,
+        linear_velocity_source,
+        angular_velocity_source,
+    })
+}
+
+// runtime velocities where flagged valid, otherwise differenced from the pose history
+fn space_velocity(
+    estimator: &mut VelocityEstimator,
+    location: &xr::SpaceLocation,
+    velocity: &xr::SpaceVelocity,
+    time: xr::Time,
+) -> ([f32; 3], VelocitySource, [f32; 3], VelocitySource) {
+    let flags = tracking_flags(location.location_flags);
+    let tracked = flags.position_valid && flags.orientation_valid;
+
+    let estimated = if tracked {
+        let p = location.pose.position;
+        let o = location.pose.orientation;
+        estimator.update(
+            time.as_nanos() as f64 * 1e-9,
+            Vector3::new(p.x, p.y, p.z),
+            UnitQuaternion::from_quaternion(Quaternion::new(o.w, o.x, o.y, o.z)),
+        )
+    } else {
+        estimator.reset();
+        None
+    };
+
+    let pick = |valid: xr::SpaceVelocityFlags, runtime: xr::Vector3f, fallback: Option<Vector3<f32>>| {
+        if velocity.velocity_flags.contains(valid) {
+            ([runtime.x, runtime.y, runtime.z], VelocitySource::Runtime)
+        } else if let Some(v) = fallback {
+            (v.into(), VelocitySource::FiniteDifference)
+        } else {
+            ([0.0f32, 0.0, 0.0], VelocitySource::Unavailable)
+        }
+    };
+    let (linear, linear_source) = pick(
+        xr::SpaceVelocityFlags::LINEAR_VALID,
+        velocity.linear_velocity,
+        estimated.map(|(linear, _)| linear),
+    );
+    let (angular, angular_source) = pick(
+        xr::SpaceVelocityFlags::ANGULAR_VALID,
+        velocity.angular_velocity,
+        estimated.map(|(_, angular)| angular),
+    );
+    (linear, linear_source, angular, angular_source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_reports::TICKS_PER_US;
+
+    fn still_imu(tracker: &mut TrackingCollector, world_from_imu: &UnitQuaternion<f32>, samples: u64) {
+        let accel = world_from_imu.inverse_transform_vector(&Vector3::y());
+        let samples: Vec<_> = (0..samples)
+            .map(|n| ImuSample {
+                sequence: n as u8,
+                timestamp_ticks: (n + 1) * 1000 * TICKS_PER_US,
+                accel: accel.into(),
+                gyro: [0.0; 3],
+                dropped_before: 0,
+            })
+            .collect();
+        tracker.push_imu_samples(&samples);
+    }
+
+    #[test]
+    fn imu_orientation_in_the_base_space() {
+        // imu mounted rolled 90 deg in the head, head pitched down 20 deg and turned 60 deg
+        let head_from_imu = UnitQuaternion::from_scaled_axis(Vector3::z() * 90f32.to_radians());
+        let base_from_head = UnitQuaternion::from_scaled_axis(Vector3::y() * 60f32.to_radians())
+            * UnitQuaternion::from_scaled_axis(Vector3::x() * -20f32.to_radians());
+
+        let mut tracker = TrackingCollector::new();
+        tracker.set_imu_axes(head_from_imu);
+        assert_eq!(tracker.imu_head_orientation(Some(base_from_head)), None);
+        still_imu(&mut tracker, &(base_from_head * head_from_imu), 200);
+
+        // no runtime orientation yet: right tilt, startup yaw
+        let unaligned = tracker.imu_head_orientation(None).unwrap();
+        let up = |q: UnitQuaternion<f32>| q.inverse_transform_vector(&Vector3::y());
+        assert!((up(unaligned) - up(base_from_head)).norm() < 1e-3);
+        assert!(unaligned.angle_to(&base_from_head) > 0.5);
+
+        let aligned = tracker.imu_head_orientation(Some(base_from_head)).unwrap();
+        assert!(aligned.angle_to(&base_from_head) < 1e-3, "{}", aligned.angle_to(&base_from_head));
+
+        // the yaw is taken once, later runtime poses do not steer the imu
+        let other = UnitQuaternion::from_scaled_axis(Vector3::y() * 1.0);
+        let later = tracker.imu_head_orientation(Some(other)).unwrap();
+        assert!(later.angle_to(&aligned) < 1e-6);
+    }
+
+    #[test]
+    fn yaw_between_ignores_tilt() {
+        let yaw = UnitQuaternion::from_scaled_axis(Vector3::y() * 0.7);
+        let tilt = UnitQuaternion::from_scaled_axis(Vector3::new(0.3, 0.0, -0.2));
+        let offset = yaw_between(&tilt, &(yaw * tilt));
+        assert!(offset.angle_to(&yaw) < 1e-5);
+        assert_eq!(yaw_between(&tilt, &tilt), UnitQuaternion::identity());
+    }
+
+    const FRAME_S: f64 = 1.0 / 90.0;
+
+    // pose at `t` moving with constant linear and angular velocity (base space)
+    fn moving(t: f64, linear: Vector3<f32>, angular: Vector3<f32>) -> (Vector3<f32>, UnitQuaternion<f32>) {
+        let start = UnitQuaternion::from_scaled_axis(Vector3::new(0.1, -0.4, 0.2));
+        let position = Vector3::new(0.1, 1.6, -0.3) + linear * t as f32;
+        (position, UnitQuaternion::from_scaled_axis(angular * t as f32) * start)
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>, tolerance: f32) {
+        assert!((a - b).norm() < tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn velocity_of_constant_motion() {
+        let linear = Vector3::new(0.5, 0.0, -0.2);
+        let angular = Vector3::new(0.0, 1.0, 0.3);
+        let mut estimator = VelocityEstimator::new();
+
+        let (p, q) = moving(0.0, linear, angular);
+        assert_eq!(estimator.update(0.0, p, q), None);
+        for n in 1..30 {
+            let t = n as f64 * FRAME_S;
+            let (p, q) = moving(t, linear, angular);
+            let (l, a) = estimator.update(t, p, q).unwrap();
+            assert_close(l, linear, 1e-3);
+            assert_close(a, angular, 1e-3);
+        }
+    }
+
+    #[test]
+    fn velocity_window_follows_a_change() {
+        let mut estimator = VelocityEstimator::new();
+        let fast = Vector3::new(1.0, 0.0, 0.0);
+        for n in 0..30 {
+            let t = n as f64 * FRAME_S;
+            let (p, q) = moving(t, fast, Vector3::zeros());
+            estimator.update(t, p, q);
+        }
+
+        // stop dead: once the window holds only still poses the estimate decays to zero
+        let (stopped, q) = moving(29.0 * FRAME_S, fast, Vector3::zeros());
+        let mut last = None;
+        for n in 30..60 {
+            let t = n as f64 * FRAME_S;
+            last = estimator.update(t, stopped, q);
+            let &(oldest, _, _) = estimator.history.front().unwrap();
+            assert!(t - oldest < VELOCITY_WINDOW_S + FRAME_S, "{} s kept", t - oldest);
+            assert!(estimator.history.len() <= (VELOCITY_WINDOW_S / FRAME_S) as usize + 2);
+        }
+        let (l, a) = last.unwrap();
+        assert_close(l, Vector3::zeros(), 1e-4);
+        assert_close(a, Vector3::zeros(), 1e-4);
+    }
+
+    #[test]
+    fn velocity_ignores_repeated_and_older_times() {
+        let linear = Vector3::new(0.0, 0.3, 0.0);
+        let mut estimator = VelocityEstimator::new();
+        let mut last = None;
+        for n in 0..5 {
+            let t = n as f64 * FRAME_S;
+            let (p, q) = moving(t, linear, Vector3::zeros());
+            last = estimator.update(t, p, q);
+        }
+        let kept = estimator.history.len();
+
+        // a wild pose at a repeated or older time must not make a velocity spike
+        let far = Vector3::new(10.0, 10.0, 10.0);
+        let q = UnitQuaternion::identity();
+        assert_eq!(estimator.update(4.0 * FRAME_S, far, q), last);
+        assert_eq!(estimator.update(2.0 * FRAME_S, far, q), last);
+        assert_eq!(estimator.history.len(), kept);
+
+        let (p, q) = moving(5.0 * FRAME_S, linear, Vector3::zeros());
+        let (l, _) = estimator.update(5.0 * FRAME_S, p, q).unwrap();
+        assert_close(l, linear, 1e-3);
+    }
+
+    fn location(position: Vector3<f32>, orientation: UnitQuaternion<f32>, tracked: bool) -> xr::SpaceLocation {
+        let flags = if tracked {
+            xr::SpaceLocationFlags::POSITION_VALID | xr::SpaceLocationFlags::ORIENTATION_VALID
+        } else {
+            xr::SpaceLocationFlags::ORIENTATION_VALID
+        };
+        let q = orientation.quaternion();
+        xr::SpaceLocation {
+            location_flags: flags,
+            pose: xr::Posef {
+                orientation: xr::Quaternionf { x: q.i, y: q.j, z: q.k, w: q.w },
+                position: xr::Vector3f { x: position.x, y: position.y, z: position.z },
+            },
+        }
+    }
+
+    #[test]
+    fn untracked_frame_restarts_the_estimate() {
+        let linear = Vector3::new(0.0, 0.0, 0.4);
+        let no_runtime = xr::SpaceVelocity {
+            velocity_flags: xr::SpaceVelocityFlags::EMPTY,
+            linear_velocity: xr::Vector3f::default(),
+            angular_velocity: xr::Vector3f::default(),
+        };
+        let mut estimator = VelocityEstimator::new();
+        let frame = |estimator: &mut VelocityEstimator, n: i64, tracked: bool| {
+            let (p, q) = moving(n as f64 * FRAME_S, linear, Vector3::zeros());
+            let time = xr::Time::from_nanos((n as f64 * FRAME_S * 1e9) as i64);
+            space_velocity(estimator, &location(p, q, tracked), &no_runtime, time)
+        };
+
+        assert_eq!(frame(&mut estimator, 0, true).1, VelocitySource::Unavailable);
+        let (l, source, _, _) = frame(&mut estimator, 1, true);
+        assert_eq!(source, VelocitySource::FiniteDifference);
+        assert_close(Vector3::from(l), linear, 1e-3);
+
+        let (l, source, _, angular_source) = frame(&mut estimator, 2, false);
+        assert_eq!((l, source, angular_source), ([0.0; 3], VelocitySource::Unavailable, VelocitySource::Unavailable));
+        assert!(estimator.history.is_empty());
+
+        // the gap is not differenced across, the first pose after it starts over
+        assert_eq!(frame(&mut estimator, 3, true).1, VelocitySource::Unavailable);
+        assert_eq!(frame(&mut estimator, 4, true).1, VelocitySource::FiniteDifference);
+
+        // runtime velocities win where flagged valid
+        let runtime = xr::SpaceVelocity {
+            velocity_flags: xr::SpaceVelocityFlags::LINEAR_VALID,
+            linear_velocity: xr::Vector3f { x: 1.0, y: 2.0, z: 3.0 },
+            angular_velocity: xr::Vector3f::default(),
+        };
+        let (p, q) = moving(5.0 * FRAME_S, linear, Vector3::zeros());
+        let time = xr::Time::from_nanos((5.0 * FRAME_S * 1e9) as i64);
+        let (l, source, _, angular_source) = space_velocity(&mut estimator, &location(p, q, true), &runtime, time);
+        assert_eq!((l, source), ([1.0, 2.0, 3.0], VelocitySource::Runtime));
+        assert_eq!(angular_source, VelocitySource::FiniteDifference);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,220 @@
+// openxr input layer: one action set, every action split per hand with the
+// /user/hand/left and /user/hand/right subaction paths. bindings are suggested
+// for the vive wand, the index controller and khr/simple_controller; the runtime
+// picks whichever profile matches the connected controllers
+//
+// actions only update on xrSyncActions, VrSession::run_loop calls sync() once a frame
+
+use openxr as xr;
+use crate::controller_input::{ControllerInputState, Hand};
+
+const HAND_PATHS: [&str; 2] = ["/user/hand/left", "/user/hand/right"];
+
+const VIVE_CONTROLLER: &str = "/interaction_profiles/htc/vive_controller";
+const INDEX_CONTROLLER: &str = "/interaction_profiles/valve/index_controller";
+const SIMPLE_CONTROLLER: &str = "/interaction_profiles/khr/simple_controller";
+
+pub struct XrInput {
+    pub action_set: xr::ActionSet,
+    hand_paths: [xr::Path; 2],
+    grip_pose: xr::Action<xr::Posef>,
+    aim_pose: xr::Action<xr::Posef>,
+    trigger: xr::Action<f32>,
+    trigger_click: xr::Action<bool>,
+    trackpad: xr::Action<xr::Vector2f>,
+    trackpad_touch: xr::Action<bool>,
+    trackpad_click: xr::Action<bool>,
+    menu: xr::Action<bool>,
+    grip: xr::Action<bool>,
+    haptic: xr::Action<xr::Haptic>,
+    // indexed by Hand::index()
+    grip_spaces: [xr::Space; 2],
+    aim_spaces: [xr::Space; 2],
+}
+
+impl XrInput {
+    // creates the actions, suggests bindings and attaches the set to the session
+    // (bindings can not change after attaching)
+    pub fn new<G>(
+        instance: &xr::Instance,
+        session: &xr::Session<G>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let hand_paths = [
+            instance.string_to_path(HAND_PATHS[0])?,
+            instance.string_to_path(HAND_PATHS[1])?,
+        ];
+
+        let action_set = instance.create_action_set("input", "input", 0)?;
+        let grip_pose = action_set.create_action::<xr::Posef>("grip_pose", "grip pose", &hand_paths)?;
+        let aim_pose = action_set.create_action::<xr::Posef>("aim_pose", "aim pose", &hand_paths)?;
+        let trigger = action_set.create_action::<f32>("trigger", "trigger", &hand_paths)?;
+        let trigger_click = action_set.create_action::<bool>("trigger_click", "trigger click", &hand_paths)?;
+        let trackpad = action_set.create_action::<xr::Vector2f>("trackpad", "trackpad", &hand_paths)?;
+        let trackpad_touch = action_set.create_action::<bool>("trackpad_touch", "trackpad touch", &hand_paths)?;
+        let trackpad_click = action_set.create_action::<bool>("trackpad_click", "trackpad click", &hand_paths)?;
+        let menu = action_set.create_action::<bool>("menu", "menu", &hand_paths)?;
+        let grip = action_set.create_action::<bool>("grip", "grip", &hand_paths)?;
+        let haptic = action_set.create_action::<xr::Haptic>("haptic", "haptic", &hand_paths)?;
+
+        let input = Self {
+            grip_spaces: [
+                grip_pose.create_space(session.clone(), hand_paths[0], xr::Posef::IDENTITY)?,
+                grip_pose.create_space(session.clone(), hand_paths[1], xr::Posef::IDENTITY)?,
+            ],
+            aim_spaces: [
+                aim_pose.create_space(session.clone(), hand_paths[0], xr::Posef::IDENTITY)?,
+                aim_pose.create_space(session.clone(), hand_paths[1], xr::Posef::IDENTITY)?,
+            ],
+            action_set,
+            hand_paths,
+            grip_pose,
+            aim_pose,
+            trigger,
+            trigger_click,
+            trackpad,
+            trackpad_touch,
+            trackpad_click,
+            menu,
+            grip,
+            haptic,
+        };
+
+        input.suggest_bindings(instance)?;
+        session.attach_action_sets(&[&input.action_set])?;
+        Ok(input)
+    }
+
+    // a runtime without one of the profiles rejects just that one, the others still apply
+    fn suggest_bindings(&self, instance: &xr::Instance) -> Result<(), Box<dyn std::error::Error>> {
+        let mut vive = Vec::new();
+        bind(instance, &mut vive, &self.grip_pose, "input/grip/pose")?;
+        bind(instance, &mut vive, &self.aim_pose, "input/aim/pose")?;
+        bind(instance, &mut vive, &self.trigger, "input/trigger/value")?;
+        bind(instance, &mut vive, &self.trigger_click, "input/trigger/click")?;
+        bind(instance, &mut vive, &self.trackpad, "input/trackpad")?;
+        bind(instance, &mut vive, &self.trackpad_touch, "input/trackpad/touch")?;
+        bind(instance, &mut vive, &self.trackpad_click, "input/trackpad/click")?;
+        bind(instance, &mut vive, &self.menu, "input/menu/click")?;
+        bind(instance, &mut vive, &self.grip, "input/squeeze/click")?;
+        bind(instance, &mut vive, &self.haptic, "output/haptic")?;
+
+        // index has no trackpad click or menu button, b and the squeeze force stand in
+        let mut index = Vec::new();
+        bind(instance, &mut index, &self.grip_pose, "input/grip/pose")?;
+        bind(instance, &mut index, &self.aim_pose, "input/aim/pose")?;
+        bind(instance, &mut index, &self.trigger, "input/trigger/value")?;
+        bind(instance, &mut index, &self.trigger_click, "input/trigger/click")?;
+        bind(instance, &mut index, &self.trackpad, "input/trackpad")?;
+        bind(instance, &mut index, &self.trackpad_touch, "input/trackpad/touch")?;
+        bind(instance, &mut index, &self.trackpad_click, "input/trackpad/force")?;
+        bind(instance, &mut index, &self.menu, "input/b/click")?;
+        bind(instance, &mut index, &self.grip, "input/squeeze/value")?;
+        bind(instance, &mut index, &self.haptic, "output/haptic")?;
+
+        let mut simple = Vec::new();
+        bind(instance, &mut simple, &self.grip_pose, "input/grip/pose")?;
+        bind(instance, &mut simple, &self.aim_pose, "input/aim/pose")?;
+        bind(instance, &mut simple, &self.trigger_click, "input/select/click")?;
+        bind(instance, &mut simple, &self.menu, "input/menu/click")?;
+        bind(instance, &mut simple, &self.haptic, "output/haptic")?;
+
+        for (profile, bindings) in [(VIVE_CONTROLLER, vive), (INDEX_CONTROLLER, index), (SIMPLE_CONTROLLER, simple)] {
+            let path = instance.string_to_path(profile)?;
+            if let Err(err) = instance.suggest_interaction_profile_bindings(path, &bindings) {
+                println!("runtime rejected bindings for {}: {}", profile, err);
+            }
+        }
+        Ok(())
+    }
+
+    // once per frame, before reading any action state or locating the hand spaces
+    pub fn sync<G>(&self, session: &xr::Session<G>) -> Result<(), Box<dyn std::error::Error>> {
+        session.sync_actions(&[xr::ActiveActionSet::new(&self.action_set)])?;
+        Ok(())
+    }
+
+    pub fn hand_path(&self, hand: Hand) -> xr::Path {
+        self.hand_paths[hand.index()]
+    }
+
+    pub fn grip_space(&self, hand: Hand) -> &xr::Space {
+        &self.grip_spaces[hand.index()]
+    }
+
+    pub fn aim_space(&self, hand: Hand) -> &xr::Space {
+        &self.aim_spaces[hand.index()]
+    }
+
+    // one vibration on the hand's output/haptic binding, frequency 0 lets the runtime pick
+    pub fn apply_haptic<G>(
+        &self,
+        session: &xr::Session<G>,
+        hand: Hand,
+        duration: xr::Duration,
+        frequency: f32,
+        amplitude: f32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let vibration = xr::HapticVibration::new()
+            .duration(duration)
+            .frequency(frequency)
+            .amplitude(amplitude);
+        self.haptic.apply_feedback(session, self.hand_path(hand), &vibration)?;
+        Ok(())
+    }
+
+    pub fn stop_haptic<G>(&self, session: &xr::Session<G>, hand: Hand) -> Result<(), Box<dyn std::error::Error>> {
+        self.haptic.stop_feedback(session, self.hand_path(hand))?;
+        Ok(())
+    }
+
+    // false while no controller is bound to that hand
+    pub fn is_active<G>(&self, session: &xr::Session<G>, hand: Hand) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.grip_pose.is_active(session, self.hand_path(hand))?)
+    }
+
+    // buttons in the same shape as the 0x24 hid reports, None without a controller;
+    // the system button is reserved by the runtime and always reads false
+    pub fn controller_state<G>(
+        &self,
+        session: &xr::Session<G>,
+        hand: Hand,
+    ) -> Result<Option<ControllerInputState>, Box<dyn std::error::Error>> {
+        if !self.is_active(session, hand)? {
+            return Ok(None);
+        }
+        let path = self.hand_path(hand);
+        let pressed = |action: &xr::Action<bool>| -> Result<bool, xr::sys::Result> {
+            action.state(session, path).map(|s| s.is_active && s.current_state)
+        };
+
+        let trigger = self.trigger.state(session, path)?;
+        let trackpad = self.trackpad.state(session, path)?;
+        Ok(Some(ControllerInputState {
+            trigger_click: pressed(&self.trigger_click)?,
+            trackpad_touch: pressed(&self.trackpad_touch)?,
+            trackpad_click: pressed(&self.trackpad_click)?,
+            menu: pressed(&self.menu)?,
+            grip: pressed(&self.grip)?,
+            system: false,
+            trigger: if trigger.is_active { trigger.current_state } else { 0.0 },
+            trackpad: if trackpad.is_active {
+                [trackpad.current_state.x, trackpad.current_state.y]
+            } else {
+                [0.0, 0.0]
+            },
+        }))
+    }
+}
+
+// one suggested binding per hand for "<hand>/<path>"
+fn bind<'a, T: xr::ActionTy>(
+    instance: &xr::Instance,
+    bindings: &mut Vec<xr::Binding<'a>>,
+    action: &'a xr::Action<T>,
+    path: &str,
+) -> Result<(), xr::sys::Result> {
+    for hand in HAND_PATHS {
+        bindings.push(xr::Binding::new(action, instance.string_to_path(&format!("{}/{}", hand, path))?));
+    }
+    Ok(())
+}